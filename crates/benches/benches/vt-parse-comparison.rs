@@ -5,6 +5,107 @@ use std::hash::{Hash, Hasher};
 const CORPUS_REPEAT: usize = 10000;
 
 static MIXED: &str = include_str!("mixed.txt");
+static NO_ANSI: &str = include_str!("no-ansi-corpus.txt");
+
+/// Long OSC 52 (clipboard) and DCS (sixel-like) payloads, which are mostly
+/// skipped over by the parsers.
+fn payload_corpus() -> Vec<u8> {
+    let mut corpus = vec![];
+    corpus.extend_from_slice(b"\x1b]52;c;");
+    corpus.extend_from_slice("SGVsbG8sIHdvcmxkIQ==".repeat(200).as_bytes());
+    corpus.extend_from_slice(b"\x07\x1bPq");
+    corpus.extend_from_slice("#0;2;0;0;0~~@@vv@@~~".repeat(200).as_bytes());
+    corpus.extend_from_slice(b"\x1b\\");
+    corpus
+}
+
+#[derive(Default)]
+struct VteProcess {
+    hash: std::hash::DefaultHasher,
+}
+
+impl vte::Perform for VteProcess {
+    fn print(&mut self, c: char) {
+        c.hash(&mut self.hash);
+    }
+    fn execute(&mut self, b: u8) {
+        b.hash(&mut self.hash);
+    }
+    fn put(&mut self, b: u8) {
+        b.hash(&mut self.hash);
+    }
+    fn esc_dispatch(&mut self, intermediates: &[u8], _private: bool, final_byte: u8) {
+        intermediates.hash(&mut self.hash);
+        final_byte.hash(&mut self.hash);
+    }
+    fn csi_dispatch(
+        &mut self,
+        params: &vte::Params,
+        intermediates: &[u8],
+        _private: bool,
+        final_byte: char,
+    ) {
+        params.len().hash(&mut self.hash);
+        intermediates.len().hash(&mut self.hash);
+        final_byte.hash(&mut self.hash);
+    }
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        params.hash(&mut self.hash);
+    }
+}
+
+#[derive(Default)]
+struct PushProcess {
+    hash: std::hash::DefaultHasher,
+}
+
+impl vt_push_parser::VTEventCallback for &'_ mut PushProcess {
+    fn event(&mut self, event: vt_push_parser::event::VTEvent) {
+        event.hash(&mut self.hash);
+    }
+}
+
+fn bench_vte(b: divan::Bencher, corpus: Vec<u8>) {
+    b.bench(move || {
+        let mut parser = vte::Parser::new();
+        let mut process = VteProcess::default();
+        for _ in 0..CORPUS_REPEAT {
+            parser.advance(&mut process, &corpus);
+        }
+        divan::black_box_drop(process.hash.finish());
+    });
+}
+
+fn bench_vt_push_parser(b: divan::Bencher, corpus: Vec<u8>) {
+    b.bench(move || {
+        let mut parser = vt_push_parser::VTPushParser::new();
+        let mut process = PushProcess::default();
+        for _ in 0..CORPUS_REPEAT {
+            parser.feed_with(&corpus, &mut process);
+        }
+        divan::black_box_drop(process.hash.finish());
+    });
+}
+
+#[divan::bench]
+fn vte_parse_no_ansi(b: divan::Bencher) {
+    bench_vte(b, NO_ANSI.as_bytes().to_vec());
+}
+
+#[divan::bench]
+fn vt_push_parser_parse_no_ansi(b: divan::Bencher) {
+    bench_vt_push_parser(b, NO_ANSI.as_bytes().to_vec());
+}
+
+#[divan::bench]
+fn vte_parse_payload(b: divan::Bencher) {
+    bench_vte(b, payload_corpus());
+}
+
+#[divan::bench]
+fn vt_push_parser_parse_payload(b: divan::Bencher) {
+    bench_vt_push_parser(b, payload_corpus());
+}
 
 #[divan::bench]
 fn vte_parse_mixed(b: divan::Bencher) {
@@ -144,13 +245,13 @@ fn vt_push_parser_parse_mixed_sum(b: divan::Bencher) {
                             }
                         }
                     }
-                    OscStart => {
+                    OscStart { .. } => {
                         self.hash.write_u8(0);
                     }
                     OscData(data) => {
                         data.hash(&mut self.hash);
                     }
-                    OscEnd { data, used_bel } => {
+                    OscEnd { data, .. } => {
                         data.hash(&mut self.hash);
                     }
                     OscCancel => {
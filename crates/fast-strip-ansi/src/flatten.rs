@@ -0,0 +1,448 @@
+//! Flatten terminal output into the text that would finally be visible.
+//!
+//! Stripping the escape sequences from a progress bar or spinner keeps every
+//! frame that was drawn. A [`Flattener`] instead interprets the carriage
+//! returns, backspaces, line erases and cursor motion that redraw a line, and
+//! emits only the final content of each line.
+//!
+//! Lines are kept in a window of recent lines, which the cursor can move
+//! around in. A line is emitted once it scrolls out of the window (or when
+//! the output is finished), so memory use is bounded by the size of the
+//! window and the maximum line width. Output to the alternate screen (eg:
+//! from a full-screen program) is discarded.
+//!
+//! ```rust
+//! use fast_strip_ansi::flatten::flatten_bytes;
+//!
+//! let input = b"Downloading  10%\rDownloading  50%\rDownloading 100%\n\x1b[32mdone\x1b[0m\n";
+//! assert_eq!(flatten_bytes(input), b"Downloading 100%\ndone\n");
+//! ```
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+
+use vt_push_parser::VTPushParser;
+use vt_push_parser::event::{CSI, VTEvent};
+use vt_push_parser::text::{VTTextDecoder, VTTextEvent};
+
+/// The longest SGR style that is kept for a cell.
+const MAX_STYLE_LEN: usize = 256;
+
+/// Options for a [`Flattener`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlattenOptions {
+    /// The number of recent lines that the cursor can move between.
+    pub window: usize,
+    /// The width at which long lines are wrapped.
+    pub max_width: usize,
+    /// Keep SGR colors and attributes in the output.
+    pub sgr: bool,
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            max_width: 4096,
+            sgr: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    /// The SGR parameters in effect when this cell was written.
+    style: Option<Arc<[u8]>>,
+}
+
+const BLANK: Cell = Cell {
+    c: ' ',
+    style: None,
+};
+
+/// The lines in the window and the cursor position.
+#[derive(Debug)]
+struct Screen {
+    options: FlattenOptions,
+    lines: VecDeque<Vec<Cell>>,
+    row: usize,
+    col: usize,
+    style: Option<Arc<[u8]>>,
+    alternate: bool,
+    /// The bytes of the line being emitted, reused between lines.
+    output: Vec<u8>,
+}
+
+impl Screen {
+    fn text(&mut self, text: &str, cb: &mut impl FnMut(&[u8])) {
+        for c in text.chars() {
+            match c {
+                '\r' => self.col = 0,
+                // Logs assume that the terminal translates LF to CR LF
+                '\n' => self.newline(cb),
+                '\t' => self.col = ((self.col / 8 + 1) * 8).min(self.options.max_width - 1),
+                c if c.is_control() => {}
+                c => self.put(c, cb),
+            }
+        }
+    }
+
+    fn put(&mut self, c: char, cb: &mut impl FnMut(&[u8])) {
+        if self.col >= self.options.max_width {
+            self.newline(cb);
+        }
+        let cell = Cell {
+            c,
+            style: self.style.clone(),
+        };
+        let line = &mut self.lines[self.row];
+        if self.col < line.len() {
+            line[self.col] = cell;
+        } else {
+            line.resize(self.col, BLANK);
+            line.push(cell);
+        }
+        self.col += 1;
+    }
+
+    fn newline(&mut self, cb: &mut impl FnMut(&[u8])) {
+        self.col = 0;
+        self.row += 1;
+        if self.row == self.lines.len() {
+            self.lines.push_back(Vec::new());
+            if self.lines.len() > self.options.window {
+                let line = self.lines.pop_front().unwrap();
+                self.emit(&line, true, cb);
+                self.row -= 1;
+            }
+        }
+    }
+
+    fn emit(&mut self, line: &[Cell], newline: bool, cb: &mut impl FnMut(&[u8])) {
+        let len = line.len() - line.iter().rev().take_while(|c| **c == BLANK).count();
+        self.output.clear();
+        let mut style: Option<&Arc<[u8]>> = None;
+        for cell in &line[..len] {
+            if cell.style.as_ref() != style {
+                if style.is_some() {
+                    self.output.extend_from_slice(b"\x1b[0m");
+                }
+                if let Some(style) = &cell.style {
+                    self.output.extend_from_slice(b"\x1b[");
+                    self.output.extend_from_slice(style);
+                    self.output.push(b'm');
+                }
+                style = cell.style.as_ref();
+            }
+            self.output
+                .extend_from_slice(cell.c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        if style.is_some() {
+            self.output.extend_from_slice(b"\x1b[0m");
+        }
+        if newline {
+            self.output.push(b'\n');
+        }
+        if !self.output.is_empty() {
+            cb(&self.output);
+        }
+    }
+
+    fn finish(&mut self, cb: &mut impl FnMut(&[u8])) {
+        let lines = std::mem::replace(&mut self.lines, VecDeque::from([Vec::new()]));
+        for (i, line) in lines.iter().enumerate() {
+            self.emit(line, i + 1 < lines.len(), cb);
+        }
+        self.row = 0;
+        self.col = 0;
+    }
+
+    fn event(&mut self, event: VTTextEvent<'_>, cb: &mut impl FnMut(&[u8])) {
+        match event {
+            VTTextEvent::Event(VTEvent::Csi(csi)) => self.csi(&csi),
+            _ if self.alternate => {}
+            VTTextEvent::Text(text) => self.text(text, cb),
+            VTTextEvent::InvalidUtf8(_) => self.put(char::REPLACEMENT_CHARACTER, cb),
+            VTTextEvent::Event(VTEvent::C0(0x08)) => self.col = self.col.saturating_sub(1),
+            VTTextEvent::Event(VTEvent::C0(0x0b | 0x0c)) => self.newline(cb),
+            VTTextEvent::Event(_) => {}
+        }
+    }
+
+    fn csi(&mut self, csi: &CSI<'_>) {
+        if csi.private == Some(b'?') && matches!(csi.final_byte, b'h' | b'l') {
+            let modes = csi.params.numeric().into_iter().map(|p| p.sole());
+            if modes
+                .into_iter()
+                .any(|m| matches!(m, Some(47 | 1047 | 1049)))
+            {
+                self.alternate = csi.final_byte == b'h';
+            }
+            return;
+        }
+        if self.alternate || csi.private.is_some() || !csi.intermediates.is_empty() {
+            return;
+        }
+        let param = csi.params.try_parse::<usize>(0);
+        let n = param.unwrap_or(1).max(1);
+        let last_row = self.lines.len() - 1;
+        match csi.final_byte {
+            b'A' => self.row = self.row.saturating_sub(n),
+            b'B' => self.row = (self.row + n).min(last_row),
+            b'C' => self.col = (self.col + n).min(self.options.max_width - 1),
+            b'D' => self.col = self.col.saturating_sub(n),
+            b'E' => (self.row, self.col) = ((self.row + n).min(last_row), 0),
+            b'F' => (self.row, self.col) = (self.row.saturating_sub(n), 0),
+            b'G' | b'`' => self.col = (n - 1).min(self.options.max_width - 1),
+            b'K' => {
+                let line = &mut self.lines[self.row];
+                match param.unwrap_or(0) {
+                    0 => line.truncate(self.col),
+                    1 => {
+                        let end = (self.col + 1).min(line.len());
+                        line[..end].fill(BLANK);
+                    }
+                    2 => line.clear(),
+                    _ => {}
+                }
+            }
+            b'J' if param.unwrap_or(0) == 0 => {
+                self.lines[self.row].truncate(self.col);
+                self.lines.truncate(self.row + 1);
+            }
+            b'm' if self.options.sgr => self.sgr(csi),
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, csi: &CSI<'_>) {
+        let first = csi.params.get(0).unwrap_or_default();
+        let reset = first.is_empty() || first == b"0";
+        if reset && csi.params.len() <= 1 {
+            self.style = None;
+            return;
+        }
+        let mut style = match &self.style {
+            Some(style) if !reset => style.to_vec(),
+            _ => Vec::new(),
+        };
+        for param in &csi.params {
+            if !style.is_empty() {
+                style.push(b';');
+            }
+            style.extend_from_slice(param);
+        }
+        if style.len() > MAX_STYLE_LEN {
+            style.clear();
+            style.extend_from_slice(csi.params.get(0).unwrap_or_default());
+        }
+        self.style = Some(style.into());
+    }
+}
+
+/// A streaming flattener that can be fed chunks of data and yields the final
+/// text of each line to a callback. See the [module documentation](self).
+pub struct Flattener {
+    parser: VTPushParser,
+    decoder: VTTextDecoder,
+    screen: Screen,
+}
+
+impl Default for Flattener {
+    fn default() -> Self {
+        Self::new(FlattenOptions::default())
+    }
+}
+
+impl Flattener {
+    pub fn new(options: FlattenOptions) -> Self {
+        Self {
+            parser: VTPushParser::new(),
+            decoder: VTTextDecoder::new(),
+            screen: Screen {
+                options: FlattenOptions {
+                    window: options.window.max(1),
+                    max_width: options.max_width.max(1),
+                    ..options
+                },
+                lines: VecDeque::from([Vec::new()]),
+                row: 0,
+                col: 0,
+                style: None,
+                alternate: false,
+                output: Vec::new(),
+            },
+        }
+    }
+
+    /// Feed a chunk of data to the flattener. The callback will be called
+    /// with the text of each line that scrolls out of the window.
+    pub fn feed(&mut self, s: &[u8], cb: &mut impl FnMut(&[u8])) {
+        let decoder = &mut self.decoder;
+        let screen = &mut self.screen;
+        self.parser.feed_with(s, &mut |event: VTEvent| {
+            decoder.event(event, |event| screen.event(event, cb));
+        });
+    }
+
+    /// Emit all of the lines in the window, at the end of the output. The
+    /// last line is emitted without a newline.
+    pub fn finish(&mut self, cb: &mut impl FnMut(&[u8])) {
+        let screen = &mut self.screen;
+        self.decoder.flush(|event| screen.event(event, cb));
+        screen.finish(cb);
+    }
+}
+
+/// Flatten the output in a byte slice with the default options.
+pub fn flatten_bytes(s: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(s.len());
+    let mut flattener = Flattener::default();
+    let mut cb = |line: &[u8]| output.extend_from_slice(line);
+    flattener.feed(s, &mut cb);
+    flattener.finish(&mut cb);
+    output
+}
+
+/// A writer that flattens the data written to it and writes the final text
+/// of each line to the underlying writer.
+///
+/// Lines are written once they scroll out of the window, so
+/// [`FlattenWriter::finish`] must be called to write the remaining lines.
+/// Due to limitations of the [`std::io::Write`] interface, if the underlying
+/// writer returns an error, it may be uncertain how many bytes were written.
+pub struct FlattenWriter<W: io::Write> {
+    writer: W,
+    flattener: Flattener,
+}
+
+impl<W: io::Write> FlattenWriter<W> {
+    pub fn new(writer: W, options: FlattenOptions) -> Self {
+        Self {
+            writer,
+            flattener: Flattener::new(options),
+        }
+    }
+
+    /// Write the remaining lines, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut result = Ok(());
+        let writer = &mut self.writer;
+        self.flattener.finish(&mut |line| {
+            if result.is_ok() {
+                result = writer.write_all(line);
+            }
+        });
+        result.map(|_| self.writer)
+    }
+}
+
+impl<W: io::Write> io::Write for FlattenWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut result = Ok(());
+        let writer = &mut self.writer;
+        self.flattener.feed(buf, &mut |line| {
+            if result.is_ok() {
+                result = writer.write_all(line);
+            }
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn flatten(options: FlattenOptions, input: &[u8]) -> String {
+        let mut writer = FlattenWriter::new(Vec::new(), options);
+        for chunk in input.chunks(3) {
+            writer.write_all(chunk).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_carriage_return() {
+        assert_eq!(
+            flatten(Default::default(), b"a\n[    ]\r[==  ]\r[====] ok\r\nb"),
+            "a\n[====] ok\nb"
+        );
+        // A spinner drawn with backspaces
+        assert_eq!(
+            flatten(Default::default(), b"wait |\x08/\x08-\x08\\\x08 \n"),
+            "wait\n"
+        );
+        assert_eq!(flatten(Default::default(), "✅ é\n".as_bytes()), "✅ é\n");
+    }
+
+    #[test]
+    fn test_erase_and_cursor_up() {
+        // Two progress bars redrawn by moving the cursor up
+        let input = b"one 0%\ntwo 0%\n\x1b[2A\x1b[2Kone 100%\n\x1b[Ktwo 50%\x1b[1Gtwo 100%\n";
+        assert_eq!(flatten(Default::default(), input), "one 100%\ntwo 100%\n");
+        // Erase to the start of the line, and the rest of the screen
+        assert_eq!(
+            flatten(
+                Default::default(),
+                b"abcdef\x1b[3D\x1b[1K\n1\n2\n\x1b[2F\x1b[J3"
+            ),
+            "    ef\n3"
+        );
+    }
+
+    #[test]
+    fn test_window() {
+        let options = FlattenOptions {
+            window: 2,
+            ..Default::default()
+        };
+        let mut lines = vec![];
+        let mut flattener = Flattener::new(options);
+        flattener.feed(b"a\nb\nc\n", &mut |line| lines.push(line.to_vec()));
+        assert_eq!(lines, [b"a\n", b"b\n"]);
+        // The cursor can't move up out of the window
+        flattener.feed(b"\x1b[5Ax", &mut |line| lines.push(line.to_vec()));
+        flattener.finish(&mut |line| lines.push(line.to_vec()));
+        assert_eq!(lines, [&b"a\n"[..], b"b\n", b"x\n"]);
+    }
+
+    #[test]
+    fn test_wrap_and_alternate_screen() {
+        let options = FlattenOptions {
+            max_width: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            flatten(
+                options,
+                b"abcdef\n\x1b[?1049hfull screen\n\x1b[2J\x1b[?1049lend"
+            ),
+            "abcd\nef\nend"
+        );
+    }
+
+    #[test]
+    fn test_sgr() {
+        let options = FlattenOptions {
+            sgr: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            flatten(options, b"\x1b[31mred\x1b[1m!\x1b[0m \x1b[0;4mu\x1b[m\rR\n"),
+            "R\x1b[31med\x1b[0m\x1b[31;1m!\x1b[0m \x1b[0;4mu\x1b[0m\n"
+        );
+    }
+}
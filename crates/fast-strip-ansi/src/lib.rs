@@ -5,6 +5,10 @@ use std::borrow::Cow;
 use vt_push_parser::event::VTEvent;
 use vt_push_parser::{VT_PARSER_INTEREST_NONE, VTPushParser};
 
+pub mod flatten;
+pub mod offsets;
+pub mod width;
+
 /// Strip ANSI escape sequences from a string. If the input contains no ANSI
 /// escape sequences, the input is returned as-is.
 pub fn strip_ansi_string(s: &str) -> Cow<'_, str> {
@@ -72,6 +76,16 @@ pub fn strip_ansi_bytes_callback(s: &[u8], mut cb: impl FnMut(&[u8])) {
     });
 }
 
+/// The offset of `text` in `input`, if `text` borrows from it. The parser's
+/// `Raw` events borrow from the input, except for a byte that it held from the
+/// end of the previous chunk.
+pub(crate) fn offset_in(input: &[u8], text: &[u8]) -> Option<usize> {
+    let range = input.as_ptr_range();
+    let start = text.as_ptr();
+    (range.contains(&start) && text.as_ptr_range().end <= range.end)
+        .then(|| start as usize - range.start as usize)
+}
+
 /// A streaming ANSI escape sequence stripper that can be fed chunks of data
 /// and yields text chunks to a callback.
 pub struct StreamingStripper {
@@ -196,4 +210,13 @@ mod tests {
             Cow::Borrowed(b"")
         );
     }
+
+    #[test]
+    fn test_offset_in() {
+        let input = b"ab\x1b[1mcd";
+        assert_eq!(offset_in(input, &input[6..]), Some(6));
+        assert_eq!(offset_in(input, &input[..0]), Some(0));
+        assert_eq!(offset_in(input, &[0x1b]), None);
+        assert_eq!(offset_in(&input[..4], &input[2..6]), None);
+    }
 }
@@ -0,0 +1,284 @@
+//! Map offsets between stripped text and the original input.
+//!
+//! Searching the stripped text finds matches in stripped offsets, but
+//! highlighting them in the original output needs offsets into the original
+//! bytes. An [`OffsetMap`] records where each run of text came from, one
+//! entry per run, and projects offsets in either direction.
+//!
+//! ```rust
+//! use fast_strip_ansi::offsets::strip_ansi_string_with_offsets;
+//!
+//! let input = "error: \x1b[31mfile not found\x1b[0m";
+//! let (text, map) = strip_ansi_string_with_offsets(input);
+//! let start = text.find("not").unwrap();
+//! let range = map.to_original_range(start..start + 3);
+//! assert_eq!(&input[range], "not");
+//! ```
+use std::ops::Range;
+
+use vt_push_parser::event::VTEvent;
+use vt_push_parser::{VT_PARSER_INTEREST_NONE, VTPushParser};
+
+/// A run of text that is contiguous in both the stripped text and the
+/// original input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    stripped: usize,
+    original: usize,
+}
+
+/// A mapping between offsets in stripped text and offsets in the original
+/// input.
+///
+/// Offsets in the original input that fall inside an escape sequence map to
+/// the text that follows it. At the boundary between two runs of text, the
+/// start of a range maps past the escape sequences between them, and the end
+/// of a range maps before them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    runs: Vec<Run>,
+    stripped_len: usize,
+    original_end: usize,
+}
+
+impl OffsetMap {
+    pub const fn new() -> Self {
+        Self {
+            runs: Vec::new(),
+            stripped_len: 0,
+            original_end: 0,
+        }
+    }
+
+    /// Records `len` bytes of text that start at `original` in the input.
+    fn push(&mut self, original: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let contiguous = self
+            .runs
+            .last()
+            .is_some_and(|last| last.original + (self.stripped_len - last.stripped) == original);
+        if !contiguous {
+            self.runs.push(Run {
+                stripped: self.stripped_len,
+                original,
+            });
+        }
+        self.stripped_len += len;
+        self.original_end = original + len;
+    }
+
+    /// The number of runs of text, which is the size of the map.
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The length of the stripped text.
+    pub fn stripped_len(&self) -> usize {
+        self.stripped_len
+    }
+
+    /// The end of the last run of text in the original input.
+    pub fn original_end(&self) -> usize {
+        self.original_end
+    }
+
+    fn run_end(&self, index: usize) -> usize {
+        self.runs
+            .get(index + 1)
+            .map_or(self.stripped_len, |run| run.stripped)
+    }
+
+    /// Maps an offset in the stripped text to the original input, treating it
+    /// as the start of a range.
+    pub fn to_original(&self, offset: usize) -> usize {
+        let index = self.runs.partition_point(|run| run.stripped <= offset);
+        let Some(index) = index.checked_sub(1) else {
+            return 0;
+        };
+        let run = self.runs[index];
+        run.original + (offset.min(self.run_end(index)) - run.stripped)
+    }
+
+    /// Maps an offset in the stripped text to the original input, treating it
+    /// as the end of a range.
+    pub fn to_original_end(&self, offset: usize) -> usize {
+        let index = self.runs.partition_point(|run| run.stripped < offset);
+        let Some(index) = index.checked_sub(1) else {
+            return self.runs.first().map_or(0, |run| run.original);
+        };
+        let run = self.runs[index];
+        run.original + (offset.min(self.run_end(index)) - run.stripped)
+    }
+
+    /// Maps a range in the stripped text to the original input. The result
+    /// includes any escape sequences inside the range, but not those around
+    /// it.
+    pub fn to_original_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.to_original(range.start);
+        let end = self.to_original_end(range.end).max(start);
+        start..end
+    }
+
+    /// Maps an offset in the original input to the stripped text.
+    pub fn to_stripped(&self, offset: usize) -> usize {
+        let index = self.runs.partition_point(|run| run.original <= offset);
+        let Some(index) = index.checked_sub(1) else {
+            return 0;
+        };
+        let run = self.runs[index];
+        let len = self.run_end(index) - run.stripped;
+        run.stripped + (offset - run.original).min(len)
+    }
+
+    /// Maps a range in the original input to the stripped text.
+    pub fn to_stripped_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.to_stripped(range.start);
+        let end = self.to_stripped(range.end).max(start);
+        start..end
+    }
+}
+
+/// Strip ANSI escape sequences from a string, returning the stripped text and
+/// the mapping of its offsets to the input.
+pub fn strip_ansi_string_with_offsets(s: &str) -> (String, OffsetMap) {
+    let mut output = String::with_capacity(s.len());
+    let mut stripper = OffsetStripper::new();
+    stripper.feed(s.as_bytes(), &mut |text| {
+        output.push_str(String::from_utf8_lossy(text).as_ref())
+    });
+    (output, stripper.into_map())
+}
+
+/// Strip ANSI escape sequences from a byte slice, returning the stripped text
+/// and the mapping of its offsets to the input.
+pub fn strip_ansi_bytes_with_offsets(s: &[u8]) -> (Vec<u8>, OffsetMap) {
+    let mut output = Vec::with_capacity(s.len());
+    let mut stripper = OffsetStripper::new();
+    stripper.feed(s, &mut |text| output.extend_from_slice(text));
+    (output, stripper.into_map())
+}
+
+/// A streaming ANSI escape sequence stripper that builds an [`OffsetMap`] for
+/// the text it yields. Offsets in the map are relative to the start of the
+/// first chunk.
+pub struct OffsetStripper {
+    parser: VTPushParser<VT_PARSER_INTEREST_NONE>,
+    map: OffsetMap,
+    /// The number of bytes fed before the current chunk.
+    consumed: usize,
+}
+
+impl Default for OffsetStripper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OffsetStripper {
+    pub const fn new() -> Self {
+        Self {
+            parser: VTPushParser::new_with_interest::<VT_PARSER_INTEREST_NONE>(),
+            map: OffsetMap::new(),
+            consumed: 0,
+        }
+    }
+
+    /// Feed a chunk of data to the stripper. The callback will be called for
+    /// each raw text chunk.
+    pub fn feed(&mut self, s: &[u8], cb: &mut impl FnMut(&[u8])) {
+        let map = &mut self.map;
+        let consumed = self.consumed;
+        self.parser.feed_with(s, &mut |event: VTEvent| {
+            if let VTEvent::Raw(text) = event {
+                // Raw text borrows from the chunk, so its position is known,
+                // except for a byte held from the end of the previous chunk
+                let start = match crate::offset_in(s, text) {
+                    Some(start) => consumed + start,
+                    None => consumed.saturating_sub(text.len()),
+                };
+                map.push(start, text.len());
+                cb(text)
+            }
+        });
+        self.consumed += s.len();
+    }
+
+    /// The mapping for the text yielded so far.
+    pub fn map(&self) -> &OffsetMap {
+        &self.map
+    }
+
+    pub fn into_map(self) -> OffsetMap {
+        self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offsets() {
+        let input = "ab\x1b[31mcd\x1b[0m\x1b[1mef\x1b[0m";
+        let (text, map) = strip_ansi_string_with_offsets(input);
+        assert_eq!(text, "abcdef");
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.stripped_len(), 6);
+        assert_eq!(map.original_end(), 19);
+
+        assert_eq!(map.to_original(0), 0);
+        assert_eq!(map.to_original(2), 7);
+        assert_eq!(map.to_original_end(2), 2);
+        assert_eq!(map.to_original(5), 18);
+        assert_eq!(map.to_original(6), 19);
+        assert_eq!(&input[map.to_original_range(2..4)], "cd");
+        assert_eq!(
+            &input[map.to_original_range(1..5)],
+            "b\x1b[31mcd\x1b[0m\x1b[1me"
+        );
+        assert_eq!(map.to_original_range(3..3), 8..8);
+
+        assert_eq!(map.to_stripped(0), 0);
+        assert_eq!(map.to_stripped(3), 2);
+        assert_eq!(map.to_stripped(8), 3);
+        assert_eq!(map.to_stripped(10), 4);
+        assert_eq!(map.to_stripped(input.len()), 6);
+        assert_eq!(map.to_stripped_range(2..16), 2..4);
+    }
+
+    #[test]
+    fn test_offsets_edges() {
+        let (text, map) = strip_ansi_bytes_with_offsets(b"\x1b[1m\x1b[0m");
+        assert_eq!(text, b"");
+        assert!(map.is_empty());
+        assert_eq!(map.to_original_range(0..0), 0..0);
+        assert_eq!(map.to_stripped(3), 0);
+
+        let input = b"\x1b[1mab\x1b[0m";
+        let (_, map) = strip_ansi_bytes_with_offsets(input);
+        assert_eq!(map.to_original_range(0..2), 4..6);
+        assert_eq!(map.to_original_range(0..0), 4..4);
+        assert_eq!(map.to_stripped(2), 0);
+    }
+
+    #[test]
+    fn test_offsets_streaming() {
+        let input = b"one \x1b[31mtwo\x1b[0m three\x1b]0;title\x07 four";
+        let (text, expected) = strip_ansi_bytes_with_offsets(input);
+        for split in 0..input.len() {
+            let mut stripper = OffsetStripper::new();
+            let mut output = vec![];
+            for chunk in [&input[..split], &input[split..]] {
+                stripper.feed(chunk, &mut |text| output.extend_from_slice(text));
+            }
+            assert_eq!(output, text, "split at {split}");
+            assert_eq!(stripper.map(), &expected, "split at {split}");
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Measure, truncate and wrap text that contains escape sequences.
+//!
+//! Widths are measured in terminal columns over the visible text only, using
+//! grapheme clusters and Unicode East Asian width. Control characters have no
+//! width.
+//!
+//! Truncation and wrapping keep the escape sequences of the input, and keep
+//! them balanced: a line that is cut while a color or an OSC 8 hyperlink is
+//! active is closed with a reset, and a continuation line re-opens whatever
+//! was active where the line was broken.
+//!
+//! ```rust
+//! use fast_strip_ansi::width::{ansi_truncate, ansi_width, ansi_wrap};
+//!
+//! let input = "\x1b[31mhello\x1b[0m, 世界";
+//! assert_eq!(ansi_width(input), 11);
+//! assert_eq!(ansi_truncate(input, 4, "…"), "\x1b[31mhel…\x1b[0m");
+//! assert_eq!(
+//!     ansi_wrap("\x1b[1mone two\x1b[0m", 4),
+//!     ["\x1b[1mone\x1b[0m", "\x1b[1mtwo\x1b[0m"]
+//! );
+//! ```
+use std::borrow::Cow;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use vt_push_parser::VTPushParser;
+use vt_push_parser::event::{OscParams, VTEvent};
+
+/// The longest OSC 8 sequence that is tracked as a hyperlink.
+const MAX_LINK_LEN: usize = 4096;
+
+const SGR_RESET: &str = "\x1b[0m";
+const LINK_CLOSE: &str = "\x1b]8;;\x1b\\";
+
+/// The SGR attributes and hyperlink that are active at a point in the text.
+#[derive(Debug, Clone, Default)]
+struct AnsiState {
+    /// The SGR sequences since the last reset.
+    sgr: Vec<u8>,
+    /// The OSC 8 sequence that opened the current hyperlink.
+    link: Vec<u8>,
+    /// The OSC 8 sequence being collected.
+    osc: Option<Vec<u8>>,
+}
+
+impl AnsiState {
+    fn event(&mut self, event: &VTEvent<'_>) {
+        match event {
+            VTEvent::Csi(csi)
+                if csi.final_byte == b'm'
+                    && csi.private.is_none()
+                    && csi.intermediates.is_empty() =>
+            {
+                if csi.params.get(0).is_none_or(|p| matches!(p, b"" | b"0")) {
+                    self.sgr.clear();
+                }
+                if !csi.params.into_iter().all(|p| matches!(p, b"" | b"0")) {
+                    _ = event.write_to(&mut self.sgr);
+                }
+            }
+            VTEvent::OscStart { command: Some(8) } => self.osc = Some(Vec::new()),
+            VTEvent::OscData(data) => {
+                if let Some(osc) = &mut self.osc {
+                    osc.extend_from_slice(data);
+                    if osc.len() > MAX_LINK_LEN {
+                        self.osc = None;
+                    }
+                }
+            }
+            VTEvent::OscEnd { data, .. } => {
+                let Some(mut osc) = self.osc.take() else {
+                    return;
+                };
+                osc.extend_from_slice(data);
+                self.link.clear();
+                if OscParams::new(&osc)
+                    .remainder(2)
+                    .is_some_and(|uri| !uri.is_empty())
+                {
+                    self.link.extend_from_slice(b"\x1b]");
+                    self.link.extend_from_slice(&osc);
+                    self.link.extend_from_slice(b"\x1b\\");
+                }
+            }
+            VTEvent::OscCancel => self.osc = None,
+            _ => {}
+        }
+    }
+
+    /// Appends the sequences that re-establish this state.
+    fn open(&self, out: &mut String) {
+        out.push_str(&String::from_utf8_lossy(&self.sgr));
+        out.push_str(&String::from_utf8_lossy(&self.link));
+    }
+
+    /// Appends the sequences that reset this state.
+    fn close(&self, out: &mut String) {
+        if !self.link.is_empty() {
+            out.push_str(LINK_CLOSE);
+        }
+        if !self.sgr.is_empty() {
+            out.push_str(SGR_RESET);
+        }
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    /// One or more escape sequences or control characters, verbatim.
+    Escape(&'a str),
+}
+
+/// Splits the input into text and escape sequences, with the state that is
+/// active at each text segment.
+fn segments(s: &str, mut cb: impl FnMut(Segment<'_>, &AnsiState)) {
+    let mut parser = VTPushParser::new();
+    let mut state = AnsiState::default();
+    let mut end = 0;
+    parser.feed_with(s.as_bytes(), |event: VTEvent| {
+        if let VTEvent::Raw(text) = event {
+            // Raw text borrows from the input, so its position is known. The
+            // input is fed at once, so no byte is held from a previous chunk.
+            let Some(start) = crate::offset_in(s.as_bytes(), text) else {
+                debug_assert!(false, "Raw text outside of the input");
+                return;
+            };
+            if start > end {
+                cb(Segment::Escape(&s[end..start]), &state);
+            }
+            end = start + text.len();
+            cb(Segment::Text(&s[start..end]), &state);
+        } else {
+            state.event(&event);
+        }
+    });
+    if end < s.len() {
+        cb(Segment::Escape(&s[end..]), &state);
+    }
+}
+
+fn grapheme_width(g: &str) -> usize {
+    if g.chars().any(char::is_control) {
+        0
+    } else {
+        g.width()
+    }
+}
+
+/// The display width of the visible text, in columns.
+pub fn ansi_width(s: &str) -> usize {
+    let mut width = 0;
+    segments(s, |segment, _| {
+        if let Segment::Text(text) = segment {
+            width += text.graphemes(true).map(grapheme_width).sum::<usize>();
+        }
+    });
+    width
+}
+
+/// Truncates the input to at most `width` columns, ending it with `ellipsis`
+/// if anything was cut. If the input already fits, it is returned as-is.
+///
+/// The escape sequences before the cut are kept, and any SGR attributes or
+/// hyperlink that are still active are closed after the ellipsis. If the
+/// ellipsis is wider than `width`, it is left out.
+pub fn ansi_truncate<'a>(s: &'a str, width: usize, ellipsis: &str) -> Cow<'a, str> {
+    if ansi_width(s) <= width {
+        return Cow::Borrowed(s);
+    }
+    let ellipsis_width = ansi_width(ellipsis);
+    let (limit, ellipsis) = if ellipsis_width <= width {
+        (width - ellipsis_width, ellipsis)
+    } else {
+        (width, "")
+    };
+
+    let mut out = String::with_capacity(s.len());
+    let mut col = 0;
+    let mut cut = false;
+    segments(s, |segment, state| {
+        if cut {
+            return;
+        }
+        match segment {
+            Segment::Escape(escape) => out.push_str(escape),
+            Segment::Text(text) => {
+                for g in text.graphemes(true) {
+                    let w = grapheme_width(g);
+                    if col + w > limit {
+                        out.push_str(ellipsis);
+                        state.close(&mut out);
+                        cut = true;
+                        return;
+                    }
+                    out.push_str(g);
+                    col += w;
+                }
+            }
+        }
+    });
+    Cow::Owned(out)
+}
+
+/// A point in the current line where it can be broken.
+struct Break {
+    /// The byte offset of the start of the whitespace.
+    cut: usize,
+    cut_state: AnsiState,
+    /// The byte offset and column just after the whitespace.
+    resume: usize,
+    resume_col: usize,
+    resume_state: AnsiState,
+}
+
+/// Wraps the input into lines of at most `width` columns, breaking at
+/// whitespace where possible and within words otherwise. Newlines in the
+/// input always start a new line.
+///
+/// Each line is balanced: any SGR attributes or hyperlink active at the end
+/// of a line are closed, and re-opened at the start of the next line.
+/// Whitespace at a break is dropped.
+pub fn ansi_wrap(s: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut col = 0;
+    let mut brk: Option<Break> = None;
+    let mut last_state = AnsiState::default();
+    segments(s, |segment, state| {
+        last_state.clone_from(state);
+        let text = match segment {
+            Segment::Escape(escape) => {
+                line.push_str(escape);
+                return;
+            }
+            Segment::Text(text) => text,
+        };
+        for g in text.graphemes(true) {
+            if g.contains('\n') {
+                state.close(&mut line);
+                lines.push(std::mem::take(&mut line));
+                state.open(&mut line);
+                col = 0;
+                brk = None;
+                continue;
+            }
+            let w = grapheme_width(g);
+            if g.chars().all(char::is_whitespace) {
+                match &mut brk {
+                    Some(brk) if brk.resume == line.len() => {
+                        brk.resume_state = state.clone();
+                    }
+                    _ => {
+                        brk = Some(Break {
+                            cut: line.len(),
+                            cut_state: state.clone(),
+                            resume: 0,
+                            resume_col: 0,
+                            resume_state: state.clone(),
+                        })
+                    }
+                }
+                line.push_str(g);
+                col += w;
+                let brk = brk.as_mut().unwrap();
+                brk.resume = line.len();
+                brk.resume_col = col;
+                continue;
+            }
+            if col + w > width && col > 0 {
+                if let Some(brk) = brk.take() {
+                    let mut next = String::new();
+                    brk.resume_state.open(&mut next);
+                    next.push_str(&line[brk.resume..]);
+                    line.truncate(brk.cut);
+                    brk.cut_state.close(&mut line);
+                    lines.push(std::mem::replace(&mut line, next));
+                    col -= brk.resume_col;
+                }
+                if col + w > width && col > 0 {
+                    state.close(&mut line);
+                    lines.push(std::mem::take(&mut line));
+                    state.open(&mut line);
+                    col = 0;
+                }
+            }
+            line.push_str(g);
+            col += w;
+        }
+    });
+    last_state.close(&mut line);
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width() {
+        assert_eq!(ansi_width(""), 0);
+        assert_eq!(ansi_width("\x1b[1;31mred\x1b[0m"), 3);
+        assert_eq!(ansi_width("\x1b]8;;http://x/\x1b\\link\x1b]8;;\x07"), 4);
+        assert_eq!(ansi_width("日本語"), 6);
+        assert_eq!(ansi_width("e\u{301}👍🏽"), 3);
+        assert_eq!(ansi_width("a\tb\r\n"), 2);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert!(matches!(
+            ansi_truncate("\x1b[31mfits\x1b[0m", 4, "…"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(ansi_truncate("abcdef", 4, "…"), "abc…");
+        assert_eq!(ansi_truncate("abcdef", 4, ""), "abcd");
+        assert_eq!(ansi_truncate("abcdef", 1, "..."), "a");
+        // Wide characters are never split
+        assert_eq!(ansi_truncate("日本語", 4, "…"), "日…");
+        assert_eq!(
+            ansi_truncate("\x1b[31mred\x1b[1m bold\x1b[0m plain", 6, "…"),
+            "\x1b[31mred\x1b[1m b…\x1b[0m"
+        );
+        assert_eq!(
+            ansi_truncate("\x1b[31mred\x1b[0m plain", 6, "…"),
+            "\x1b[31mred\x1b[0m p…"
+        );
+        assert_eq!(
+            ansi_truncate("\x1b]8;id=1;http://x/\x1b\\a link\x1b]8;;\x1b\\", 3, "…"),
+            "\x1b]8;id=1;http://x/\x1b\\a …\x1b]8;;\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(ansi_wrap("", 4), [""]);
+        assert_eq!(
+            ansi_wrap("the quick brown fox", 10),
+            ["the quick", "brown fox"]
+        );
+        assert_eq!(ansi_wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(ansi_wrap("ab  cd\nef", 3), ["ab", "cd", "ef"]);
+        assert_eq!(ansi_wrap("日本語", 3), ["日", "本", "語"]);
+        assert_eq!(
+            ansi_wrap("\x1b[31mred \x1b[1mbold\x1b[0m text", 4),
+            ["\x1b[31mred\x1b[0m", "\x1b[31m\x1b[1mbold\x1b[0m", "text"]
+        );
+    }
+
+    #[test]
+    fn test_wrap_hyperlink() {
+        assert_eq!(
+            ansi_wrap("see \x1b]8;;http://x/\x07the docs\x1b]8;;\x07 here", 8),
+            [
+                "see \x1b]8;;http://x/\x07the\x1b]8;;\x1b\\",
+                "\x1b]8;;http://x/\x1b\\docs\x1b]8;;\x07",
+                "here"
+            ]
+        );
+    }
+}
@@ -8,12 +8,16 @@ use vt_push_parser::VTPushParser;
 use vt_push_parser::ascii::AsciiControl;
 use vt_push_parser::signature::VTEscapeSignature;
 
+/// Terminal profiles that may be selected via cargo features.
+const PROFILES: &[&str] = &["xterm", "vt220", "vt52", "sun", "linux", "rxvt", "kitty"];
+
 #[derive(Debug, Clone)]
 struct Match {
     sequence: String,
     match_type: MatchType,
     key: KeyModifier,
     key_sequence: KeySequence,
+    enabled: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +111,23 @@ fn decode_sequence(sequence: &str) -> Vec<u8> {
     sequence_bytes
 }
 
+/// Parse a `[profile, ...]` section header, returning whether any of the
+/// profiles are enabled.
+fn parse_section(section: &str) -> bool {
+    let mut enabled = false;
+    for profile in section.split(',').map(str::trim) {
+        if profile == "common" {
+            enabled = true;
+        } else if PROFILES.contains(&profile) {
+            let feature = format!("CARGO_FEATURE_{}", profile.to_ascii_uppercase());
+            enabled |= std::env::var_os(feature).is_some();
+        } else {
+            panic!("Unknown profile: {profile:?}");
+        }
+    }
+    enabled
+}
+
 fn generate_matcher_fn(
     fn_name: &str,
     more_data: bool,
@@ -270,12 +291,9 @@ fn generate_matcher_fn(
         }
 
         match (more_data, node.match_type) {
-            (true, MatchType::Weak) => {
-                out.push_str(&format!(
-                    "{ind3}    _ => return MatchResult::PendingMatch,\n"
-                ));
-            }
-            (false, MatchType::Weak) => {
+            // An unmatched byte below a weak node can never become a match,
+            // regardless of how much more data arrives.
+            (_, MatchType::Weak) => {
                 out.push_str(&format!(
                     "{ind3}    _ => return MatchResult::NoMatch {{ length: {depth} }},\n"
                 ));
@@ -381,11 +399,16 @@ fn main() {
     let mut key_codes_tilde = BTreeMap::new();
     let mut key_codes_sun = BTreeMap::new();
     let mut all_sequence_set = BTreeSet::new();
+    let mut enabled = true;
 
     for (i, mut line) in keys.lines().enumerate() {
         if line.trim().is_empty() || line.starts_with("//") {
             continue;
         }
+        if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            enabled = parse_section(section);
+            continue;
+        }
         if line.contains(" // ") {
             line = line.split(" // ").next().unwrap();
         }
@@ -456,6 +479,7 @@ fn main() {
             match_type,
             key,
             key_sequence,
+            enabled,
         };
         writeln!(key_sequence_file, "// {m:?}").unwrap();
 
@@ -466,23 +490,29 @@ fn main() {
     let mut modifiers = BTreeSet::new();
     let mut named_keys = BTreeSet::new();
     let mut all_sequences = BTreeMap::new();
+    let mut regular_sequences = BTreeMap::new();
     for m in all_keys {
         modifiers.extend(m.key.modifiers.clone());
         if let Key::Named(key) = &m.key.key {
             named_keys.insert(key.clone());
         }
-        let KeySequence::Irregular(irregular) = m.key_sequence else {
+        // Keys are always generated, but only enabled sequences are matched
+        if !m.enabled {
             continue;
+        }
+        let key = match &m.key.key {
+            Key::Char(c) => format!("{c:?}, ({})", m.key.modifiers.join(", ")),
+            Key::Named(n) => format!("{n}, ({})", m.key.modifiers.join(", ")),
         };
-        let len = irregular.len();
-        let payload = match m.key.key {
-            Key::Char(c) => {
-                format!("e!({len}, {c:?}, ({}))", m.key.modifiers.join(", "))
-            }
-            Key::Named(n) => {
-                format!("e!({len}, {n}, ({}))", m.key.modifiers.join(", "))
+        let irregular = match m.key_sequence {
+            KeySequence::Regular(param, final_byte) => {
+                regular_sequences.insert((final_byte, param), key);
+                continue;
             }
+            KeySequence::Irregular(irregular) => irregular,
         };
+        let len = irregular.len();
+        let payload = format!("e!({len}, {key})");
 
         if all_sequences
             .insert(m.sequence.clone(), (payload, m.match_type))
@@ -494,7 +524,7 @@ fn main() {
 
     writeln!(
         key_sequence_file,
-        "#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::TryFrom)]"
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, derive_more::TryFrom)]"
     )
     .unwrap();
     writeln!(key_sequence_file, "#[try_from(repr)]").unwrap();
@@ -503,11 +533,37 @@ fn main() {
     writeln!(key_sequence_file, "#[allow(clippy::upper_case_acronyms)]").unwrap();
     writeln!(key_sequence_file, "#[allow(unused)]").unwrap();
     writeln!(key_sequence_file, "pub enum Key {{").unwrap();
-    for key in named_keys {
+    for key in &named_keys {
         writeln!(key_sequence_file, "    {key},").unwrap();
     }
     writeln!(key_sequence_file, "}}").unwrap();
 
+    writeln!(key_sequence_file, "impl Key {{").unwrap();
+    writeln!(key_sequence_file, "    /// All named keys.").unwrap();
+    writeln!(key_sequence_file, "    pub const ALL: &[Key] = &[").unwrap();
+    for key in &named_keys {
+        writeln!(key_sequence_file, "        Key::{key},").unwrap();
+    }
+    writeln!(key_sequence_file, "    ];").unwrap();
+    writeln!(key_sequence_file).unwrap();
+    writeln!(
+        key_sequence_file,
+        "    /// The name of this key (eg: `PAGE_UP`)."
+    )
+    .unwrap();
+    writeln!(
+        key_sequence_file,
+        "    pub const fn name(self) -> &'static str {{"
+    )
+    .unwrap();
+    writeln!(key_sequence_file, "        match self {{").unwrap();
+    for key in &named_keys {
+        writeln!(key_sequence_file, "            Key::{key} => {key:?},").unwrap();
+    }
+    writeln!(key_sequence_file, "        }}").unwrap();
+    writeln!(key_sequence_file, "    }}").unwrap();
+    writeln!(key_sequence_file, "}}").unwrap();
+
     let all_keys = key_codes_u
         .keys()
         .chain(key_codes_tilde.keys())
@@ -544,6 +600,30 @@ fn main() {
     }
     writeln!(key_sequence_file, "}}").unwrap();
 
+    writeln!(key_sequence_file, "#[allow(unused)]").unwrap();
+    writeln!(
+        key_sequence_file,
+        "pub fn find_regular(final_byte: u8, param: u16) -> Option<u32> {{"
+    )
+    .unwrap();
+    if regular_sequences.is_empty() {
+        // A match with only a wildcard arm trips clippy::match_single_binding
+        writeln!(key_sequence_file, "    None").unwrap();
+    } else {
+        writeln!(key_sequence_file, "    match (final_byte, param) {{").unwrap();
+        for ((final_byte, param), key) in regular_sequences {
+            writeln!(
+                key_sequence_file,
+                "        (b{:?}, {param}) => Some(k!({key})),",
+                final_byte as char
+            )
+            .unwrap();
+        }
+        writeln!(key_sequence_file, "        _ => None,").unwrap();
+        writeln!(key_sequence_file, "    }}").unwrap();
+    }
+    writeln!(key_sequence_file, "}}").unwrap();
+
     let max_sequence_len = all_sequences.keys().map(|s| s.len()).max().unwrap();
     writeln!(
         key_sequence_file,
@@ -0,0 +1,261 @@
+//! Light/dark background detection.
+//!
+//! [`detect_color_scheme`] asks the terminal for its default foreground and
+//! background colors (OSC 10 and OSC 11), and decides whether the terminal is
+//! using a light or dark color scheme from the perceived luminance of the
+//! reply. The queries are fenced with a DA1 request so that terminals that
+//! don't support color queries are detected without waiting for the timeout.
+//!
+//! The reader and writer are supplied by the caller, and are typically the
+//! terminal's stdin and stdout in raw mode. The reader should return
+//! periodically (eg: using a read timeout or non-blocking mode) so that the
+//! timeout can be enforced. Any input that isn't a reply is passed through to
+//! the caller, as the user may type while the query is in flight.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use vt_input_push_parser::background::detect_color_scheme;
+//! use vt_input_push_parser::{ColorScheme, VTPushParserInput};
+//!
+//! let mut parser = VTPushParserInput::new();
+//! let scheme = detect_color_scheme(
+//!     &mut parser,
+//!     std::io::stdin(),
+//!     std::io::stdout(),
+//!     Duration::from_millis(100),
+//!     ColorScheme::Dark,
+//!     |event| println!("{event:?}"),
+//! )
+//! .unwrap();
+//! ```
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::query::{CapabilityQuery, Query, Rgb};
+use crate::{ColorScheme, InputEvent, VTPushParserInput};
+
+/// The default colors reported by the terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerminalColors {
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+}
+
+impl TerminalColors {
+    /// The color scheme implied by the colors. The background color is used if
+    /// known, otherwise a light foreground implies a dark background.
+    pub fn color_scheme(&self) -> Option<ColorScheme> {
+        let light = match (self.background, self.foreground) {
+            (Some(background), _) => background.luminance() > 0.5,
+            (None, Some(foreground)) => foreground.luminance() <= 0.5,
+            (None, None) => return None,
+        };
+        Some(if light {
+            ColorScheme::Light
+        } else {
+            ColorScheme::Dark
+        })
+    }
+}
+
+/// Queries the terminal's default colors, returning once the terminal has
+/// replied or `timeout` has elapsed. Colors that the terminal doesn't report
+/// are `None`.
+///
+/// Input is parsed with `parser`, and any input that isn't a reply is passed
+/// to `cb`.
+pub fn query_colors(
+    parser: &mut VTPushParserInput,
+    mut reader: impl Read,
+    mut writer: impl Write,
+    timeout: Duration,
+    mut cb: impl FnMut(InputEvent),
+) -> io::Result<TerminalColors> {
+    let deadline = Instant::now() + timeout;
+    let mut query = CapabilityQuery::empty()
+        .with(Query::ForegroundColor)
+        .with(Query::BackgroundColor);
+    query.write_to(&mut writer)?;
+    writer.flush()?;
+
+    let mut buf = [0; 256];
+    while !query.is_complete() && Instant::now() < deadline {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            // Avoid spinning on readers that return immediately
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        query.feed_with(parser, &buf[..n], &mut cb);
+    }
+    let capabilities = query.capabilities();
+    Ok(TerminalColors {
+        foreground: capabilities.foreground,
+        background: capabilities.background,
+    })
+}
+
+/// Detects whether the terminal is using a light or dark color scheme,
+/// returning `fallback` if the terminal doesn't report its colors before
+/// `timeout` elapses.
+///
+/// Input is parsed with `parser`, and any input that isn't a reply is passed
+/// to `cb`.
+pub fn detect_color_scheme(
+    parser: &mut VTPushParserInput,
+    reader: impl Read,
+    writer: impl Write,
+    timeout: Duration,
+    fallback: ColorScheme,
+    cb: impl FnMut(InputEvent),
+) -> io::Result<ColorScheme> {
+    Ok(query_colors(parser, reader, writer, timeout, cb)?
+        .color_scheme()
+        .unwrap_or(fallback))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// A fake terminal that replies with scripted chunks, then has no more
+    /// input.
+    struct ScriptedReader(VecDeque<&'static [u8]>);
+
+    impl Read for ScriptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Err(io::ErrorKind::WouldBlock.into());
+            };
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    fn detect(
+        replies: &[&'static [u8]],
+        timeout: Duration,
+        fallback: ColorScheme,
+    ) -> (ColorScheme, Vec<u8>, Vec<String>) {
+        let reader = ScriptedReader(replies.iter().copied().collect());
+        let mut parser = VTPushParserInput::new();
+        let mut written = vec![];
+        let mut input = vec![];
+        let scheme = detect_color_scheme(
+            &mut parser,
+            reader,
+            &mut written,
+            timeout,
+            fallback,
+            |event| input.push(format!("{event:?}")),
+        )
+        .unwrap();
+        (scheme, written, input)
+    }
+
+    #[test]
+    fn test_detect() {
+        let timeout = Duration::from_millis(20);
+        let (scheme, written, input) = detect(
+            &[
+                b"\x1b]10;rgb:0000/0000/0000\x1b\\\x1b]11;rgb:ff",
+                b"ff/ffff/ffff\x07\x1b[?62c",
+            ],
+            timeout,
+            ColorScheme::Dark,
+        );
+        assert_eq!(scheme, ColorScheme::Light);
+        assert_eq!(written, b"\x1b]10;?\x1b\\\x1b]11;?\x1b\\\x1b[c");
+        assert!(input.is_empty());
+
+        let (scheme, _, _) = detect(
+            &[b"\x1b]11;#1e1e2e\x07\x1b[?62c"],
+            timeout,
+            ColorScheme::Light,
+        );
+        assert_eq!(scheme, ColorScheme::Dark);
+
+        // Only the foreground is known
+        let (scheme, _, _) = detect(
+            &[b"\x1b]10;rgb:eeee/eeee/eeee\x07\x1b[?62c"],
+            timeout,
+            ColorScheme::Light,
+        );
+        assert_eq!(scheme, ColorScheme::Dark);
+    }
+
+    #[test]
+    fn test_passthrough() {
+        // The user types while the query is in flight
+        let (scheme, _, input) = detect(
+            &[b"a\x1b]11;#ffffff\x07b", b"\x1b]2;title\x07\x1b[?62cc"],
+            Duration::from_millis(20),
+            ColorScheme::Dark,
+        );
+        assert_eq!(scheme, ColorScheme::Light);
+        assert_eq!(
+            input,
+            [
+                "KeyChar('a', Modifier(0))",
+                "KeyChar('b', Modifier(0))",
+                "Osc([50, 59, 116, 105, 116, 108, 101])",
+                "KeyChar('c', Modifier(0))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fallback() {
+        // The terminal doesn't support color queries, so we stop at DA1
+        let start = Instant::now();
+        let (scheme, _, input) = detect(
+            &[b"x\x1b[?1;2c", b"\x1b]11;#ffffff\x07"],
+            Duration::from_secs(10),
+            ColorScheme::Dark,
+        );
+        assert_eq!(scheme, ColorScheme::Dark);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(input, ["KeyChar('x', Modifier(0))"]);
+
+        // The terminal never replies
+        let (scheme, _, _) = detect(&[], Duration::from_millis(20), ColorScheme::Light);
+        assert_eq!(scheme, ColorScheme::Light);
+    }
+
+    #[test]
+    fn test_luminance() {
+        let rgb = |v| TerminalColors {
+            foreground: None,
+            background: Some(Rgb { r: v, g: v, b: v }),
+        };
+        assert_eq!(rgb(0x7000).color_scheme(), Some(ColorScheme::Dark));
+        assert_eq!(rgb(0x9000).color_scheme(), Some(ColorScheme::Light));
+        // Green is perceived as much brighter than blue
+        let green = Rgb {
+            r: 0,
+            g: 0xffff,
+            b: 0,
+        };
+        let blue = Rgb {
+            r: 0,
+            g: 0,
+            b: 0xffff,
+        };
+        assert!(green.luminance() > 0.5);
+        assert!(blue.luminance() < 0.5);
+    }
+}
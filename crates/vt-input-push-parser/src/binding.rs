@@ -0,0 +1,489 @@
+//! Declarative key bindings.
+//!
+//! Key bindings are written as `+`-separated modifiers followed by a key, eg:
+//! `ctrl+alt+x`, `shift+F5` or `alt+enter`. A [`Chord`] is a whitespace
+//! separated sequence of bindings, eg: `ctrl+x ctrl+s`.
+//!
+//! Terminals encode many keys in more than one way, so both parsed bindings
+//! and incoming [`InputEvent`]s are normalized before being compared:
+//!
+//!  - Control characters are mapped to the key that produces them:
+//!    `<01>` is `ctrl+a`, `<1b>` is `esc`, `<09>` is `tab` and so on. Likewise,
+//!    `ctrl+i` is `tab`, `ctrl+m` is `enter` and `ctrl+h` is
+//!    `ctrl+backspace`, as legacy terminals can't tell them apart.
+//!  - Uppercase ASCII letters are mapped to `shift` plus the lowercase letter,
+//!    while `shift` is dropped from other printable characters (the character
+//!    already reflects it).
+//!  - The `keypad`, `sun` and `vt52` modifiers, which describe the encoding
+//!    rather than the key, are dropped.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{InputEvent, Key, Modifier};
+
+/// An error parsing a key, modifier, binding or chord.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseKeyError {
+    Empty,
+    UnknownKey(String),
+    UnknownModifier(String),
+}
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseKeyError::Empty => write!(f, "empty key binding"),
+            ParseKeyError::UnknownKey(key) => write!(f, "unknown key: {key:?}"),
+            ParseKeyError::UnknownModifier(modifier) => {
+                write!(f, "unknown modifier: {modifier:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseKeyError {}
+
+/// Alternative names accepted when parsing keys.
+const KEY_ALIASES: &[(&str, Key)] = &[
+    ("escape", Key::ESC),
+    ("return", Key::ENTER),
+    ("del", Key::DELETE),
+    ("ins", Key::INSERT),
+    ("pgup", Key::PAGE_UP),
+    ("pgdown", Key::PAGE_DOWN),
+];
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.name().chars() {
+            write!(f, "{}", c.to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a key name, ignoring case and treating `-` as `_` (eg: `page-up`,
+/// `PAGE_UP` and `Page_Up` are all [`Key::PAGE_UP`]).
+impl FromStr for Key {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let matches = |name: &str| {
+            name.len() == s.len()
+                && name
+                    .bytes()
+                    .zip(s.bytes())
+                    .all(|(a, b)| a.eq_ignore_ascii_case(&if b == b'-' { b'_' } else { b }))
+        };
+        Key::ALL
+            .iter()
+            .copied()
+            .find(|key| matches(key.name()))
+            .or_else(|| {
+                KEY_ALIASES
+                    .iter()
+                    .find(|(name, _)| matches(name))
+                    .map(|(_, key)| *key)
+            })
+            .ok_or_else(|| ParseKeyError::UnknownKey(s.to_owned()))
+    }
+}
+
+const MODIFIER_NAMES: &[(&str, Modifier)] = &[
+    ("ctrl", Modifier::CTRL),
+    ("alt", Modifier::ALT),
+    ("shift", Modifier::SHIFT),
+    ("keypad", Modifier::KEYPAD),
+    ("sun", Modifier::SUN),
+    ("vt52", Modifier::VT52),
+];
+
+/// Alternative names accepted when parsing modifiers.
+const MODIFIER_ALIASES: &[(&str, Modifier)] = &[
+    ("control", Modifier::CTRL),
+    ("meta", Modifier::ALT),
+    ("option", Modifier::ALT),
+];
+
+/// Displays modifiers as `+`-separated names (eg: `ctrl+alt`).
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (name, modifier) in MODIFIER_NAMES {
+            if self.contains(*modifier) {
+                if !first {
+                    write!(f, "+")?;
+                }
+                write!(f, "{name}")?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses `+`-separated modifier names, ignoring case. An empty string is
+/// parsed as no modifiers.
+impl FromStr for Modifier {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifier::empty();
+        if s.is_empty() {
+            return Ok(modifiers);
+        }
+        for name in s.split('+') {
+            let (_, modifier) = MODIFIER_NAMES
+                .iter()
+                .chain(MODIFIER_ALIASES)
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .ok_or_else(|| ParseKeyError::UnknownModifier(name.to_owned()))?;
+            modifiers |= *modifier;
+        }
+        Ok(modifiers)
+    }
+}
+
+/// A single normalized key press, which may be compared against incoming
+/// [`InputEvent`]s.
+///
+/// See the [module documentation](self) for the normalization rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyBinding {
+    /// A named key.
+    Key(Key, Modifier),
+    /// A character key.
+    Char(char, Modifier),
+}
+
+impl KeyBinding {
+    /// Creates a normalized binding for a named key.
+    pub fn key(key: Key, modifiers: Modifier) -> Self {
+        KeyBinding::Key(
+            key,
+            modifiers & (Modifier::SHIFT | Modifier::ALT | Modifier::CTRL),
+        )
+    }
+
+    /// Creates a normalized binding for a character.
+    pub fn char(c: char, modifiers: Modifier) -> Self {
+        let modifiers = modifiers & (Modifier::SHIFT | Modifier::ALT | Modifier::CTRL);
+        // Map control-key combinations to the control character they produce
+        let c = if modifiers.contains(Modifier::CTRL) {
+            match c {
+                ' ' | '@' | '2' => '\0',
+                'a'..='z' | 'A'..='Z' | '[' | '\\' | ']' | '^' | '_' => {
+                    ((c as u8).to_ascii_uppercase() & 0x1f) as char
+                }
+                '?' => '\x7f',
+                c => c,
+            }
+        } else {
+            c
+        };
+        let with_ctrl = modifiers | Modifier::CTRL;
+        match c {
+            '\x1b' => KeyBinding::Key(Key::ESC, modifiers - Modifier::CTRL),
+            '\r' | '\n' => KeyBinding::Key(Key::ENTER, modifiers - Modifier::CTRL),
+            '\t' => KeyBinding::Key(Key::TAB, modifiers - Modifier::CTRL),
+            '\x7f' => KeyBinding::Key(Key::BACKSPACE, modifiers - Modifier::CTRL),
+            '\x08' => KeyBinding::Key(Key::BACKSPACE, with_ctrl),
+            '\0' => KeyBinding::Char(' ', with_ctrl),
+            '\x01'..='\x1a' => KeyBinding::Char((c as u8 + 0x60) as char, with_ctrl),
+            '\x1c'..='\x1f' => KeyBinding::Char((c as u8 + 0x40) as char, with_ctrl),
+            'A'..='Z' => KeyBinding::Char(c.to_ascii_lowercase(), modifiers | Modifier::SHIFT),
+            ' ' => KeyBinding::Char(c, modifiers),
+            c if !c.is_alphabetic() => KeyBinding::Char(c, modifiers - Modifier::SHIFT),
+            c => KeyBinding::Char(c, modifiers),
+        }
+    }
+
+    /// Creates a normalized binding from an input event, if it is a key press.
+    pub fn from_event(event: &InputEvent<'_>) -> Option<Self> {
+        match *event {
+            InputEvent::Key(key, modifiers) => Some(KeyBinding::key(key, modifiers)),
+            InputEvent::KeyChar(c, modifiers) => Some(KeyBinding::char(c, modifiers)),
+            InputEvent::Char(c) => Some(KeyBinding::char(c, Modifier::empty())),
+            InputEvent::C0(c0) => Some(KeyBinding::char(c0 as char, Modifier::empty())),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the input event is this key press.
+    pub fn matches(&self, event: &InputEvent<'_>) -> bool {
+        KeyBinding::from_event(event) == Some(*self)
+    }
+
+    pub fn modifiers(&self) -> Modifier {
+        match *self {
+            KeyBinding::Key(_, modifiers) | KeyBinding::Char(_, modifiers) => modifiers,
+        }
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = self.modifiers();
+        if !modifiers.is_empty() {
+            write!(f, "{modifiers}+")?;
+        }
+        match *self {
+            KeyBinding::Key(key, _) => write!(f, "{key}"),
+            KeyBinding::Char(' ', _) => write!(f, "space"),
+            KeyBinding::Char('+', _) => write!(f, "plus"),
+            KeyBinding::Char(c, _) => write!(f, "{c}"),
+        }
+    }
+}
+
+/// Parses a binding such as `ctrl+alt+x`, `shift+F5`, `ctrl++` or `space`.
+impl FromStr for KeyBinding {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (modifiers, key) = if let Some(modifiers) = s.strip_suffix("++") {
+            (modifiers, "+")
+        } else if s == "+" {
+            ("", "+")
+        } else {
+            s.rsplit_once('+').unwrap_or(("", s))
+        };
+        if key.is_empty() {
+            return Err(ParseKeyError::Empty);
+        }
+        let modifiers = modifiers.parse::<Modifier>()?;
+
+        let mut chars = key.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(KeyBinding::char(c, modifiers));
+        }
+        if key.eq_ignore_ascii_case("space") {
+            return Ok(KeyBinding::char(' ', modifiers));
+        }
+        if key.eq_ignore_ascii_case("plus") {
+            return Ok(KeyBinding::char('+', modifiers));
+        }
+        Ok(KeyBinding::key(key.parse()?, modifiers))
+    }
+}
+
+/// A sequence of one or more key bindings that must be pressed in order (eg:
+/// `ctrl+x ctrl+s`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Chord(pub Vec<KeyBinding>);
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, binding) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{binding}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Chord {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bindings = s
+            .split_whitespace()
+            .map(KeyBinding::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        if bindings.is_empty() {
+            return Err(ParseKeyError::Empty);
+        }
+        Ok(Chord(bindings))
+    }
+}
+
+impl From<KeyBinding> for Chord {
+    fn from(binding: KeyBinding) -> Self {
+        Chord(vec![binding])
+    }
+}
+
+/// The result of feeding an input event to a [`ChordMatcher`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChordMatch<'a, T> {
+    /// The event did not complete or extend any chord, and did not start one
+    /// either. Any pending prefix has been discarded.
+    NoMatch,
+    /// The event extended a pending prefix of at least one chord.
+    Pending,
+    /// The event completed a chord.
+    Match(&'a T),
+}
+
+/// Matches input events against a set of [`Chord`]s, tracking a pending prefix
+/// across events.
+///
+/// If a chord is also the prefix of a longer chord, the shorter chord matches
+/// as soon as it is complete.
+#[derive(Debug, Clone)]
+pub struct ChordMatcher<T> {
+    chords: HashMap<Vec<KeyBinding>, T>,
+    prefixes: HashSet<Vec<KeyBinding>>,
+    pending: Vec<KeyBinding>,
+}
+
+impl<T> Default for ChordMatcher<T> {
+    fn default() -> Self {
+        Self {
+            chords: HashMap::new(),
+            prefixes: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<T> ChordMatcher<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a chord, returning the value previously bound to it.
+    pub fn bind(&mut self, chord: impl Into<Chord>, value: T) -> Option<T> {
+        let Chord(bindings) = chord.into();
+        for i in 1..bindings.len() {
+            self.prefixes.insert(bindings[..i].to_vec());
+        }
+        self.chords.insert(bindings, value)
+    }
+
+    /// The key presses of a partially-matched chord.
+    pub fn pending(&self) -> &[KeyBinding] {
+        &self.pending
+    }
+
+    /// Discards any partially-matched chord.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Feeds an input event to the matcher. Events that are not key presses
+    /// (eg: mouse events) return [`ChordMatch::NoMatch`] without affecting the
+    /// pending prefix.
+    pub fn feed(&mut self, event: &InputEvent<'_>) -> ChordMatch<'_, T> {
+        let Some(binding) = KeyBinding::from_event(event) else {
+            return ChordMatch::NoMatch;
+        };
+        self.pending.push(binding);
+        if self.pending.len() > 1
+            && !self.chords.contains_key(&self.pending)
+            && !self.prefixes.contains(&self.pending)
+        {
+            // The key broke the pending chord, but may start another one
+            self.pending.clear();
+            self.pending.push(binding);
+        }
+        if let Some(value) = self.chords.get(&self.pending) {
+            self.pending.clear();
+            ChordMatch::Match(value)
+        } else if self.prefixes.contains(&self.pending) {
+            ChordMatch::Pending
+        } else {
+            self.pending.clear();
+            ChordMatch::NoMatch
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display() {
+        for (input, display) in [
+            ("ctrl+alt+x", "ctrl+alt+x"),
+            ("shift+F5", "shift+f5"),
+            ("alt+enter", "alt+enter"),
+            ("Control+Page-Up", "ctrl+page_up"),
+            ("ctrl++", "ctrl+plus"),
+            ("ctrl+space", "ctrl+space"),
+            ("X", "shift+x"),
+            ("shift+!", "!"),
+            ("ctrl+[", "esc"),
+            ("ctrl+i", "tab"),
+            ("ctrl+h", "ctrl+backspace"),
+        ] {
+            let binding = input.parse::<KeyBinding>().unwrap();
+            assert_eq!(binding.to_string(), display, "{input}");
+            assert_eq!(display.parse::<KeyBinding>().unwrap(), binding, "{input}");
+        }
+
+        assert_eq!(
+            "hyper+x".parse::<KeyBinding>(),
+            Err(ParseKeyError::UnknownModifier("hyper".to_owned()))
+        );
+        assert_eq!(
+            "ctrl+nope".parse::<KeyBinding>(),
+            Err(ParseKeyError::UnknownKey("nope".to_owned()))
+        );
+        assert_eq!("ctrl+".parse::<KeyBinding>(), Err(ParseKeyError::Empty));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let ctrl_a = "ctrl+a".parse::<KeyBinding>().unwrap();
+        assert!(ctrl_a.matches(&InputEvent::KeyChar('a', Modifier::CTRL)));
+        assert!(ctrl_a.matches(&InputEvent::C0(0x01)));
+        assert!(ctrl_a.matches(&InputEvent::KeyChar('\x01', Modifier::empty())));
+
+        let esc = "esc".parse::<KeyBinding>().unwrap();
+        assert!(esc.matches(&InputEvent::KeyChar('\x1b', Modifier::empty())));
+        assert!(esc.matches(&InputEvent::Key(Key::ESC, Modifier::empty())));
+
+        let keypad_0 = "0".parse::<KeyBinding>().unwrap();
+        assert!(keypad_0.matches(&InputEvent::KeyChar('0', Modifier::KEYPAD)));
+
+        let alt_shift_a = "alt+A".parse::<KeyBinding>().unwrap();
+        assert!(alt_shift_a.matches(&InputEvent::KeyChar('A', Modifier::ALT)));
+    }
+
+    #[test]
+    fn test_chord_matcher() {
+        let mut matcher = ChordMatcher::new();
+        matcher.bind("ctrl+x ctrl+s".parse::<Chord>().unwrap(), "save");
+        matcher.bind("ctrl+x ctrl+c".parse::<Chord>().unwrap(), "quit");
+        matcher.bind("ctrl+alt+x".parse::<KeyBinding>().unwrap(), "other");
+
+        let ctrl_x = InputEvent::KeyChar('x', Modifier::CTRL);
+        assert_eq!(matcher.feed(&ctrl_x), ChordMatch::Pending);
+        assert_eq!(matcher.pending().len(), 1);
+        assert_eq!(
+            matcher.feed(&InputEvent::C0(0x13)),
+            ChordMatch::Match(&"save")
+        );
+        assert!(matcher.pending().is_empty());
+
+        assert_eq!(matcher.feed(&ctrl_x), ChordMatch::Pending);
+        assert_eq!(
+            matcher.feed(&InputEvent::KeyChar('q', Modifier::empty())),
+            ChordMatch::NoMatch
+        );
+        assert!(matcher.pending().is_empty());
+
+        assert_eq!(
+            matcher.feed(&InputEvent::KeyChar('x', Modifier::CTRL | Modifier::ALT)),
+            ChordMatch::Match(&"other")
+        );
+        // A key that breaks a chord can complete or start another one
+        assert_eq!(matcher.feed(&ctrl_x), ChordMatch::Pending);
+        assert_eq!(
+            matcher.feed(&InputEvent::KeyChar('x', Modifier::CTRL | Modifier::ALT)),
+            ChordMatch::Match(&"other")
+        );
+        assert_eq!(matcher.feed(&ctrl_x), ChordMatch::Pending);
+        assert_eq!(matcher.feed(&ctrl_x), ChordMatch::Pending);
+        assert_eq!(matcher.pending().len(), 1);
+        assert_eq!(
+            matcher.feed(&InputEvent::C0(0x03)),
+            ChordMatch::Match(&"quit")
+        );
+    }
+}
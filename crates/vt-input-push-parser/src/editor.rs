@@ -0,0 +1,995 @@
+//! A readline-style line editor.
+//!
+//! [`LineEditor`] consumes [`InputEvent`]s (typically from a
+//! [`VTPushParserInput`](crate::VTPushParserInput)) and writes the escape
+//! sequences required to redraw the prompt. It supports the common emacs
+//! keybindings, history with incremental search, a kill ring, word motion,
+//! multi-line buffers and bracketed paste. Pasted text is inserted as-is,
+//! including tabs and newlines, but other control characters are dropped.
+//!
+//! The terminal is expected to be in raw mode, and the editor assumes that it
+//! owns the rows from the start of the prompt to the bottom of the screen.
+//!
+//! | Binding                       | Action                                   |
+//! |-------------------------------|------------------------------------------|
+//! | `ctrl+a`, `home`              | Start of line                            |
+//! | `ctrl+e`, `end`               | End of line                              |
+//! | `ctrl+b`, `left`              | Back one character                       |
+//! | `ctrl+f`, `right`             | Forward one character                    |
+//! | `alt+b`, `ctrl+left`          | Back one word                            |
+//! | `alt+f`, `ctrl+right`         | Forward one word                         |
+//! | `ctrl+p`, `up`                | Previous line, or previous history entry |
+//! | `ctrl+n`, `down`              | Next line, or next history entry         |
+//! | `alt+<`, `alt+>`              | First/last history entry                 |
+//! | `ctrl+r`, `ctrl+s`            | Reverse/forward incremental search       |
+//! | `backspace`, `ctrl+h`         | Delete the previous character            |
+//! | `ctrl+d`, `delete`            | Delete the next character (EOF if empty) |
+//! | `ctrl+k`, `ctrl+u`            | Kill to the end/start of the line        |
+//! | `alt+d`, `alt+backspace`      | Kill the next/previous word              |
+//! | `ctrl+w`                      | Kill the previous whitespace-delimited word |
+//! | `ctrl+y`, `alt+y`             | Yank, then rotate the kill ring          |
+//! | `ctrl+t`                      | Transpose characters                     |
+//! | `ctrl+l`                      | Clear the screen                         |
+//! | `alt+enter`, `shift+enter`    | Insert a newline                         |
+//! | `enter`                       | Submit the line                          |
+//! | `ctrl+c`                      | Interrupt                                |
+//! | `ctrl+g`                      | Abort an incremental search              |
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use vt_push_parser::VTPushParser;
+use vt_push_parser::event::{CSIOwned, ParamBufOwned, VTEvent, VTIntermediate, VTOwnedEvent};
+
+use crate::{InputEvent, InputReport, Key, KeyBinding, Modifier, PasteEvent};
+
+const NONE: Modifier = Modifier::empty();
+const CTRL: Modifier = Modifier::CTRL;
+const ALT: Modifier = Modifier::ALT;
+const SHIFT: Modifier = Modifier::SHIFT;
+
+/// The distance between tab stops when drawing a tab.
+const TAB_WIDTH: usize = 8;
+
+/// The result of handling an input event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineEditorAction {
+    /// The line is still being edited.
+    Continue,
+    /// The line was submitted with `enter`.
+    Submit(String),
+    /// The line was abandoned with `ctrl+c`.
+    Interrupt,
+    /// `ctrl+d` was pressed on an empty line.
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    StartOfLine,
+    EndOfLine,
+    BackwardChar,
+    ForwardChar,
+    BackwardWord,
+    ForwardWord,
+    PreviousLine,
+    NextLine,
+    FirstHistory,
+    LastHistory,
+    ReverseSearch,
+    ForwardSearch,
+    BackwardDeleteChar,
+    DeleteChar,
+    KillLine,
+    BackwardKillLine,
+    KillWord,
+    BackwardKillWord,
+    UnixWordRubout,
+    Yank,
+    YankPop,
+    TransposeChars,
+    ClearScreen,
+    InsertNewline,
+    Submit,
+    Interrupt,
+    Abort,
+}
+
+impl Command {
+    fn from_binding(binding: KeyBinding) -> Option<Self> {
+        use KeyBinding::{Char, Key as K};
+        Some(match binding {
+            Char('a', CTRL) | K(Key::HOME, NONE) => Command::StartOfLine,
+            Char('e', CTRL) | K(Key::END, NONE) => Command::EndOfLine,
+            Char('b', CTRL) | K(Key::LEFT, NONE) => Command::BackwardChar,
+            Char('f', CTRL) | K(Key::RIGHT, NONE) => Command::ForwardChar,
+            Char('b', ALT) | K(Key::LEFT, CTRL | ALT) => Command::BackwardWord,
+            Char('f', ALT) | K(Key::RIGHT, CTRL | ALT) => Command::ForwardWord,
+            Char('p', CTRL) | K(Key::UP, NONE) => Command::PreviousLine,
+            Char('n', CTRL) | K(Key::DOWN, NONE) => Command::NextLine,
+            Char('<', ALT) => Command::FirstHistory,
+            Char('>', ALT) => Command::LastHistory,
+            Char('r', CTRL) => Command::ReverseSearch,
+            Char('s', CTRL) => Command::ForwardSearch,
+            K(Key::BACKSPACE, NONE | CTRL) => Command::BackwardDeleteChar,
+            Char('d', CTRL) | K(Key::DELETE, NONE) => Command::DeleteChar,
+            Char('k', CTRL) => Command::KillLine,
+            Char('u', CTRL) => Command::BackwardKillLine,
+            Char('d', ALT) | K(Key::DELETE, CTRL) => Command::KillWord,
+            K(Key::BACKSPACE, ALT) => Command::BackwardKillWord,
+            Char('w', CTRL) => Command::UnixWordRubout,
+            Char('y', CTRL) => Command::Yank,
+            Char('y', ALT) => Command::YankPop,
+            Char('t', CTRL) => Command::TransposeChars,
+            Char('l', CTRL) => Command::ClearScreen,
+            K(Key::ENTER, ALT | SHIFT) => Command::InsertNewline,
+            K(Key::ENTER, NONE) => Command::Submit,
+            Char('c', CTRL) => Command::Interrupt,
+            Char('g', CTRL) => Command::Abort,
+            _ => return None,
+        })
+    }
+
+    fn is_kill(self) -> bool {
+        matches!(
+            self,
+            Command::KillLine
+                | Command::BackwardKillLine
+                | Command::KillWord
+                | Command::BackwardKillWord
+                | Command::UnixWordRubout
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Search {
+    query: String,
+    backward: bool,
+    /// The history entry currently matched.
+    index: Option<usize>,
+    failed: bool,
+    /// The buffer and cursor before the search started.
+    original: (String, usize),
+}
+
+/// A readline-style line editor.
+///
+/// See the [module documentation](self) for the supported keybindings.
+#[derive(Debug)]
+pub struct LineEditor {
+    prompt: String,
+    width: usize,
+    buffer: String,
+    /// Byte offset into `buffer`, always on a grapheme boundary.
+    cursor: usize,
+
+    history: Vec<String>,
+    history_limit: usize,
+    /// The history entry being edited, if any.
+    history_index: Option<usize>,
+    /// The line being edited before moving into history.
+    history_saved: String,
+    search: Option<Search>,
+
+    kill_ring: VecDeque<String>,
+    kill_ring_limit: usize,
+    /// The range and kill ring index of the most recent yank.
+    yank: Option<(Range<usize>, usize)>,
+    last_command: Option<Command>,
+
+    /// Bytes of an incomplete UTF-8 character from a paste.
+    paste_pending: Vec<u8>,
+    pasting: bool,
+    /// The row of the terminal cursor, relative to the start of the prompt.
+    cursor_row: usize,
+}
+
+impl LineEditor {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            width: 80,
+            buffer: String::new(),
+            cursor: 0,
+            history: Vec::new(),
+            history_limit: 1000,
+            history_index: None,
+            history_saved: String::new(),
+            search: None,
+            kill_ring: VecDeque::new(),
+            kill_ring_limit: 16,
+            yank: None,
+            last_command: None,
+            paste_pending: Vec::new(),
+            pasting: false,
+            cursor_row: 0,
+        }
+    }
+
+    /// Sets the prompt, which may contain escape sequences (eg: colors).
+    pub fn set_prompt(&mut self, prompt: impl Into<String>) {
+        self.prompt = prompt.into();
+    }
+
+    /// Sets the width of the terminal in columns. This is updated automatically
+    /// from in-band resize reports.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width.max(1);
+    }
+
+    /// Sets the maximum number of history entries to keep.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// The cursor position, as a byte offset into the buffer.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Adds an entry to the history. Empty entries and repeats of the most
+    /// recent entry are ignored.
+    pub fn add_history(&mut self, entry: impl Into<String>) {
+        let entry = entry.into();
+        if entry.is_empty() || self.history.last() == Some(&entry) {
+            return;
+        }
+        self.history.push(entry);
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        if self.history.len() > self.history_limit {
+            self.history
+                .drain(..self.history.len() - self.history_limit);
+        }
+    }
+
+    /// Handles an input event, writing any output required to redraw the
+    /// prompt to `out`.
+    ///
+    /// After a line is submitted, interrupted or ends with EOF, the cursor is
+    /// left at the start of the next row and [`LineEditor::render`] must be
+    /// called to draw a fresh prompt.
+    pub fn handle_event(&mut self, event: &InputEvent<'_>, out: &mut Vec<u8>) -> LineEditorAction {
+        match *event {
+            InputEvent::Paste(ref kind, data) => {
+                self.paste(kind, data);
+                if !self.pasting {
+                    self.render(out);
+                }
+                return LineEditorAction::Continue;
+            }
+            InputEvent::Report(InputReport::InBandResize(_, width, _, _)) => {
+                self.set_width(width as usize);
+                self.render(out);
+                return LineEditorAction::Continue;
+            }
+            _ => {}
+        }
+
+        if self.search.is_some() && self.handle_search_event(event, out) {
+            return LineEditorAction::Continue;
+        }
+
+        if let Some(c) = insertable_char(event) {
+            let mut buf = [0; 4];
+            self.insert(c.encode_utf8(&mut buf));
+            self.last_command = None;
+            self.render(out);
+            return LineEditorAction::Continue;
+        }
+
+        let Some(command) = KeyBinding::from_event(event).and_then(Command::from_binding) else {
+            return LineEditorAction::Continue;
+        };
+        let action = self.execute(command, out);
+        self.last_command = Some(command);
+        action
+    }
+
+    /// Redraws the prompt and buffer, leaving the terminal cursor at the
+    /// editor's cursor.
+    pub fn render(&mut self, out: &mut Vec<u8>) {
+        self.render_with_cursor(out, self.cursor);
+    }
+
+    fn render_with_cursor(&mut self, out: &mut Vec<u8>, cursor: usize) {
+        // Move to the start of the prompt and clear everything below it
+        if self.cursor_row > 0 {
+            csi(out, Some(self.cursor_row), b'A');
+        }
+        out.push(b'\r');
+        csi(out, None, b'J');
+
+        let search_prompt;
+        let prompt = match &self.search {
+            Some(search) => {
+                search_prompt = format!(
+                    "({}{}i-search)`{}': ",
+                    if search.failed { "failed " } else { "" },
+                    if search.backward { "reverse-" } else { "" },
+                    search.query
+                );
+                &search_prompt
+            }
+            None => &self.prompt,
+        };
+        out.extend_from_slice(prompt.as_bytes());
+
+        let prompt_width = display_width(prompt);
+        let (mut row, mut col) = (prompt_width / self.width, prompt_width % self.width);
+        let mut cursor_position = None;
+        for (index, grapheme) in self.buffer.grapheme_indices(true) {
+            if index == cursor {
+                cursor_position = Some((row, col));
+            }
+            if grapheme == "\n" {
+                out.extend_from_slice(b"\r\n");
+                (row, col) = (row + 1, 0);
+                continue;
+            }
+            // Wrap explicitly so that the terminal's idea of the cursor position
+            // always matches ours. For the same reason, tabs are drawn as spaces
+            // up to the next tab stop.
+            if grapheme == "\t" {
+                let width = (TAB_WIDTH - col % TAB_WIDTH).min(self.width - col);
+                out.extend(std::iter::repeat_n(b' ', width));
+                col += width;
+            } else {
+                let width = grapheme.width();
+                if col + width > self.width {
+                    out.extend_from_slice(b"\r\n");
+                    (row, col) = (row + 1, 0);
+                }
+                out.extend_from_slice(grapheme.as_bytes());
+                col += width;
+            }
+            if col >= self.width {
+                out.extend_from_slice(b"\r\n");
+                (row, col) = (row + 1, 0);
+            }
+        }
+        let (cursor_row, cursor_col) = cursor_position.unwrap_or((row, col));
+
+        if (row, col) != (cursor_row, cursor_col) {
+            if row > cursor_row {
+                csi(out, Some(row - cursor_row), b'A');
+            }
+            out.push(b'\r');
+            if cursor_col > 0 {
+                csi(out, Some(cursor_col), b'C');
+            }
+        }
+        self.cursor_row = cursor_row;
+    }
+
+    /// Moves the terminal cursor past the end of the buffer and resets the
+    /// editor for the next line.
+    fn finish(&mut self, out: &mut Vec<u8>) -> String {
+        self.search = None;
+        self.render_with_cursor(out, self.buffer.len());
+        out.extend_from_slice(b"\r\n");
+        self.cursor_row = 0;
+        self.cursor = 0;
+        self.history_index = None;
+        self.history_saved.clear();
+        self.yank = None;
+        std::mem::take(&mut self.buffer)
+    }
+
+    fn execute(&mut self, command: Command, out: &mut Vec<u8>) -> LineEditorAction {
+        match command {
+            Command::StartOfLine => self.cursor = self.line_start(self.cursor),
+            Command::EndOfLine => self.cursor = self.line_end(self.cursor),
+            Command::BackwardChar => self.cursor = self.prev_grapheme(self.cursor),
+            Command::ForwardChar => self.cursor = self.next_grapheme(self.cursor),
+            Command::BackwardWord => self.cursor = self.prev_word(self.cursor),
+            Command::ForwardWord => self.cursor = self.next_word(self.cursor),
+            Command::PreviousLine => {
+                if let Some(cursor) = self.vertical(self.cursor, false) {
+                    self.cursor = cursor;
+                } else if let Some(index) = self
+                    .history_index
+                    .unwrap_or(self.history.len())
+                    .checked_sub(1)
+                {
+                    self.load_history(index);
+                }
+            }
+            Command::NextLine => {
+                if let Some(cursor) = self.vertical(self.cursor, true) {
+                    self.cursor = cursor;
+                } else if let Some(index) = self.history_index {
+                    self.load_history(index + 1);
+                }
+            }
+            Command::FirstHistory => {
+                if !self.history.is_empty() {
+                    self.load_history(0);
+                }
+            }
+            Command::LastHistory => self.load_history(self.history.len()),
+            Command::ReverseSearch | Command::ForwardSearch => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    backward: command == Command::ReverseSearch,
+                    index: None,
+                    failed: false,
+                    original: (self.buffer.clone(), self.cursor),
+                });
+            }
+            Command::BackwardDeleteChar => {
+                let start = self.prev_grapheme(self.cursor);
+                self.buffer.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Command::DeleteChar => {
+                if self.buffer.is_empty() {
+                    self.finish(out);
+                    return LineEditorAction::Eof;
+                }
+                let end = self.next_grapheme(self.cursor);
+                self.buffer.drain(self.cursor..end);
+            }
+            Command::KillLine => {
+                // At the end of a line, kill the newline instead
+                let mut end = self.line_end(self.cursor);
+                if end == self.cursor {
+                    end = self.next_grapheme(end);
+                }
+                self.kill(self.cursor..end, false);
+            }
+            Command::BackwardKillLine => {
+                self.kill(self.line_start(self.cursor)..self.cursor, true);
+            }
+            Command::KillWord => self.kill(self.cursor..self.next_word(self.cursor), false),
+            Command::BackwardKillWord => self.kill(self.prev_word(self.cursor)..self.cursor, true),
+            Command::UnixWordRubout => {
+                let before = &self.buffer[..self.cursor];
+                let trimmed = before.trim_end_matches(char::is_whitespace);
+                let start = trimmed
+                    .rfind(char::is_whitespace)
+                    .map(|i| i + trimmed[i..].chars().next().unwrap().len_utf8())
+                    .unwrap_or(0);
+                self.kill(start..self.cursor, true);
+            }
+            Command::Yank => {
+                if let Some(text) = self.kill_ring.front().cloned() {
+                    let start = self.cursor;
+                    self.insert(&text);
+                    self.yank = Some((start..self.cursor, 0));
+                }
+            }
+            Command::YankPop => {
+                let yanked = matches!(self.last_command, Some(Command::Yank | Command::YankPop));
+                if let (true, Some((range, index))) = (yanked, self.yank.take()) {
+                    let index = (index + 1) % self.kill_ring.len();
+                    let text = self.kill_ring[index].clone();
+                    self.buffer.replace_range(range.clone(), &text);
+                    self.cursor = range.start + text.len();
+                    self.yank = Some((range.start..self.cursor, index));
+                } else {
+                    // Nothing to rotate, so leave the last command as-is
+                    return LineEditorAction::Continue;
+                }
+            }
+            Command::TransposeChars => {
+                // At the end of the line, transpose the two previous characters
+                let mut cursor = self.cursor;
+                if cursor == self.line_end(cursor) {
+                    cursor = self.prev_grapheme(cursor);
+                }
+                let start = self.prev_grapheme(cursor);
+                let end = self.next_grapheme(cursor);
+                if start < cursor && cursor < end {
+                    let swapped = format!(
+                        "{}{}",
+                        &self.buffer[cursor..end],
+                        &self.buffer[start..cursor]
+                    );
+                    if !swapped.contains('\n') {
+                        self.buffer.replace_range(start..end, &swapped);
+                        self.cursor = end;
+                    }
+                }
+            }
+            Command::ClearScreen => {
+                csi(out, Some(2), b'J');
+                csi(out, None, b'H');
+                self.cursor_row = 0;
+            }
+            Command::InsertNewline => self.insert("\n"),
+            Command::Submit => {
+                let line = self.finish(out);
+                self.add_history(line.clone());
+                return LineEditorAction::Submit(line);
+            }
+            Command::Interrupt => {
+                self.finish(out);
+                return LineEditorAction::Interrupt;
+            }
+            Command::Abort => {}
+        }
+        self.render(out);
+        LineEditorAction::Continue
+    }
+
+    /// Handles an event during an incremental search, returning false if the
+    /// search was accepted and the event should be handled normally.
+    fn handle_search_event(&mut self, event: &InputEvent<'_>, out: &mut Vec<u8>) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let mut from = search.index;
+        if let Some(c) = insertable_char(event) {
+            search.query.push(c);
+        } else {
+            match KeyBinding::from_event(event) {
+                Some(KeyBinding::Char('r', CTRL)) => {
+                    search.backward = true;
+                    from = search.index.and_then(|i| i.checked_sub(1));
+                }
+                Some(KeyBinding::Char('s', CTRL)) => {
+                    search.backward = false;
+                    from = search.index.map(|i| i + 1);
+                }
+                Some(KeyBinding::Key(Key::BACKSPACE, _)) => {
+                    search.query.pop();
+                    from = None;
+                }
+                Some(KeyBinding::Char('g', CTRL)) => {
+                    let (buffer, cursor) = std::mem::take(&mut search.original);
+                    self.search = None;
+                    self.buffer = buffer;
+                    self.cursor = cursor;
+                    self.render(out);
+                    return true;
+                }
+                Some(KeyBinding::Key(Key::ESC, NONE)) => {
+                    self.search = None;
+                    self.render(out);
+                    return true;
+                }
+                _ => {
+                    // Any other key accepts the search and is handled normally
+                    self.search = None;
+                    return false;
+                }
+            }
+        }
+
+        let found = find_history(&self.history, &search.query, from, search.backward);
+        search.failed = found.is_none();
+        if let Some((index, offset)) = found {
+            search.index = Some(index);
+            self.buffer = self.history[index].clone();
+            self.cursor = offset;
+            self.history_index = None;
+        }
+        self.render(out);
+        true
+    }
+
+    fn load_history(&mut self, index: usize) {
+        if self.history_index.is_none() {
+            if index >= self.history.len() {
+                return;
+            }
+            self.history_saved = self.buffer.clone();
+        }
+        if index >= self.history.len() {
+            self.history_index = None;
+            self.buffer = std::mem::take(&mut self.history_saved);
+        } else {
+            self.history_index = Some(index);
+            self.buffer = self.history[index].clone();
+        }
+        self.cursor = self.buffer.len();
+    }
+
+    fn paste(&mut self, kind: &PasteEvent, data: &[u8]) {
+        match kind {
+            PasteEvent::Start => {
+                self.pasting = true;
+                self.paste_pending.clear();
+            }
+            PasteEvent::End => {
+                self.pasting = false;
+                // Any incomplete character left over is invalid
+                if !self.paste_pending.is_empty() {
+                    self.paste_pending.clear();
+                    self.insert("\u{FFFD}");
+                }
+            }
+            PasteEvent::Continue => {}
+        }
+        if data.is_empty() {
+            return;
+        }
+
+        self.paste_pending.extend_from_slice(data);
+        let pending = std::mem::take(&mut self.paste_pending);
+        let mut text = String::new();
+        let mut rest = &pending[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(error) => {
+                    let (valid, after) = rest.split_at(error.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap());
+                    match error.error_len() {
+                        Some(len) => {
+                            text.push('\u{FFFD}');
+                            rest = &after[len..];
+                        }
+                        // An incomplete character that may be completed by the
+                        // next chunk
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.paste_pending.extend_from_slice(rest);
+
+        // Control characters are never executed. Tabs and newlines are kept,
+        // and other controls are dropped.
+        let mut sanitized = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' => {
+                    chars.next_if_eq(&'\n');
+                    sanitized.push('\n');
+                }
+                '\n' => sanitized.push('\n'),
+                '\t' => sanitized.push('\t'),
+                c if c.is_control() => {}
+                c => sanitized.push(c),
+            }
+        }
+        self.insert(&sanitized);
+        self.last_command = None;
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.buffer.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    fn kill(&mut self, range: Range<usize>, backward: bool) {
+        let text = self.buffer[range.clone()].to_owned();
+        self.buffer.replace_range(range.clone(), "");
+        self.cursor = range.start;
+        if text.is_empty() {
+            return;
+        }
+        // Consecutive kills are accumulated into a single entry
+        match self.kill_ring.front_mut() {
+            Some(front) if self.last_command.is_some_and(Command::is_kill) => {
+                if backward {
+                    front.insert_str(0, &text);
+                } else {
+                    front.push_str(&text);
+                }
+            }
+            _ => {
+                self.kill_ring.push_front(text);
+                self.kill_ring.truncate(self.kill_ring_limit);
+            }
+        }
+    }
+
+    fn prev_grapheme(&self, offset: usize) -> usize {
+        self.buffer[..offset]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    fn next_grapheme(&self, offset: usize) -> usize {
+        self.buffer[offset..]
+            .graphemes(true)
+            .next()
+            .map(|g| offset + g.len())
+            .unwrap_or(offset)
+    }
+
+    fn prev_word(&self, offset: usize) -> usize {
+        let mut graphemes = self.buffer[..offset]
+            .grapheme_indices(true)
+            .rev()
+            .peekable();
+        while graphemes.next_if(|(_, g)| !is_word(g)).is_some() {}
+        let mut start = graphemes.peek().map(|(i, _)| *i).unwrap_or(0);
+        while let Some((i, _)) = graphemes.next_if(|(_, g)| is_word(g)) {
+            start = i;
+        }
+        start
+    }
+
+    fn next_word(&self, offset: usize) -> usize {
+        let mut graphemes = self.buffer[offset..].grapheme_indices(true).peekable();
+        while graphemes.next_if(|(_, g)| !is_word(g)).is_some() {}
+        while graphemes.next_if(|(_, g)| is_word(g)).is_some() {}
+        graphemes
+            .peek()
+            .map(|(i, _)| offset + i)
+            .unwrap_or(self.buffer.len())
+    }
+
+    fn line_start(&self, offset: usize) -> usize {
+        self.buffer[..offset]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0)
+    }
+
+    fn line_end(&self, offset: usize) -> usize {
+        self.buffer[offset..]
+            .find('\n')
+            .map(|i| offset + i)
+            .unwrap_or(self.buffer.len())
+    }
+
+    /// Moves to the same column on the next or previous line of a multi-line
+    /// buffer, if there is one.
+    fn vertical(&self, offset: usize, down: bool) -> Option<usize> {
+        let start = self.line_start(offset);
+        let column = self.buffer[start..offset].width();
+        let target = if down {
+            let end = self.line_end(offset);
+            if end == self.buffer.len() {
+                return None;
+            }
+            end + 1
+        } else {
+            if start == 0 {
+                return None;
+            }
+            self.line_start(start - 1)
+        };
+        let end = self.line_end(target);
+        let mut width = 0;
+        for (i, grapheme) in self.buffer[target..end].grapheme_indices(true) {
+            width += grapheme.width();
+            if width > column {
+                return Some(target + i);
+            }
+        }
+        Some(end)
+    }
+}
+
+/// Returns the character to insert for a plain (or shifted) key press.
+fn insertable_char(event: &InputEvent<'_>) -> Option<char> {
+    let (InputEvent::Char(c) | InputEvent::KeyChar(c, _)) = *event else {
+        return None;
+    };
+    if let InputEvent::KeyChar(_, modifiers) = *event
+        && modifiers.intersects(CTRL | ALT)
+    {
+        return None;
+    }
+    (!c.is_control()).then_some(c)
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+/// Finds the next history entry containing `query`, starting from `from`
+/// (inclusive), returning the entry index and the byte offset of the match.
+fn find_history(
+    history: &[String],
+    query: &str,
+    from: Option<usize>,
+    backward: bool,
+) -> Option<(usize, usize)> {
+    let find = |index: usize| history[index].find(query).map(|offset| (index, offset));
+    if backward {
+        let from = from.unwrap_or(history.len().saturating_sub(1));
+        (0..=from.min(history.len().checked_sub(1)?))
+            .rev()
+            .find_map(find)
+    } else {
+        (from.unwrap_or(0)..history.len()).find_map(find)
+    }
+}
+
+/// The display width of text, ignoring any escape sequences.
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    VTPushParser::new().feed_with(text.as_bytes(), |event: VTEvent<'_>| {
+        if let VTEvent::Raw(raw) = event {
+            width += String::from_utf8_lossy(raw).width();
+        }
+    });
+    width
+}
+
+/// Writes a CSI sequence with an optional numeric parameter.
+fn csi(out: &mut Vec<u8>, param: Option<usize>, final_byte: u8) {
+    let param = param.map(|p| p.to_string());
+    let params = param.iter().map(|p| p.as_bytes()).collect::<Vec<_>>();
+    let event = VTOwnedEvent::Csi(CSIOwned {
+        private: None,
+        params: ParamBufOwned::new(&params),
+        intermediates: VTIntermediate::empty(),
+        final_byte,
+    });
+    // Writing to a Vec can't fail
+    _ = event.borrow().write_to(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(editor: &mut LineEditor, bytes: &[u8]) -> Vec<LineEditorAction> {
+        let mut input = crate::VTPushParserInput::new();
+        let mut actions = vec![];
+        let mut out = vec![];
+        input.feed_with(bytes, |event| {
+            let action = editor.handle_event(&event, &mut out);
+            if action != LineEditorAction::Continue {
+                actions.push(action);
+            }
+        });
+        actions
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = LineEditor::new("> ");
+        // Type, move back a word, kill it, then yank it at the start
+        feed(&mut editor, b"hello world\x1bb\x0b\x01\x19");
+        assert_eq!(editor.buffer(), "worldhello ");
+        assert_eq!(editor.cursor(), 5);
+
+        // Transpose at the end of the line
+        feed(&mut editor, b"\x05\x14");
+        assert_eq!(editor.buffer(), "worldhell o");
+
+        // Consecutive kills accumulate, and yank-pop rotates
+        feed(&mut editor, b"\x01\x0b");
+        assert_eq!(editor.buffer(), "");
+        feed(&mut editor, b"abc def\x17\x17\x19");
+        assert_eq!(editor.buffer(), "abc def");
+        feed(&mut editor, b"\x1by");
+        assert_eq!(editor.buffer(), "worldhell o");
+        feed(&mut editor, b"\x1by");
+        assert_eq!(editor.buffer(), "world");
+        feed(&mut editor, b"\x1by");
+        assert_eq!(editor.buffer(), "abc def");
+    }
+
+    #[test]
+    fn test_graphemes() {
+        let mut editor = LineEditor::new("> ");
+        // e + combining acute, then a family emoji (ZWJ sequence)
+        feed(&mut editor, "e\u{301}\u{1F468}\u{200D}\u{1F469}".as_bytes());
+        feed(&mut editor, b"\x02");
+        assert_eq!(editor.cursor(), 3);
+        feed(&mut editor, b"\x02");
+        assert_eq!(editor.cursor(), 0);
+        feed(&mut editor, b"\x04");
+        assert_eq!(editor.buffer(), "\u{1F468}\u{200D}\u{1F469}");
+    }
+
+    #[test]
+    fn test_submit_and_history() {
+        let mut editor = LineEditor::new("> ");
+        assert_eq!(
+            feed(&mut editor, b"one\rtwo\rthree\r"),
+            vec![
+                LineEditorAction::Submit("one".to_owned()),
+                LineEditorAction::Submit("two".to_owned()),
+                LineEditorAction::Submit("three".to_owned()),
+            ]
+        );
+        assert_eq!(editor.history(), ["one", "two", "three"]);
+
+        // Up twice, then down restores the line being edited
+        feed(&mut editor, b"x\x10\x10");
+        assert_eq!(editor.buffer(), "two");
+        feed(&mut editor, b"\x0e\x0e");
+        assert_eq!(editor.buffer(), "x");
+
+        // Reverse search, then search again for an older match
+        feed(&mut editor, b"\x12o");
+        assert_eq!(editor.buffer(), "two");
+        feed(&mut editor, b"\x12");
+        assert_eq!(editor.buffer(), "one");
+        // Abort restores the original line
+        feed(&mut editor, b"\x07");
+        assert_eq!(editor.buffer(), "x");
+        // Accepting a search with enter submits it
+        assert_eq!(
+            feed(&mut editor, b"\x12thr\r"),
+            vec![LineEditorAction::Submit("three".to_owned())]
+        );
+
+        assert_eq!(feed(&mut editor, b"\x04"), vec![LineEditorAction::Eof]);
+        assert_eq!(
+            feed(&mut editor, b"abc\x03"),
+            vec![LineEditorAction::Interrupt]
+        );
+        assert_eq!(editor.buffer(), "");
+    }
+
+    #[test]
+    fn test_multiline() {
+        let mut editor = LineEditor::new("> ");
+        feed(&mut editor, b"first line\x1b\rsecond");
+        assert_eq!(editor.buffer(), "first line\nsecond");
+        // Up moves within the buffer rather than to history
+        feed(&mut editor, b"\x10");
+        assert_eq!(editor.cursor(), 6);
+        feed(&mut editor, b"\x0b\x0b");
+        assert_eq!(editor.buffer(), "first second");
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut editor = LineEditor::new("> ");
+        // Control characters in a paste are never executed
+        let actions = feed(
+            &mut editor,
+            "\x1b[200~a\x03b\r\nc\u{e9}\td\x1b[201~".as_bytes(),
+        );
+        assert!(actions.is_empty());
+        assert_eq!(editor.buffer(), "ab\nc\u{e9}\td");
+        // Tabs are drawn as spaces to the next tab stop
+        let mut out = vec![];
+        editor.render(&mut out);
+        assert!(out.ends_with(b"c\xc3\xa9      d"), "{out:?}");
+
+        // UTF-8 split across paste chunks
+        let mut editor = LineEditor::new("> ");
+        let mut out = vec![];
+        for event in [
+            InputEvent::Paste(PasteEvent::Start, &[]),
+            InputEvent::Paste(PasteEvent::Continue, &[0xc3]),
+            InputEvent::Paste(PasteEvent::Continue, &[0xa9]),
+            InputEvent::Paste(PasteEvent::End, &[]),
+        ] {
+            editor.handle_event(&event, &mut out);
+        }
+        assert_eq!(editor.buffer(), "\u{e9}");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut editor = LineEditor::new("> ");
+        editor.set_width(10);
+        let mut out = vec![];
+        editor.render(&mut out);
+        assert_eq!(out, b"\r\x1b[J> ");
+
+        // Wraps at the width, then moves back to the cursor
+        feed(&mut editor, b"abcdefghij\x01");
+        let mut out = vec![];
+        editor.render(&mut out);
+        assert_eq!(out, b"\r\x1b[J> abcdefgh\r\nij\x1b[1A\r\x1b[2C");
+    }
+}
@@ -0,0 +1,150 @@
+//! User-defined key sequences.
+//!
+//! Terminals and multiplexers commonly allow users to bind arbitrary byte
+//! sequences to keys (eg: iTerm2's "send escape sequence" or tmux's
+//! `user-keys`). A [`KeyMap`] allows these to be registered at runtime.
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::InputEvent;
+
+/// A set of user-defined byte sequences, each mapped to an [`InputEvent`].
+///
+/// Registered sequences are matched alongside the built-in key sequences using
+/// the same longest-match rules: if a registered sequence may be extended by
+/// further input, the parser waits for more data (even across `feed_with`
+/// calls) before deciding. When a registered sequence and a built-in sequence
+/// match the same number of bytes, the registered sequence wins.
+#[derive(Debug, Clone, Default)]
+pub struct KeyMap {
+    sequences: BTreeMap<Vec<u8>, InputEvent<'static>>,
+    max_len: usize,
+}
+
+/// The result of matching input against a [`KeyMap`].
+#[derive(Debug)]
+pub(crate) enum KeyMapMatch<'a> {
+    NoMatch,
+    /// The input is a prefix of a longer registered sequence.
+    PendingMatch,
+    Match {
+        length: usize,
+        event: &'a InputEvent<'static>,
+    },
+}
+
+impl KeyMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a sequence, returning the event previously registered for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sequence is empty.
+    pub fn insert(
+        &mut self,
+        sequence: impl Into<Vec<u8>>,
+        event: InputEvent<'static>,
+    ) -> Option<InputEvent<'static>> {
+        let sequence = sequence.into();
+        assert!(!sequence.is_empty(), "Key sequences must not be empty");
+        self.max_len = self.max_len.max(sequence.len());
+        self.sequences.insert(sequence, event)
+    }
+
+    /// Unregisters a sequence, returning the event registered for it.
+    pub fn remove(&mut self, sequence: &[u8]) -> Option<InputEvent<'static>> {
+        let event = self.sequences.remove(sequence)?;
+        if sequence.len() == self.max_len {
+            self.max_len = self.sequences.keys().map(Vec::len).max().unwrap_or(0);
+        }
+        Some(event)
+    }
+
+    pub fn get(&self, sequence: &[u8]) -> Option<&InputEvent<'static>> {
+        self.sequences.get(sequence)
+    }
+
+    pub fn clear(&mut self) {
+        self.sequences.clear();
+        self.max_len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// Iterates over the registered sequences in byte order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &InputEvent<'static>)> {
+        self.sequences.iter().map(|(k, v)| (k.as_slice(), v))
+    }
+
+    /// The length of the longest registered sequence.
+    pub(crate) fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub(crate) fn find(&self, bytes: &[u8]) -> KeyMapMatch<'_> {
+        if self.sequences.is_empty() {
+            return KeyMapMatch::NoMatch;
+        }
+
+        // Any longer sequence that starts with these bytes sorts directly after
+        // them.
+        if bytes.len() < self.max_len
+            && let Some((sequence, _)) = self
+                .sequences
+                .range::<[u8], _>((Bound::Excluded(bytes), Bound::Unbounded))
+                .next()
+            && sequence.starts_with(bytes)
+        {
+            return KeyMapMatch::PendingMatch;
+        }
+
+        for length in (1..=bytes.len().min(self.max_len)).rev() {
+            if let Some(event) = self.sequences.get(&bytes[..length]) {
+                return KeyMapMatch::Match { length, event };
+            }
+        }
+
+        KeyMapMatch::NoMatch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Modifier;
+
+    #[test]
+    fn test_find() {
+        let mut map = KeyMap::new();
+        map.insert(b"\x1b[99x", InputEvent::KeyChar('x', Modifier::CTRL));
+        map.insert(b"\x1b[99xy", InputEvent::KeyChar('y', Modifier::CTRL));
+
+        assert!(matches!(map.find(b"\x1b[9"), KeyMapMatch::PendingMatch));
+        assert!(matches!(map.find(b"\x1b[99x"), KeyMapMatch::PendingMatch));
+        assert!(matches!(
+            map.find(b"\x1b[99xz"),
+            KeyMapMatch::Match { length: 5, .. }
+        ));
+        assert!(matches!(
+            map.find(b"\x1b[99xyz"),
+            KeyMapMatch::Match { length: 6, .. }
+        ));
+        assert!(matches!(map.find(b"\x1b[98"), KeyMapMatch::NoMatch));
+
+        map.remove(b"\x1b[99xy");
+        assert_eq!(map.max_len(), 5);
+        assert!(matches!(
+            map.find(b"\x1b[99x"),
+            KeyMapMatch::Match { length: 5, .. }
+        ));
+    }
+}
@@ -1,6 +1,17 @@
-use vt_push_parser::event::{CSI, SS2, SS3, VTEvent};
+use vt_push_parser::event::{CSI, DCSOwned, Esc, EscInvalid, SS2, SS3, VTEvent};
 use vt_push_parser::{VTPushParser, capture};
 
+pub mod background;
+pub mod binding;
+pub mod editor;
+pub mod keymap;
+pub mod query;
+
+pub use binding::{Chord, ChordMatcher, KeyBinding};
+pub use editor::LineEditor;
+pub use keymap::KeyMap;
+pub use keys::Key;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PasteEvent {
     Start,
@@ -48,19 +59,31 @@ pub enum InputEvent<'a> {
     /// A mouse event.
     Mouse(MouseEvent),
     /// A report from the terminal.
-    // Report(InputReport),
+    Report(InputReport),
     /// A bracketed paste event.
     Paste(PasteEvent, &'a [u8]),
     /// A raw VT CSI event.
     Csi(CSI<'a>),
-    /// A raw VT OSC event.
+    /// A raw VT OSC event, with the complete payload.
     Osc(&'a [u8]),
-    /// A raw VT DCS event.
-    Dcs(&'a [u8]),
+    /// A raw VT DCS event, with its header and complete payload.
+    ///
+    /// The header identifies the reply (eg: `DCS > |` for XTVERSION or
+    /// `DCS 1 + r` for XTGETTCAP), so the payload alone can't be interpreted.
+    Dcs(&'a DCSOwned, &'a [u8]),
     /// A raw VT SS2 event.
     Ss2(SS2),
     /// A raw VT SS3 event.
     Ss3(SS3),
+    /// A raw VT ESC event that is not part of a key sequence.
+    Esc(Esc),
+    /// An invalid VT escape sequence.
+    EscInvalid(EscInvalid),
+    /// A C0 control that is not part of a key sequence (eg: one embedded in an
+    /// escape sequence).
+    C0(u8),
+    /// Bytes that were not recognized as any other event.
+    Unknown(&'a [u8]),
 }
 
 /// Common input reports.
@@ -92,13 +115,25 @@ pub enum InputReport {
     ReportScreenSizeCharacter(u16, u16),
     // OSC Ps1 ; rgb:... BEL
     ReportDynamicColor(u8, u16, u16, u16),
-    // ESC I
+    // CSI I (mode 1004)
     FocusIn,
-    // ESC O
+    // CSI O (mode 1004)
     FocusOut,
+    // CSI ? 997 ; Ps n (mode 2031)
+    ColorScheme(ColorScheme),
+    // CSI 48 ; h ; w ; ph ; pw t (mode 2048)
+    InBandResize(u16, u16, u16, u16),
 }
 
+/// The color scheme preferred by the terminal, as reported by a color scheme
+/// update.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct Modifier(pub(crate) u8);
 
@@ -117,17 +152,20 @@ bitflags::bitflags! {
 mod keys {
     use super::Modifier;
 
-    macro_rules! e {
-        ($depth:literal, $c:literal, ($($mod:ident),*)) => {
-            MatchResult::Match {
-                length: $depth,
-                what: pack_mod($c as _, (Modifier::empty() $( | Modifier::$mod )*).0),
-            }
+    macro_rules! k {
+        ($c:literal, ($($mod:ident),*)) => {
+            pack_mod($c as _, (Modifier::empty() $( | Modifier::$mod )*).0)
+        };
+        ($name:ident, ($($mod:ident),*)) => {
+            pack_mod(Key::$name as _, 0x80 | (Modifier::empty() $( | Modifier::$mod )*).0)
         };
-        ($depth:literal, $name:ident, ($($mod:ident),*)) => {
+    }
+
+    macro_rules! e {
+        ($depth:literal, $($key:tt)*) => {
             MatchResult::Match {
                 length: $depth,
-                what: pack_mod(Key::$name as _, 0x80 | (Modifier::empty() $( | Modifier::$mod )*).0),
+                what: k!($($key)*),
             }
         };
     }
@@ -172,7 +210,7 @@ mod keys {
         }
         // 110xxxxx 10xxxxxx
         let Some(char) =
-            char::from_u32((bytes[0] & 0b11111) as u32 | ((bytes[1] & 0b111111) as u32) << 6)
+            char::from_u32(((bytes[0] & 0b11111) as u32) << 6 | (bytes[1] & 0b111111) as u32)
         else {
             return MatchResult::Match {
                 length: 2 + alt as u8,
@@ -311,6 +349,106 @@ fn decode_mouse_button_byte(cb: u16) -> (MouseButton, Modifier, bool) {
     (button, modifiers, is_motion)
 }
 
+/// Decodes the xterm-style modifier parameter (1 + bitmask of shift, alt and
+/// ctrl). Other modifier bits (meta, super, etc) are ignored.
+fn decode_modifier_param(param: u16) -> Modifier {
+    Modifier::from_bits_truncate(
+        (param.saturating_sub(1) as u8) & (Modifier::SHIFT | Modifier::ALT | Modifier::CTRL).0,
+    )
+}
+
+/// The longest OSC or DCS payload that is assembled into a single event (eg:
+/// an OSC 52 clipboard reply).
+const MAX_STRING_LEN: usize = 1 << 20;
+
+/// Accumulates OSC and DCS payloads, which the VT parser streams in chunks.
+///
+/// Strings that are cancelled or grow longer than [`MAX_STRING_LEN`] are
+/// passed through as [`InputEvent::Unknown`] bytes, starting with the bytes
+/// that introduced them. The VT parser discards any data of a cancelled string
+/// that it has not yet emitted, so that data is not included.
+#[derive(Debug, Default)]
+struct StringAccumulator {
+    /// A string has been started and has not yet ended.
+    active: bool,
+    dcs: Option<DCSOwned>,
+    data: Vec<u8>,
+    /// The string is too long, and is being passed through.
+    overflow: bool,
+}
+
+impl StringAccumulator {
+    fn start(&mut self, dcs: Option<DCSOwned>) {
+        self.reset();
+        self.active = true;
+        self.dcs = dcs;
+    }
+
+    fn reset(&mut self) {
+        self.active = false;
+        self.dcs = None;
+        self.data.clear();
+        self.overflow = false;
+    }
+
+    /// Emits the bytes that introduced the string and those accumulated so far.
+    fn flush(&mut self, cb: &mut impl FnMut(InputEvent)) {
+        let mut raw = Vec::with_capacity(self.data.len() + 16);
+        _ = match &self.dcs {
+            Some(dcs) => VTEvent::DcsStart(dcs.borrow()).write_to(&mut raw),
+            None => VTEvent::OscStart { command: None }.write_to(&mut raw),
+        };
+        raw.append(&mut self.data);
+        cb(InputEvent::Unknown(&raw));
+    }
+
+    fn data(&mut self, data: &[u8], cb: &mut impl FnMut(InputEvent)) {
+        if self.overflow {
+            cb(InputEvent::Unknown(data));
+            return;
+        }
+        self.data.extend_from_slice(data);
+        if self.data.len() > MAX_STRING_LEN {
+            self.flush(cb);
+            self.overflow = true;
+        }
+    }
+
+    fn osc_end(&mut self, data: &[u8], cb: &mut impl FnMut(InputEvent)) {
+        if self.overflow || self.data.len() + data.len() > MAX_STRING_LEN {
+            self.data(data, cb);
+        } else if self.data.is_empty() {
+            cb(InputEvent::Osc(data));
+        } else {
+            self.data.extend_from_slice(data);
+            cb(InputEvent::Osc(&self.data));
+        }
+        self.reset();
+    }
+
+    fn dcs_end(&mut self, data: &[u8], cb: &mut impl FnMut(InputEvent)) {
+        if self.overflow || self.data.len() + data.len() > MAX_STRING_LEN {
+            self.data(data, cb);
+        } else if let Some(dcs) = &self.dcs {
+            // DcsStart always precedes DcsEnd
+            if self.data.is_empty() {
+                cb(InputEvent::Dcs(dcs, data));
+            } else {
+                self.data.extend_from_slice(data);
+                cb(InputEvent::Dcs(dcs, &self.data));
+            }
+        }
+        self.reset();
+    }
+
+    fn cancel(&mut self, cb: &mut impl FnMut(InputEvent)) {
+        if !self.overflow {
+            self.flush(cb);
+        }
+        self.reset();
+    }
+}
+
 enum HandleAction<'a> {
     Handled,
     Capture(CaptureState, capture::VTCaptureInternal),
@@ -323,14 +461,24 @@ enum HandleAction<'a> {
 /// protocol. In addition, most common input control sequences are automatically
 /// decoded.
 ///
-/// Unrecognized sequences are emitted as raw [`VTEvent`]s.
+/// Unrecognized sequences are emitted as raw VT events (eg:
+/// [`InputEvent::Csi`], [`InputEvent::Esc`] or [`InputEvent::Unknown`]) so that
+/// no input is silently dropped. Cancelled OSC and DCS strings are emitted as
+/// [`InputEvent::Unknown`], with the data received before the cancel. Other
+/// sequences that end without an event (eg: a CSI cancelled by CAN or SUB, or
+/// interrupted by ESC) are emitted as [`InputEvent::Unknown`], and key matching
+/// resumes right after them.
 pub struct VTPushParserInput {
-    key_buffer: [u8; keys::MAX_SEQUENCE_LEN],
-    key_buffer_len: usize,
+    /// Bytes that may form part of a key sequence, waiting for more input.
+    key_buffer: Vec<u8>,
+    /// Bytes of the sequence the VT parser is reading, outside of strings.
+    sequence: Vec<u8>,
+    keymap: KeyMap,
     data_accumulator: Vec<u8>,
 
     capture: capture::VTCaptureInternal,
     capture_state: CaptureState,
+    string: StringAccumulator,
     parser: VTPushParser,
 }
 
@@ -405,16 +553,70 @@ fn handle_vt_event<'a>(event: VTEvent<'a>, cb: &mut impl FnMut(InputEvent)) -> H
                     return HandleAction::Handled;
                 }
             }
-            (None, b'u') => match csi.params.len() {
-                1 => {}
-                2 => {}
-                _ => {}
-            },
-            (None | Some(b'>'), b'~') => match csi.params.len() {
-                2 => {}
-                3 => {}
-                _ => {}
-            },
+            // Focus in/out: CSI I / CSI O
+            (None, b'I' | b'O') if csi.params.is_empty() => {
+                cb(InputEvent::Report(if csi.final_byte == b'I' {
+                    InputReport::FocusIn
+                } else {
+                    InputReport::FocusOut
+                }));
+                return HandleAction::Handled;
+            }
+            // Color scheme update: CSI ? 997 ; Ps n
+            (Some(b'?'), b'n') if csi.params.len() == 2 => {
+                let scheme = match (
+                    csi.params.try_parse::<u16>(0),
+                    csi.params.try_parse::<u16>(1),
+                ) {
+                    (Some(997), Some(1)) => Some(ColorScheme::Dark),
+                    (Some(997), Some(2)) => Some(ColorScheme::Light),
+                    _ => None,
+                };
+                if let Some(scheme) = scheme {
+                    cb(InputEvent::Report(InputReport::ColorScheme(scheme)));
+                    return HandleAction::Handled;
+                }
+            }
+            // In-band resize: CSI 48 ; h ; w ; ph ; pw t
+            (None, b't') if csi.params.len() == 5 => {
+                if let (Some(48), Some(h), Some(w), Some(ph), Some(pw)) = (
+                    csi.params.try_parse::<u16>(0),
+                    csi.params.try_parse::<u16>(1),
+                    csi.params.try_parse::<u16>(2),
+                    csi.params.try_parse::<u16>(3),
+                    csi.params.try_parse::<u16>(4),
+                ) {
+                    cb(InputEvent::Report(InputReport::InBandResize(h, w, ph, pw)));
+                    return HandleAction::Handled;
+                }
+            }
+            // Bracketed paste: CSI 200 ~ ... CSI 201 ~
+            (None, b'~')
+                if csi.params.len() == 1 && csi.params.try_parse::<u16>(0) == Some(200) =>
+            {
+                cb(InputEvent::Paste(PasteEvent::Start, &[]));
+                return HandleAction::Capture(
+                    CaptureState::Paste,
+                    capture::VTInputCapture::Terminator(b"\x1b[201~").into(),
+                );
+            }
+            // Regular keys: CSI code [; modifier] (u|~|z)
+            (None | Some(b'>'), b'u' | b'~' | b'z') if matches!(csi.params.len(), 1 | 2) => {
+                let params = csi.params.numeric();
+                let modifiers = params
+                    .get(1)
+                    .and_then(|p| p.first())
+                    .map(decode_modifier_param)
+                    .unwrap_or(Modifier::empty());
+                if let Some(what) = params
+                    .get(0)
+                    .and_then(|p| p.first())
+                    .and_then(|code| keys::find_regular(csi.final_byte, code))
+                {
+                    handle_key_event(what | (modifiers.0 as u32) << 24, &mut *cb);
+                    return HandleAction::Handled;
+                }
+            }
             _ => {}
         }
     }
@@ -422,6 +624,32 @@ fn handle_vt_event<'a>(event: VTEvent<'a>, cb: &mut impl FnMut(InputEvent)) -> H
     HandleAction::Unhandled(event)
 }
 
+/// The result of matching input against both the built-in and user-defined
+/// key sequences.
+enum SequenceMatch<'a> {
+    Builtin(keys::MatchResult),
+    User(usize, &'a InputEvent<'static>),
+}
+
+fn find_sequence<'a>(keymap: &'a KeyMap, bytes: &[u8]) -> SequenceMatch<'a> {
+    let builtin = keys::find_sequence(bytes);
+    match keymap.find(bytes) {
+        keymap::KeyMapMatch::NoMatch => SequenceMatch::Builtin(builtin),
+        keymap::KeyMapMatch::PendingMatch => {
+            SequenceMatch::Builtin(keys::MatchResult::PendingMatch)
+        }
+        keymap::KeyMapMatch::Match { length, event } => match builtin {
+            // Longest match wins, with user-defined sequences winning ties
+            keys::MatchResult::Match {
+                length: builtin_length,
+                ..
+            } if builtin_length as usize > length => SequenceMatch::Builtin(builtin),
+            keys::MatchResult::PendingMatch => SequenceMatch::Builtin(builtin),
+            _ => SequenceMatch::User(length, event),
+        },
+    }
+}
+
 fn handle_key_event(what: u32, mut cb: impl FnMut(InputEvent)) {
     if what & 0x80000000 != 0 {
         let key = keys::Key::try_from(what as u8).unwrap();
@@ -443,20 +671,43 @@ impl Default for VTPushParserInput {
 impl VTPushParserInput {
     pub fn new() -> Self {
         Self {
-            key_buffer: [0; keys::MAX_SEQUENCE_LEN],
-            key_buffer_len: 0,
+            key_buffer: Vec::with_capacity(keys::MAX_SEQUENCE_LEN),
+            sequence: Vec::new(),
+            keymap: KeyMap::new(),
             capture: capture::VTCaptureInternal::None,
             capture_state: CaptureState::None,
+            string: StringAccumulator::default(),
             parser: VTPushParser::new(),
             data_accumulator: Vec::with_capacity(256),
         }
     }
 
-    pub fn feed_with(&mut self, mut bytes: &[u8], mut cb: impl FnMut(InputEvent)) {
+    /// The user-defined key sequences recognized by this parser.
+    pub fn keymap(&self) -> &KeyMap {
+        &self.keymap
+    }
+
+    /// Mutable access to the user-defined key sequences recognized by this
+    /// parser. Changes apply to any input that has not yet been matched.
+    pub fn keymap_mut(&mut self) -> &mut KeyMap {
+        &mut self.keymap
+    }
+
+    pub fn feed_with(&mut self, bytes: &[u8], mut cb: impl FnMut(InputEvent)) {
+        self.feed_with_inner(bytes, &mut cb);
+    }
+
+    /// Feeds the VT parser until it emits an event or returns to the ground
+    /// state, returning the number of bytes consumed.
+    ///
+    /// Outside of strings, the parser is fed a byte at a time so that a
+    /// sequence that ends without an event can be emitted as
+    /// [`InputEvent::Unknown`], and the bytes after it are matched as keys.
+    fn feed_parser<F: FnMut(InputEvent)>(&mut self, bytes: &[u8], cb: &mut F) -> usize {
         // Handle the result of handle_vt_event, setting up capture or emitting
-        // unhandled events as raw CSI.
+        // unhandled events as raw VT events.
         macro_rules! dispatch_vt {
-            ($action:expr, $cb:expr, $capture:expr, $capture_state:expr) => {
+            ($action:expr, $cb:expr, $capture:expr, $capture_state:expr, $string:expr) => {
                 match $action {
                     HandleAction::Handled => {}
                     HandleAction::Capture(state, internal) => {
@@ -464,141 +715,229 @@ impl VTPushParserInput {
                         *$capture_state = state;
                     }
                     HandleAction::Unhandled(event) => {
-                        let input_event = match event {
-                            VTEvent::Csi(csi) => Some(InputEvent::Csi(csi)),
-                            VTEvent::OscEnd { data, .. } => Some(InputEvent::Osc(data)),
-                            VTEvent::DcsEnd(data) => Some(InputEvent::Dcs(data)),
-                            VTEvent::Ss2(ss2) => Some(InputEvent::Ss2(ss2)),
-                            VTEvent::Ss3(ss3) => Some(InputEvent::Ss3(ss3)),
-                            _ => None,
-                        };
-                        if let Some(e) = input_event {
-                            $cb(e);
+                        let string: &mut StringAccumulator = $string;
+                        match event {
+                            VTEvent::Raw(data) => $cb(InputEvent::Unknown(data)),
+                            VTEvent::C0(c0) => $cb(InputEvent::C0(c0)),
+                            VTEvent::Esc(esc) => $cb(InputEvent::Esc(esc)),
+                            VTEvent::EscInvalid(esc) => $cb(InputEvent::EscInvalid(esc)),
+                            VTEvent::Csi(csi) => $cb(InputEvent::Csi(csi)),
+                            VTEvent::Ss2(ss2) => $cb(InputEvent::Ss2(ss2)),
+                            VTEvent::Ss3(ss3) => $cb(InputEvent::Ss3(ss3)),
+                            VTEvent::OscStart { .. } => string.start(None),
+                            VTEvent::DcsStart(dcs) => string.start(Some(dcs.to_owned())),
+                            VTEvent::OscData(data) | VTEvent::DcsData(data) => {
+                                string.data(data, $cb)
+                            }
+                            VTEvent::OscEnd { data, .. } => string.osc_end(data, $cb),
+                            VTEvent::DcsEnd(data) => string.dcs_end(data, $cb),
+                            VTEvent::OscCancel | VTEvent::DcsCancel => string.cancel($cb),
                         }
                     }
                 }
             };
         }
 
+        let capture = &mut self.capture;
+        let capture_state = &mut self.capture_state;
+        let string = &mut self.string;
+        if string.active {
+            return self.parser.feed_with_abortable(bytes, |event: VTEvent| {
+                let action = handle_vt_event(event, cb);
+                dispatch_vt!(action, cb, capture, capture_state, string);
+                false
+            });
+        }
+
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            let mut emitted = false;
+            let read = self
+                .parser
+                .feed_with_abortable(&bytes[i..=i], |event: VTEvent| {
+                    emitted = true;
+                    let action = handle_vt_event(event, cb);
+                    dispatch_vt!(action, cb, capture, capture_state, string);
+                    false
+                });
+            i += read;
+            if emitted {
+                // The event includes any buffered bytes. If this byte was not
+                // part of it (eg: ESC ESC), it starts the next sequence.
+                self.sequence.clear();
+                if self.parser.is_ground() || string.active {
+                    return i;
+                }
+                if read == 1 {
+                    self.sequence.push(b);
+                }
+                continue;
+            }
+
+            if self.parser.is_ground() {
+                // As with strings, a CAN or SUB is not part of the sequence it
+                // cancels. Other bytes (eg: the final byte of an ignored CSI)
+                // are.
+                if !matches!(b, 0x18 | 0x1a) {
+                    self.sequence.push(b);
+                }
+                if !self.sequence.is_empty() {
+                    cb(InputEvent::Unknown(&self.sequence));
+                }
+                self.sequence.clear();
+                return i;
+            }
+            self.sequence.push(b);
+            // An ESC that leaves the parser waiting on a new escape sequence
+            // interrupted the previous one. Reset the parser so that the ESC
+            // can be matched as part of a key.
+            if b == 0x1b && self.sequence.len() > 1 && self.parser.idle().is_some() {
+                cb(InputEvent::Unknown(
+                    &self.sequence[..self.sequence.len() - 1],
+                ));
+                self.sequence.clear();
+                return i - 1;
+            }
+            if self.sequence.len() >= MAX_STRING_LEN {
+                cb(InputEvent::Unknown(&self.sequence));
+                self.sequence.clear();
+            }
+        }
+        i
+    }
+
+    fn feed_with_inner<F: FnMut(InputEvent)>(&mut self, mut bytes: &[u8], cb: &mut F) {
         loop {
             // First, check if we have an active capture.
-            if let Some(captured) = self.capture.feed(&mut bytes) {
-                self.data_accumulator.extend_from_slice(captured);
-                match std::mem::take(&mut self.capture_state) {
-                    CaptureState::None => {}
-                    CaptureState::Paste => {
-                        cb(InputEvent::Paste(PasteEvent::End, &self.data_accumulator));
-                        self.data_accumulator.clear();
+            match self.capture_state {
+                CaptureState::None => {}
+                CaptureState::Paste => {
+                    // Paste data is streamed until the terminator has been matched
+                    let end = self.capture.feed(&mut bytes, |captured| {
+                        cb(InputEvent::Paste(PasteEvent::Continue, captured));
+                    });
+                    if end.is_none() {
+                        return;
                     }
-                    CaptureState::Mouse => {
-                        let data = &self.data_accumulator;
-                        if data.len() >= 3 {
-                            // X10 protocol: all three bytes are offset by 32
-                            let cb_byte = (data[0] as u16).saturating_sub(32);
-                            let x = (data[1] as u16).saturating_sub(33);
-                            let y = (data[2] as u16).saturating_sub(33);
-                            // In X10 protocol, button byte 3 (bits 0-1) means release
-                            let is_release = cb_byte & 0x03 == 3;
-                            let (button, modifiers, is_motion) =
-                                decode_mouse_button_byte(cb_byte);
-                            let kind = if is_release {
-                                // X10 doesn't tell us which button was released
-                                MouseEventKind::Release(MouseButton::Left)
-                            } else if is_motion {
-                                MouseEventKind::Drag(button)
-                            } else {
-                                MouseEventKind::Press(button)
-                            };
-                            cb(InputEvent::Mouse(MouseEvent {
-                                kind,
-                                x,
-                                y,
-                                modifiers,
-                            }));
-                        }
-                        self.data_accumulator.clear();
+                    self.capture_state = CaptureState::None;
+                    cb(InputEvent::Paste(PasteEvent::End, &[]));
+                    continue;
+                }
+                CaptureState::Mouse => {
+                    // Hold on to the mouse bytes until we have all of them
+                    let accumulator = &mut self.data_accumulator;
+                    let end = self.capture.feed(&mut bytes, |captured| {
+                        accumulator.extend_from_slice(captured);
+                    });
+                    if end.is_none() {
+                        return;
+                    }
+                    self.capture_state = CaptureState::None;
+                    let data = &self.data_accumulator;
+                    if data.len() >= 3 {
+                        // X10 protocol: all three bytes are offset by 32
+                        let cb_byte = (data[0] as u16).saturating_sub(32);
+                        let x = (data[1] as u16).saturating_sub(33);
+                        let y = (data[2] as u16).saturating_sub(33);
+                        // In X10 protocol, button byte 3 (bits 0-1) means release
+                        let is_release = cb_byte & 0x03 == 3;
+                        let (button, modifiers, is_motion) = decode_mouse_button_byte(cb_byte);
+                        let kind = if is_release {
+                            // X10 doesn't tell us which button was released
+                            MouseEventKind::Release(MouseButton::Left)
+                        } else if is_motion {
+                            MouseEventKind::Drag(button)
+                        } else {
+                            MouseEventKind::Press(button)
+                        };
+                        cb(InputEvent::Mouse(MouseEvent {
+                            kind,
+                            x,
+                            y,
+                            modifiers,
+                        }));
                     }
+                    self.data_accumulator.clear();
+                    continue;
                 }
-                continue;
             }
 
             // If no active capture, feed the parser if it's not in ground state, otherwise the key buffer.
             if !self.parser.is_ground() {
-                let capture = &mut self.capture;
-                let capture_state = &mut self.capture_state;
-                let read = self.parser.feed_with_abortable(bytes, |event: VTEvent| {
-                    let action = handle_vt_event(event, &mut cb);
-                    dispatch_vt!(action, cb, capture, capture_state);
-                    false
-                });
+                if bytes.is_empty() {
+                    return;
+                }
+                let read = self.feed_parser(bytes, cb);
                 bytes = &bytes[read..];
                 continue;
             }
 
             // If the key buffer has some bytes, feed more to it
-            if self.key_buffer_len > 0 {
-                let to_copy_into = &mut self.key_buffer[self.key_buffer_len
-                    ..keys::MAX_SEQUENCE_LEN.min(self.key_buffer_len + bytes.len())];
-                to_copy_into.copy_from_slice(&bytes[..to_copy_into.len()]);
-                self.key_buffer_len += to_copy_into.len();
-                bytes = &bytes[to_copy_into.len()..];
-
-                match keys::find_sequence(&self.key_buffer[..self.key_buffer_len]) {
-                    keys::MatchResult::Match { length, what } => {
-                        self.key_buffer.rotate_left(length as _);
-                        self.key_buffer_len -= length as usize;
-                        handle_key_event(what, &mut cb);
+            if !self.key_buffer.is_empty() {
+                let max_len = keys::MAX_SEQUENCE_LEN.max(self.keymap.max_len());
+                let to_copy = max_len
+                    .saturating_sub(self.key_buffer.len())
+                    .min(bytes.len());
+                self.key_buffer.extend_from_slice(&bytes[..to_copy]);
+                bytes = &bytes[to_copy..];
+
+                match find_sequence(&self.keymap, &self.key_buffer) {
+                    SequenceMatch::User(length, event) => {
+                        cb(event.clone());
+                        self.key_buffer.drain(..length);
+                    }
+                    SequenceMatch::Builtin(keys::MatchResult::Match { length, what }) => {
+                        self.key_buffer.drain(..length as usize);
+                        handle_key_event(what, &mut *cb);
                     }
-                    keys::MatchResult::NoMatch { length } => {
+                    SequenceMatch::Builtin(keys::MatchResult::NoMatch { length }) => {
                         // We don't have a match and we know that at least this many bytes
                         // won't match.
-                        self.key_buffer.rotate_left(length as _);
-                        let capture = &mut self.capture;
-                        let capture_state = &mut self.capture_state;
+                        let mut buffered = std::mem::take(&mut self.key_buffer);
                         // Feed them unconditionally to the parser
-                        self.parser.feed_with_abortable(
-                            &self.key_buffer[keys::MAX_SEQUENCE_LEN - length as usize..],
-                            |event: VTEvent<'_>| {
-                                let action = handle_vt_event(event, &mut cb);
-                                dispatch_vt!(action, cb, capture, capture_state);
-                                false
-                            },
-                        );
+                        let read = self.feed_parser(&buffered[..length as usize], cb);
+                        // The remaining buffered bytes (including any the parser didn't
+                        // consume before emitting an event) precede any new input
+                        self.feed_with_inner(&buffered[read..], cb);
+                        if self.key_buffer.is_empty() {
+                            buffered.clear();
+                            self.key_buffer = buffered;
+                        }
                     }
-                    keys::MatchResult::PendingMatch => {
+                    SequenceMatch::Builtin(keys::MatchResult::PendingMatch) => {
                         // We need more bytes to complete the match, which means we need another
-                        // feed call.
+                        // feed call. The buffer is only short of full if all input was consumed.
                         return;
                     }
                 }
+                continue;
             }
 
             // If we get this far, we're in the ground state for everything...
 
-            if self.key_buffer_len == 0 {
-                match keys::find_sequence(bytes) {
-                    keys::MatchResult::Match { length, what } => {
-                        bytes = &bytes[length as usize..];
-                        handle_key_event(what, &mut cb);
-                    }
-                    keys::MatchResult::NoMatch { length } => {
-                        // We don't have a match and we know that at least this many bytes
-                        // won't match.
-                        let capture = &mut self.capture;
-                        let capture_state = &mut self.capture_state;
-                        self.parser
-                            .feed_with_abortable(&bytes[..length as usize], |event: VTEvent<'_>| {
-                                let action = handle_vt_event(event, &mut cb);
-                                dispatch_vt!(action, cb, capture, capture_state);
-                                false
-                            });
-                        bytes = &bytes[length as usize..];
-                    }
-                    keys::MatchResult::PendingMatch => {
-                        // We need more bytes to complete the match, which means we need another
-                        // feed call.
-                        return;
-                    }
+            match find_sequence(&self.keymap, bytes) {
+                SequenceMatch::User(length, event) => {
+                    bytes = &bytes[length..];
+                    cb(event.clone());
+                }
+                SequenceMatch::Builtin(keys::MatchResult::Match { length, what }) => {
+                    bytes = &bytes[length as usize..];
+                    handle_key_event(what, &mut *cb);
+                }
+                SequenceMatch::Builtin(keys::MatchResult::NoMatch { length }) => {
+                    // We don't have a match and we know that at least this many bytes
+                    // won't match.
+                    // If the parser emits an event before consuming them all, the
+                    // remainder is processed on the next iteration.
+                    let read = self.feed_parser(&bytes[..length as usize], cb);
+                    bytes = &bytes[read..];
+                }
+                SequenceMatch::Builtin(keys::MatchResult::PendingMatch) => {
+                    // We need more bytes to complete the match, which means we need another
+                    // feed call. Hold on to what we have until then.
+                    self.key_buffer.extend_from_slice(bytes);
+                    return;
                 }
             }
         }
@@ -671,8 +1010,10 @@ mod tests {
         }
 
         // Unambiguous, same matches regardless of more data
+        #[cfg(feature = "xterm")]
         m!(b"\x1b[A", 3);
         m!(b"\x1bA", 2);
+        #[cfg(feature = "xterm")]
         m!(b"\x1bOA", 3);
         m!(b"\x1b\x01", 2);
         m!(b"\x1b\x1b[Z", 4);
@@ -681,7 +1022,9 @@ mod tests {
         // Ambiguous
         m!(b"\x1b", no, 1); // could be ESC or ESC ...
         m!(b"\x1b\x1b", no, 2); // could be ESC ESC or ESC CSI Z, for example
+        #[cfg(feature = "xterm")]
         m!(b"\x1bO", no, 2); // could be ESC O or ESC O A (SS3)
+        #[cfg(feature = "vt52")]
         m!(b"\x1b?", no, 2); // could be ESC ? or ESC ? . (VT52 keypad)
 
         // Super ambiguous escape chains
@@ -708,6 +1051,75 @@ mod tests {
 
         // Maybe re-visit
         m!(b"\x1bO\x1b", no); // disambiguation must end a string, but we might be able to do a lookahead here
+
+        // Profile-specific sequences
+        #[cfg(feature = "linux")]
+        {
+            m!(b"\x1b[[A", 4); // F1 (linux console)
+            m!(b"\x1b[[x", no); // not a key, must not stall waiting for more data
+        }
+        #[cfg(feature = "rxvt")]
+        {
+            m!(b"\x1b[a", 3); // UP(SHIFT)
+            m!(b"\x1bOa", 3); // UP(CTRL)
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "vt52"))]
+    fn test_profile_vt52_disabled() {
+        // Without VT52, ESC A is ALT + 'A' rather than F1
+        let events = collect_events(b"\x1bA");
+        assert_eq!(events, vec![InputEvent::KeyChar('A', Modifier::ALT)]);
+    }
+
+    #[test]
+    #[cfg(feature = "xterm")]
+    fn test_regular_keys() {
+        let events = collect_events(b"\x1b[3~\x1b[3;5~\x1b[15;2~");
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Key(keys::Key::DELETE, Modifier::empty()),
+                InputEvent::Key(keys::Key::DELETE, Modifier::CTRL),
+                InputEvent::Key(keys::Key::F5, Modifier::SHIFT),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "kitty")]
+    fn test_regular_keys_kitty() {
+        let events = collect_events(b"\x1b[57399u\x1b[13;3u");
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::KeyChar('0', Modifier::KEYPAD),
+                InputEvent::Key(keys::Key::ENTER, Modifier::ALT),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "sun")]
+    fn test_regular_keys_sun() {
+        let events = collect_events(b"\x1b[214z");
+        assert_eq!(
+            events,
+            vec![InputEvent::Key(keys::Key::HOME, Modifier::SUN)]
+        );
+    }
+
+    #[test]
+    fn test_utf8_two_byte() {
+        let events = collect_events("\u{e9}\x1b\u{e9}".as_bytes());
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::KeyChar('\u{e9}', Modifier::empty()),
+                InputEvent::KeyChar('\u{e9}', Modifier::ALT),
+            ]
+        );
     }
 
     #[test]
@@ -722,16 +1134,284 @@ mod tests {
     }
 
     fn collect_events(bytes: &[u8]) -> Vec<InputEvent<'static>> {
+        collect_events_split(&mut VTPushParserInput::new(), bytes, bytes.len())
+    }
+
+    /// Feeds `bytes` to the parser in chunks of `chunk_size`.
+    fn collect_events_split(
+        input_parser: &mut VTPushParserInput,
+        bytes: &[u8],
+        chunk_size: usize,
+    ) -> Vec<InputEvent<'static>> {
         let mut events = vec![];
-        let mut input_parser = VTPushParserInput::new();
-        input_parser.feed_with(bytes, |event| {
-            // Safety: we only inspect the event, and Mouse/Key events don't borrow
-            let event: InputEvent<'static> = unsafe { std::mem::transmute(event) };
-            events.push(event);
-        });
+        for chunk in bytes.chunks(chunk_size.max(1)) {
+            input_parser.feed_with(chunk, |event| {
+                // Safety: we only inspect the event, and Mouse/Key events don't borrow
+                let event: InputEvent<'static> = unsafe { std::mem::transmute(event) };
+                events.push(event);
+            });
+        }
         events
     }
 
+    #[test]
+    fn test_bracketed_paste() {
+        let bytes = b"\x1b[200~hello\x1b\x03\x1b[201\x1b[201~a";
+        for chunk_size in 1..=bytes.len() {
+            let mut input_parser = VTPushParserInput::new();
+            let mut events = vec![];
+            let mut pasted = vec![];
+            for chunk in bytes.chunks(chunk_size) {
+                input_parser.feed_with(chunk, |event| match event {
+                    InputEvent::Paste(PasteEvent::Continue, data) => pasted.extend_from_slice(data),
+                    event => events.push(format!("{event:?}")),
+                });
+            }
+            assert_eq!(pasted, b"hello\x1b\x03\x1b[201", "chunk size {chunk_size}");
+            assert_eq!(
+                events,
+                vec![
+                    "Paste(Start, [])",
+                    "Paste(End, [])",
+                    "KeyChar('a', Modifier(0))"
+                ],
+                "chunk size {chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_x10_mouse_split() {
+        for chunk_size in 1..=6 {
+            let events = collect_events_split(
+                &mut VTPushParserInput::new(),
+                b"\x1b[M\x20\x2a\x34",
+                chunk_size,
+            );
+            assert_eq!(events.len(), 1, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_reports() {
+        let events = collect_events(b"\x1b[I\x1b[O\x1b[?997;1n\x1b[?997;2n\x1b[48;24;80;480;640t");
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Report(InputReport::FocusIn),
+                InputEvent::Report(InputReport::FocusOut),
+                InputEvent::Report(InputReport::ColorScheme(ColorScheme::Dark)),
+                InputEvent::Report(InputReport::ColorScheme(ColorScheme::Light)),
+                InputEvent::Report(InputReport::InBandResize(24, 80, 480, 640)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lossless() {
+        let events = collect_events(b"\x1b=\x1b[<0;10\x01;20M");
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Esc(Esc {
+                    intermediates: Default::default(),
+                    private: None,
+                    final_byte: b'=',
+                }),
+                InputEvent::C0(0x01),
+                InputEvent::Mouse(MouseEvent {
+                    kind: MouseEventKind::Press(MouseButton::Left),
+                    x: 9,
+                    y: 19,
+                    modifiers: Modifier::empty(),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lossless_strings() {
+        let bytes = b"\x1b]11;rgb:0000/8080/ffff\x1b\\\x1bP1$r0;1m\x1b\\";
+        for chunk_size in 1..=bytes.len() {
+            let mut input_parser = VTPushParserInput::new();
+            let mut osc = vec![];
+            let mut dcs = vec![];
+            for chunk in bytes.chunks(chunk_size) {
+                input_parser.feed_with(chunk, |event| match event {
+                    InputEvent::Osc(data) => osc.push(data.to_vec()),
+                    InputEvent::Dcs(header, data) => {
+                        assert_eq!(header.final_byte, b'r');
+                        dcs.push(data.to_vec());
+                    }
+                    event => panic!("unexpected event {event:?}"),
+                });
+            }
+            assert_eq!(
+                osc,
+                vec![b"11;rgb:0000/8080/ffff".to_vec()],
+                "chunk size {chunk_size}"
+            );
+            assert_eq!(dcs, vec![b"0;1m".to_vec()], "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_cancelled_strings() {
+        let mut input_parser = VTPushParserInput::new();
+        let mut events = vec![];
+        for chunk in [&b"\x1b]11;rgb"[..], b"\x18\x1bP1$r", b"ab", b"\x1ax"] {
+            input_parser.feed_with(chunk, |event| events.push(format!("{event:?}")));
+        }
+        assert_eq!(
+            events,
+            [
+                "Unknown([27, 93, 49, 49, 59, 114, 103, 98])",
+                "Unknown([27, 80, 49, 36, 114, 97, 98])",
+                "KeyChar('x', Modifier(0))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupted_sequences() {
+        for (input, expected) in [
+            // CAN and SUB cancel the sequence
+            (&b"\x1b[1;2\x18abc"[..], "Unknown([27, 91, 49, 59, 50])"),
+            (b"\x1b[1;2\x1aabc", "Unknown([27, 91, 49, 59, 50])"),
+            (b"\x1bP1$\x18abc", "Unknown([27, 80, 49, 36])"),
+            (b"\x1bP1$\x1aabc", "Unknown([27, 80, 49, 36])"),
+            (b"\x1b]11\x18abc", "Unknown([27, 93, 49, 49])"),
+            (b"\x1b]11\x1aabc", "Unknown([27, 93, 49, 49])"),
+        ] {
+            for chunk_size in 1..=input.len() {
+                let mut input_parser = VTPushParserInput::new();
+                let mut events = vec![];
+                for chunk in input.chunks(chunk_size) {
+                    input_parser.feed_with(chunk, |event| events.push(format!("{event:?}")));
+                }
+                assert_eq!(
+                    events,
+                    [
+                        expected,
+                        "KeyChar('a', Modifier(0))",
+                        "KeyChar('b', Modifier(0))",
+                        "KeyChar('c', Modifier(0))",
+                    ],
+                    "{input:?} in chunks of {chunk_size}"
+                );
+            }
+        }
+
+        // ESC starts a new sequence. In an OSC command, it may start the ST
+        // that ends the (empty) string instead.
+        for (input, expected) in [
+            (&b"\x1b[1;2\x1babc"[..], "Unknown([27, 91, 49, 59, 50])"),
+            (b"\x1bP1$\x1babc", "Unknown([27, 80, 49, 36])"),
+            (b"\x1b]11\x1b\\\x1babc", "Osc([49, 49])"),
+        ] {
+            for chunk_size in 1..=input.len() {
+                let mut input_parser = VTPushParserInput::new();
+                let mut events = vec![];
+                for chunk in input.chunks(chunk_size) {
+                    input_parser.feed_with(chunk, |event| events.push(format!("{event:?}")));
+                }
+                assert_eq!(
+                    events,
+                    [
+                        expected,
+                        "KeyChar('a', Modifier(2))",
+                        "KeyChar('b', Modifier(0))",
+                        "KeyChar('c', Modifier(0))",
+                    ],
+                    "{input:?} in chunks of {chunk_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_strings() {
+        let mut bytes = b"\x1b]52;c;".to_vec();
+        bytes.resize(MAX_STRING_LEN + 100, b'A');
+        bytes.extend_from_slice(b"\x07\x1b]2;x\x07");
+        let mut input_parser = VTPushParserInput::new();
+        let mut unknown = vec![];
+        let mut osc = vec![];
+        for chunk in bytes.chunks(4096) {
+            input_parser.feed_with(chunk, |event| match event {
+                InputEvent::Unknown(data) => unknown.extend_from_slice(data),
+                InputEvent::Osc(data) => osc.push(data.to_vec()),
+                event => panic!("unexpected event {event:?}"),
+            });
+        }
+        // The long string is passed through, and the next one is unaffected
+        assert_eq!(unknown, bytes[..MAX_STRING_LEN + 100]);
+        assert_eq!(osc, vec![b"2;x".to_vec()]);
+    }
+
+    #[test]
+    #[cfg(feature = "xterm")]
+    fn test_split_feed() {
+        let bytes = b"\x1b[A\x1bOBa\x1b[3~";
+        let expected = vec![
+            InputEvent::Key(keys::Key::UP, Modifier::empty()),
+            InputEvent::Key(keys::Key::DOWN, Modifier::empty()),
+            InputEvent::KeyChar('a', Modifier::empty()),
+            InputEvent::Key(keys::Key::DELETE, Modifier::empty()),
+        ];
+        for chunk_size in 1..=bytes.len() {
+            let events = collect_events_split(&mut VTPushParserInput::new(), bytes, chunk_size);
+            assert_eq!(events, expected, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_keymap() {
+        let mut input_parser = VTPushParserInput::new();
+        let keymap = input_parser.keymap_mut();
+        keymap.insert(
+            b"\x1b[99x",
+            InputEvent::Key(keys::Key::F13, Modifier::empty()),
+        );
+        keymap.insert(
+            b"\x1b[99xy",
+            InputEvent::Key(keys::Key::F14, Modifier::empty()),
+        );
+        // Overrides the built-in sequence of the same length
+        keymap.insert(
+            b"\x1b\x01",
+            InputEvent::Key(keys::Key::F15, Modifier::empty()),
+        );
+
+        let bytes = b"\x1b[99xa\x1b[99xyb\x1b\x01";
+        let expected = vec![
+            InputEvent::Key(keys::Key::F13, Modifier::empty()),
+            InputEvent::KeyChar('a', Modifier::empty()),
+            InputEvent::Key(keys::Key::F14, Modifier::empty()),
+            InputEvent::KeyChar('b', Modifier::empty()),
+            InputEvent::Key(keys::Key::F15, Modifier::empty()),
+        ];
+        for chunk_size in 1..=bytes.len() {
+            let events = collect_events_split(&mut input_parser, bytes, chunk_size);
+            assert_eq!(events, expected, "chunk size {chunk_size}");
+        }
+
+        // A registered prefix of a longer built-in sequence doesn't shadow it
+        input_parser.keymap_mut().insert(
+            b"\x1b\x1b",
+            InputEvent::Key(keys::Key::F16, Modifier::empty()),
+        );
+        let events = collect_events_split(&mut input_parser, b"\x1b\x1b[Z\x1b\x1bc", 1);
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::Key(keys::Key::TAB, Modifier::SHIFT | Modifier::ALT),
+                InputEvent::Key(keys::Key::F16, Modifier::empty()),
+                InputEvent::KeyChar('c', Modifier::empty()),
+            ]
+        );
+    }
+
     // SGR mouse tests
 
     #[test]
@@ -0,0 +1,577 @@
+//! Terminal capability queries.
+//!
+//! [`CapabilityQuery`] writes a batch of queries to the terminal, followed by
+//! a primary device attributes (DA1) request. Every terminal answers DA1, and
+//! answers queries in order, so once the DA1 reply arrives any query that has
+//! not been answered is unsupported.
+//!
+//! Replies arrive interleaved with user input, so the query sits between the
+//! [`VTPushParserInput`] and the rest of the application, collecting replies
+//! into a [`TerminalCapabilities`] and passing everything else through.
+//!
+//! ```rust
+//! use vt_input_push_parser::VTPushParserInput;
+//! use vt_input_push_parser::query::CapabilityQuery;
+//!
+//! let mut parser = VTPushParserInput::new();
+//! let mut query = CapabilityQuery::new();
+//! let mut output = vec![];
+//! query.write_to(&mut output).unwrap();
+//!
+//! // The terminal replies, and the user types "x" in the meantime
+//! query.feed_with(&mut parser, b"\x1b[?2026;2$yx\x1b[?62;4c", |event| {
+//!     println!("{event:?}");
+//! });
+//! assert!(query.is_complete());
+//! assert!(query.capabilities().synchronized_output);
+//! assert!(query.capabilities().sixel);
+//! ```
+use std::io;
+
+use vt_push_parser::event::{
+    CSIOwned, OscParams, ParamBufOwned, VTEvent, VTIntermediate, VTOwnedEvent,
+};
+use vt_push_parser::query::{DcsReply, XtGetTcap};
+use vt_push_parser::signature::VTEscapeSignature;
+
+use crate::{InputEvent, VTPushParserInput};
+
+/// CSI ? Ps ; ... c
+const DA1_REPLY: VTEscapeSignature = VTEscapeSignature::csi(b'c')
+    .with_private(b'?')
+    .with_params_count(0..u8::MAX);
+/// CSI > Pp ; Pv ; Pc c
+const DA2_REPLY: VTEscapeSignature = VTEscapeSignature::csi(b'c')
+    .with_private(b'>')
+    .with_params_count(0..u8::MAX);
+/// CSI ? Pd ; Ps $ y
+const DECRPM_REPLY: VTEscapeSignature = VTEscapeSignature::csi(b'y')
+    .with_private(b'?')
+    .with_intermediate(b'$')
+    .with_params_exact(2);
+/// CSI ? flags u
+const KITTY_KEYBOARD_REPLY: VTEscapeSignature = VTEscapeSignature::csi(b'u')
+    .with_private(b'?')
+    .with_params_exact(1);
+/// DCS > | text ST
+const XTVERSION_REPLY: VTEscapeSignature = VTEscapeSignature::dcs(b'|').with_private(b'>');
+/// DCS Ps + r Pt ST
+const XTGETTCAP_REPLY: VTEscapeSignature = VTEscapeSignature::dcs(b'r')
+    .with_intermediate(b'+')
+    .with_params_exact(1);
+
+/// DEC private mode 2026: synchronized output.
+pub const MODE_SYNCHRONIZED_OUTPUT: u16 = 2026;
+/// DEC private mode 2004: bracketed paste.
+pub const MODE_BRACKETED_PASTE: u16 = 2004;
+/// DEC private mode 1004: focus events.
+pub const MODE_FOCUS_EVENTS: u16 = 1004;
+/// DEC private mode 2031: color scheme updates.
+pub const MODE_COLOR_SCHEME_UPDATES: u16 = 2031;
+/// DEC private mode 2048: in-band resize reports.
+pub const MODE_IN_BAND_RESIZE: u16 = 2048;
+
+/// A query to send to the terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// Secondary device attributes (DA2): `CSI > c`.
+    SecondaryDeviceAttributes,
+    /// The terminal name and version (XTVERSION): `CSI > q`.
+    Version,
+    /// The state of a DEC private mode (DECRQM): `CSI ? Ps $ p`.
+    Mode(u16),
+    /// The kitty keyboard protocol flags: `CSI ? u`.
+    KittyKeyboard,
+    /// The default foreground color: `OSC 10 ; ? ST`.
+    ForegroundColor,
+    /// The default background color: `OSC 11 ; ? ST`.
+    BackgroundColor,
+    /// A terminfo capability (XTGETTCAP): `DCS + q Pt ST`.
+    TermCap(String),
+}
+
+impl Query {
+    /// Writes the query to `writer`, returning the number of bytes written.
+    pub fn write_to(&self, writer: impl io::Write) -> io::Result<usize> {
+        match self {
+            Query::SecondaryDeviceAttributes => {
+                csi(Some(b'>'), &[], None, b'c').borrow().write_to(writer)
+            }
+            Query::Version => csi(Some(b'>'), &[], None, b'q').borrow().write_to(writer),
+            Query::Mode(mode) => {
+                let mode = mode.to_string();
+                csi(Some(b'?'), &[mode.as_bytes()], Some(b'$'), b'p')
+                    .borrow()
+                    .write_to(writer)
+            }
+            Query::KittyKeyboard => csi(Some(b'?'), &[], None, b'u').borrow().write_to(writer),
+            Query::ForegroundColor => write_osc(writer, b"10;?"),
+            Query::BackgroundColor => write_osc(writer, b"11;?"),
+            Query::TermCap(name) => XtGetTcap(&[name]).write_to(writer),
+        }
+    }
+}
+
+fn csi(
+    private: Option<u8>,
+    params: &[&[u8]],
+    intermediate: Option<u8>,
+    final_byte: u8,
+) -> VTOwnedEvent {
+    VTOwnedEvent::Csi(CSIOwned {
+        private,
+        params: ParamBufOwned::new(params),
+        intermediates: intermediate.map(VTIntermediate::one).unwrap_or_default(),
+        final_byte,
+    })
+}
+
+fn write_osc(mut writer: impl io::Write, data: &[u8]) -> io::Result<usize> {
+    let end = VTEvent::OscEnd {
+        data,
+        used_bel: false,
+    };
+    let start = VTEvent::OscStart {
+        command: OscParams::new(data).command(),
+    };
+    Ok(start.write_to(&mut writer)? + end.write_to(&mut writer)?)
+}
+
+/// A 16-bit per channel color, as reported by the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Rgb {
+    /// The perceived luminance of the color, from 0.0 (black) to 1.0 (white),
+    /// using the Rec. 709 channel weights.
+    pub fn luminance(&self) -> f32 {
+        (0.2126 * self.r as f32 + 0.7152 * self.g as f32 + 0.0722 * self.b as f32) / 65535.0
+    }
+
+    /// Parses an X11 color specification, either `rgb:R/G/B` with one to four
+    /// hex digits per channel, or `#RGB` with one to four hex digits per
+    /// channel. Channels are scaled to 16 bits.
+    pub fn parse(spec: &[u8]) -> Option<Self> {
+        fn channel(hex: &[u8]) -> Option<u16> {
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let value = u16::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+            // Scale so that the maximum value for the digit count maps to 0xffff
+            let max = (1u32 << (hex.len() * 4)) - 1;
+            Some((value as u32 * 0xffff / max) as u16)
+        }
+
+        if let Some(rgb) = spec.strip_prefix(b"rgb:") {
+            let mut channels = rgb.split(|&b| b == b'/');
+            let (r, g, b) = (channels.next()?, channels.next()?, channels.next()?);
+            if channels.next().is_some() {
+                return None;
+            }
+            Some(Rgb {
+                r: channel(r)?,
+                g: channel(g)?,
+                b: channel(b)?,
+            })
+        } else if let Some(hex) = spec.strip_prefix(b"#") {
+            if hex.is_empty() || hex.len() % 3 != 0 {
+                return None;
+            }
+            let len = hex.len() / 3;
+            Some(Rgb {
+                r: channel(&hex[..len])?,
+                g: channel(&hex[len..len * 2])?,
+                b: channel(&hex[len * 2..])?,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The state of a DEC private mode, as reported by DECRPM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModeState {
+    NotRecognized,
+    Set,
+    Reset,
+    PermanentlySet,
+    PermanentlyReset,
+}
+
+impl ModeState {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => ModeState::Set,
+            2 => ModeState::Reset,
+            3 => ModeState::PermanentlySet,
+            4 => ModeState::PermanentlyReset,
+            _ => ModeState::NotRecognized,
+        }
+    }
+
+    /// Whether the mode is recognized and can be changed (or is always on).
+    pub fn is_supported(self) -> bool {
+        matches!(
+            self,
+            ModeState::Set | ModeState::Reset | ModeState::PermanentlySet
+        )
+    }
+}
+
+/// The capabilities of the terminal, collected from query replies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalCapabilities {
+    /// The attributes from the primary device attributes (DA1) reply. The
+    /// first attribute is the conformance level (eg: 62 for VT220).
+    pub device_attributes: Vec<u16>,
+    /// The terminal type, firmware version and ROM cartridge from the
+    /// secondary device attributes (DA2) reply.
+    pub terminal_id: Option<(u16, u16, u16)>,
+    /// The terminal name and version from the XTVERSION reply.
+    pub version: Option<String>,
+    /// Sixel graphics, from DA1 attribute 4.
+    pub sixel: bool,
+    /// 24-bit color, from the `RGB` or `Tc` terminfo capabilities.
+    pub truecolor: bool,
+    /// The current kitty keyboard protocol flags, if the protocol is supported.
+    pub kitty_keyboard: Option<u16>,
+    /// Synchronized output (mode 2026).
+    pub synchronized_output: bool,
+    /// Bracketed paste (mode 2004).
+    pub bracketed_paste: bool,
+    /// Focus events (mode 1004).
+    pub focus_events: bool,
+    /// Color scheme update reports (mode 2031).
+    pub color_scheme_updates: bool,
+    /// In-band resize reports (mode 2048).
+    pub in_band_resize: bool,
+    /// The default foreground color.
+    pub foreground: Option<Rgb>,
+    /// The default background color.
+    pub background: Option<Rgb>,
+    /// The reported state of each queried DEC private mode.
+    pub modes: Vec<(u16, ModeState)>,
+    /// The terminfo capabilities the terminal recognized, with their values
+    /// (`None` for boolean capabilities).
+    pub termcap: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl TerminalCapabilities {
+    /// The reported state of a DEC private mode, if it was queried.
+    pub fn mode(&self, mode: u16) -> Option<ModeState> {
+        self.modes
+            .iter()
+            .find(|(m, _)| *m == mode)
+            .map(|(_, state)| *state)
+    }
+
+    fn update_mode(&mut self, mode: u16, state: ModeState) {
+        let supported = state.is_supported();
+        match mode {
+            MODE_SYNCHRONIZED_OUTPUT => self.synchronized_output = supported,
+            MODE_BRACKETED_PASTE => self.bracketed_paste = supported,
+            MODE_FOCUS_EVENTS => self.focus_events = supported,
+            MODE_COLOR_SCHEME_UPDATES => self.color_scheme_updates = supported,
+            MODE_IN_BAND_RESIZE => self.in_band_resize = supported,
+            _ => {}
+        }
+        match self.modes.iter_mut().find(|(m, _)| *m == mode) {
+            Some((_, existing)) => *existing = state,
+            None => self.modes.push((mode, state)),
+        }
+    }
+}
+
+/// A batch of capability queries, fenced by a DA1 request.
+#[derive(Debug, Clone)]
+pub struct CapabilityQuery {
+    queries: Vec<Query>,
+    capabilities: TerminalCapabilities,
+    complete: bool,
+}
+
+impl Default for CapabilityQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CapabilityQuery {
+    /// Creates a query for all of the capabilities in [`TerminalCapabilities`].
+    pub fn new() -> Self {
+        Self::empty()
+            .with(Query::SecondaryDeviceAttributes)
+            .with(Query::Version)
+            .with(Query::KittyKeyboard)
+            .with(Query::Mode(MODE_SYNCHRONIZED_OUTPUT))
+            .with(Query::Mode(MODE_BRACKETED_PASTE))
+            .with(Query::Mode(MODE_FOCUS_EVENTS))
+            .with(Query::Mode(MODE_COLOR_SCHEME_UPDATES))
+            .with(Query::Mode(MODE_IN_BAND_RESIZE))
+            .with(Query::ForegroundColor)
+            .with(Query::BackgroundColor)
+            .with(Query::TermCap("RGB".to_owned()))
+            .with(Query::TermCap("Tc".to_owned()))
+    }
+
+    /// Creates a query that only sends the DA1 request.
+    pub fn empty() -> Self {
+        Self {
+            queries: Vec::new(),
+            capabilities: TerminalCapabilities::default(),
+            complete: false,
+        }
+    }
+
+    /// Adds a query to the batch.
+    pub fn with(mut self, query: Query) -> Self {
+        self.queries.push(query);
+        self
+    }
+
+    pub fn queries(&self) -> &[Query] {
+        &self.queries
+    }
+
+    /// Writes the queries to `writer`, followed by the DA1 request.
+    pub fn write_to(&self, mut writer: impl io::Write) -> io::Result<usize> {
+        let mut len = 0;
+        for query in &self.queries {
+            len += query.write_to(&mut writer)?;
+        }
+        len += csi(None, &[], None, b'c').borrow().write_to(&mut writer)?;
+        Ok(len)
+    }
+
+    /// Returns true once the DA1 reply has been received. Any queries that have
+    /// not been answered by then are not supported by the terminal.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn capabilities(&self) -> &TerminalCapabilities {
+        &self.capabilities
+    }
+
+    pub fn into_capabilities(self) -> TerminalCapabilities {
+        self.capabilities
+    }
+
+    /// Feeds bytes through `parser`, collecting replies and passing all other
+    /// events to `cb`.
+    pub fn feed_with(
+        &mut self,
+        parser: &mut VTPushParserInput,
+        bytes: &[u8],
+        mut cb: impl FnMut(InputEvent),
+    ) {
+        parser.feed_with(bytes, |event| {
+            if !self.handle_event(&event) {
+                cb(event);
+            }
+        });
+    }
+
+    /// Handles an input event, returning true if it was a reply to one of the
+    /// queries. Once the query is complete, no further events are consumed.
+    pub fn handle_event(&mut self, event: &InputEvent<'_>) -> bool {
+        if self.complete {
+            return false;
+        }
+        let capabilities = &mut self.capabilities;
+        match event {
+            InputEvent::Csi(csi) => {
+                let vt = VTEvent::Csi(csi.clone());
+                let params = csi.params.numeric();
+                let param = |i| params.get(i).and_then(|p| p.first());
+                if DA1_REPLY.matches(&vt) {
+                    capabilities.device_attributes =
+                        params.into_iter().flatten().flatten().collect();
+                    capabilities.sixel = capabilities
+                        .device_attributes
+                        .get(1..)
+                        .is_some_and(|attributes| attributes.contains(&4));
+                    self.complete = true;
+                } else if DA2_REPLY.matches(&vt) {
+                    capabilities.terminal_id = Some((
+                        param(0).unwrap_or(0),
+                        param(1).unwrap_or(0),
+                        param(2).unwrap_or(0),
+                    ));
+                } else if DECRPM_REPLY.matches(&vt) {
+                    let (Some(mode), Some(state)) = (param(0), param(1)) else {
+                        return false;
+                    };
+                    let state =
+                        u8::try_from(state).map_or(ModeState::NotRecognized, ModeState::from_code);
+                    capabilities.update_mode(mode, state);
+                } else if KITTY_KEYBOARD_REPLY.matches(&vt) {
+                    capabilities.kitty_keyboard = Some(param(0).unwrap_or(0));
+                } else {
+                    return false;
+                }
+                true
+            }
+            InputEvent::Dcs(dcs, data) => {
+                let vt = VTEvent::DcsStart(dcs.borrow());
+                if XTVERSION_REPLY.matches(&vt) {
+                    capabilities.version = Some(String::from_utf8_lossy(data).into_owned());
+                } else if XTGETTCAP_REPLY.matches(&vt) {
+                    if let Some(DcsReply::TermCap {
+                        valid: true,
+                        capabilities: termcap,
+                    }) = DcsReply::decode(&dcs.borrow(), data)
+                    {
+                        for (name, value) in termcap {
+                            if name == "RGB" || name == "Tc" {
+                                capabilities.truecolor = true;
+                            }
+                            capabilities.termcap.push((name, value));
+                        }
+                    }
+                } else {
+                    return false;
+                }
+                true
+            }
+            InputEvent::Osc(data) => {
+                let (target, spec) = match data.split_first_chunk::<3>() {
+                    Some((b"10;", spec)) => (&mut capabilities.foreground, spec),
+                    Some((b"11;", spec)) => (&mut capabilities.background, spec),
+                    _ => return false,
+                };
+                *target = Rgb::parse(spec);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_queries() {
+        let query = CapabilityQuery::empty()
+            .with(Query::SecondaryDeviceAttributes)
+            .with(Query::Version)
+            .with(Query::Mode(2026))
+            .with(Query::KittyKeyboard)
+            .with(Query::BackgroundColor)
+            .with(Query::TermCap("RGB".to_owned()));
+        let mut output = vec![];
+        let len = query.write_to(&mut output).unwrap();
+        assert_eq!(len, output.len());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\x1b[>c\x1b[>q\x1b[?2026$p\x1b[?u\x1b]11;?\x1b\\\x1bP+q524742\x1b\\\x1b[c"
+        );
+    }
+
+    #[test]
+    fn test_replies() {
+        let replies: &[u8] = b"\x1b[>1;10;0c\
+            a\
+            \x1bP>|XTerm(390)\x1b\\\
+            \x1b[?1u\
+            \x1b[?2026;2$y\
+            \x1b[?2048;0$y\
+            b\
+            \x1b]11;rgb:ffff/8080/0000\x1b\\\
+            \x1bP1+r524742=382F382F38\x1b\\\
+            \x1bP0+r5463\x1b\\\
+            \x1b[?64;1;4;22c\
+            \x1b[?1u";
+
+        for chunk_size in 1..replies.len() {
+            let mut parser = VTPushParserInput::new();
+            let mut query = CapabilityQuery::new();
+            let mut passed = vec![];
+            for chunk in replies.chunks(chunk_size) {
+                query.feed_with(&mut parser, chunk, |event| {
+                    passed.push(format!("{event:?}"))
+                });
+            }
+            assert!(query.is_complete());
+
+            // Replies after the fence are passed through
+            assert_eq!(
+                passed,
+                [
+                    "KeyChar('a', Modifier(0))",
+                    "KeyChar('b', Modifier(0))",
+                    "Csi(Csi('?', '1', '', 'u'))"
+                ]
+            );
+
+            let capabilities = query.capabilities();
+            assert_eq!(capabilities.device_attributes, [64, 1, 4, 22]);
+            assert!(capabilities.sixel);
+            assert_eq!(capabilities.terminal_id, Some((1, 10, 0)));
+            assert_eq!(capabilities.version.as_deref(), Some("XTerm(390)"));
+            assert_eq!(capabilities.kitty_keyboard, Some(1));
+            assert!(capabilities.synchronized_output);
+            assert!(!capabilities.in_band_resize);
+            assert_eq!(capabilities.mode(2048), Some(ModeState::NotRecognized));
+            assert_eq!(capabilities.mode(2004), None);
+            assert_eq!(
+                capabilities.background,
+                Some(Rgb {
+                    r: 0xffff,
+                    g: 0x8080,
+                    b: 0
+                })
+            );
+            assert_eq!(capabilities.foreground, None);
+            assert!(capabilities.truecolor);
+            assert_eq!(
+                capabilities.termcap,
+                [("RGB".to_owned(), Some(b"8/8/8".to_vec()))]
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_device_attributes() {
+        for reply in [&b"\x1b[?c"[..], b"\x1b[?;c"] {
+            let mut parser = VTPushParserInput::new();
+            let mut query = CapabilityQuery::new();
+            query.feed_with(&mut parser, reply, |event| panic!("{event:?}"));
+            assert!(query.is_complete());
+            assert!(!query.capabilities().sixel);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_mode_state() {
+        // 257 must not be truncated to 1 (set)
+        let mut parser = VTPushParserInput::new();
+        let mut query = CapabilityQuery::new();
+        query.feed_with(&mut parser, b"\x1b[?2026;257$y", |event| {
+            panic!("{event:?}")
+        });
+        let capabilities = query.capabilities();
+        assert_eq!(capabilities.mode(2026), Some(ModeState::NotRecognized));
+        assert!(!capabilities.synchronized_output);
+    }
+
+    #[test]
+    fn test_parse_color() {
+        let rgb = |r, g, b| Some(Rgb { r, g, b });
+        assert_eq!(Rgb::parse(b"rgb:ffff/0000/8080"), rgb(0xffff, 0, 0x8080));
+        assert_eq!(Rgb::parse(b"rgb:f/0/8"), rgb(0xffff, 0, 0x8888));
+        assert_eq!(Rgb::parse(b"rgb:ff/00/80"), rgb(0xffff, 0, 0x8080));
+        assert_eq!(Rgb::parse(b"#ff0080"), rgb(0xffff, 0, 0x8080));
+        assert_eq!(Rgb::parse(b"#f08"), rgb(0xffff, 0, 0x8888));
+        assert_eq!(Rgb::parse(b"rgb:ff/00"), None);
+        assert_eq!(Rgb::parse(b"#ff008"), None);
+        assert_eq!(Rgb::parse(b"red"), None);
+    }
+}
@@ -1,5 +1,7 @@
 //! Raw-input-capturing push parser.
 
+use std::borrow::Cow;
+
 use crate::{VT_PARSER_INTEREST_DEFAULT, VTEvent, VTPushParser};
 
 pub trait VTInputCaptureCallback {
@@ -15,18 +17,42 @@ impl<F: FnMut(VTCaptureEvent<'_>) -> VTInputCapture> VTInputCaptureCallback for
 
 /// The type of capture mode to use after this event has been emitted.
 ///
-/// The data will be emitted as a [`VTInputEvent::Captured`] event.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The data will be emitted as [`VTCaptureEvent::Capture`] events, followed by
+/// a [`VTCaptureEvent::CaptureEnd`] event.
+///
+/// This is not `Copy`, as [`VTInputCapture::Terminators`] may own its
+/// terminators.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VTInputCapture {
     /// No capture mode. This must also be returned from any
-    /// [`VTInputEvent::Captured`] event.
+    /// [`VTCaptureEvent::Capture`] event.
     None,
     /// Capture a fixed number of bytes.
     Count(usize),
     /// Capture a fixed number of UTF-8 chars.
     CountUtf8(usize),
-    /// Capture bytes until a terminator is found.
+    /// Capture bytes until a terminator is found. The terminator must not be
+    /// empty.
     Terminator(&'static [u8]),
+    /// Capture bytes until any one of a set of terminators is found. The index
+    /// of the terminator that matched is reported with
+    /// [`VTCaptureEvent::CaptureMatched`]. If more than one terminator matches,
+    /// the one that completes first wins, and of those that complete at the
+    /// same byte, the longest. None of the terminators may be empty.
+    ///
+    /// If `limit` is set and more than `limit` bytes are captured before a
+    /// terminator is found, the capture ends with
+    /// [`VTCaptureEvent::CaptureOverflow`] and parsing resumes with the
+    /// overflowing byte.
+    Terminators {
+        terminators: Vec<Cow<'static, [u8]>>,
+        limit: Option<usize>,
+    },
+    /// Capture bytes until [`VTCapturePushParser::idle`] is called, ie: until
+    /// the input goes quiet.
+    ///
+    /// `limit` behaves as it does for [`VTInputCapture::Terminators`].
+    Idle { limit: Option<usize> },
 }
 
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
@@ -34,9 +60,30 @@ pub enum VTInputCapture {
 pub enum VTCaptureEvent<'a> {
     VTEvent(VTEvent<'a>),
     Capture(&'a [u8]),
+    /// A [`VTInputCapture::Terminators`] capture found the terminator at this
+    /// index. Followed by [`VTCaptureEvent::CaptureEnd`].
+    CaptureMatched(usize),
+    /// A capture reached its limit without ending. Followed by
+    /// [`VTCaptureEvent::CaptureEnd`].
+    CaptureOverflow,
     CaptureEnd,
 }
 
+/// How a capture ended.
+///
+/// This is not part of the public API and may change at any time.
+#[doc(hidden)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum VTCaptureEnd {
+    /// The capture completed normally.
+    Complete,
+    /// The terminator at this index matched.
+    Matched(usize),
+    /// The capture hit its limit. Any bytes that were consumed but not
+    /// captured must be parsed before the remaining input.
+    Overflow(Vec<u8>),
+}
+
 /// The internal state of the capture parser.
 ///
 /// This is not part of the public API and may change at any time.
@@ -46,92 +93,220 @@ pub enum VTCaptureInternal {
     #[default]
     None,
     Count(usize),
-    CountUtf8(usize),
-    Terminator(&'static [u8], usize),
+    CountUtf8 {
+        chars: usize,
+        /// Continuation bytes remaining in the current char.
+        continuation: usize,
+    },
+    Terminators {
+        terminators: Vec<Cow<'static, [u8]>>,
+        /// Bytes matching the prefix of at least one terminator.
+        pending: Vec<u8>,
+        limit: Option<usize>,
+        captured: usize,
+        /// Whether to report the matching terminator.
+        report: bool,
+    },
+    Idle {
+        limit: Option<usize>,
+        captured: usize,
+    },
+}
+
+impl From<VTInputCapture> for VTCaptureInternal {
+    fn from(capture: VTInputCapture) -> Self {
+        if let VTInputCapture::Terminator(terminator) = &capture {
+            assert!(!terminator.is_empty(), "capture terminator is empty");
+        }
+        if let VTInputCapture::Terminators { terminators, .. } = &capture {
+            assert!(
+                terminators.iter().all(|t| !t.is_empty()),
+                "capture terminator is empty"
+            );
+        }
+        match capture {
+            VTInputCapture::None => VTCaptureInternal::None,
+            VTInputCapture::Count(count) => VTCaptureInternal::Count(count),
+            VTInputCapture::CountUtf8(chars) => VTCaptureInternal::CountUtf8 {
+                chars,
+                continuation: 0,
+            },
+            VTInputCapture::Terminator(terminator) => VTCaptureInternal::Terminators {
+                terminators: vec![Cow::Borrowed(terminator)],
+                pending: Vec::new(),
+                limit: None,
+                captured: 0,
+                report: false,
+            },
+            VTInputCapture::Terminators { terminators, limit } => VTCaptureInternal::Terminators {
+                terminators,
+                pending: Vec::new(),
+                limit,
+                captured: 0,
+                report: true,
+            },
+            VTInputCapture::Idle { limit } => VTCaptureInternal::Idle { limit, captured: 0 },
+        }
+    }
+}
+
+/// Emits captured data, respecting the capture limit. Returns the number of
+/// bytes emitted, which is less than `data.len()` if the limit was reached.
+#[inline]
+fn emit(
+    data: &[u8],
+    limit: Option<usize>,
+    captured: &mut usize,
+    cb: &mut impl FnMut(&[u8]),
+) -> usize {
+    let len = match limit {
+        Some(limit) => data.len().min(limit - *captured),
+        None => data.len(),
+    };
+    if len > 0 {
+        cb(&data[..len]);
+        *captured += len;
+    }
+    len
 }
 
 impl VTCaptureInternal {
-    pub fn feed<'a>(&mut self, input: &mut &'a [u8]) -> Option<&'a [u8]> {
-        match self {
-            VTCaptureInternal::None => None,
+    /// Feeds input into the capture, passing captured data to `cb` and
+    /// consuming it from `input`. Returns how the capture ended, if it did.
+    pub fn feed(&mut self, input: &mut &[u8], mut cb: impl FnMut(&[u8])) -> Option<VTCaptureEnd> {
+        let end = match self {
+            VTCaptureInternal::None => return None,
             VTCaptureInternal::Count(count) => {
-                if input.len() >= *count {
-                    let (capture, rest) = input.split_at(*count);
-                    *input = rest;
-                    *self = VTCaptureInternal::None;
-                    Some(capture)
-                } else {
-                    None
+                let (capture, rest) = input.split_at((*count).min(input.len()));
+                if !capture.is_empty() {
+                    cb(capture);
+                }
+                *input = rest;
+                *count -= capture.len();
+                if *count > 0 {
+                    return None;
                 }
+                VTCaptureEnd::Complete
             }
-            VTCaptureInternal::CountUtf8(count) => {
+            VTCaptureInternal::CountUtf8 {
+                chars,
+                continuation,
+            } => {
                 // Count UTF-8 characters, not bytes
-                let mut chars_found = 0;
-                let mut bytes_consumed = 0;
-
-                for (i, &byte) in input.iter().enumerate() {
-                    // Check if this is the start of a new UTF-8 character
-                    if byte & 0xC0 != 0x80 {
-                        // Not a continuation byte
-                        chars_found += 1;
-                        if chars_found == *count {
-                            // We found the nth character, now we need to find where it ends
-                            // by consuming all its continuation bytes
-                            let mut j = i + 1;
-                            while j < input.len() && input[j] & 0xC0 == 0x80 {
-                                j += 1;
-                            }
-                            bytes_consumed = j;
-                            break;
-                        }
+                let mut i = 0;
+                while i < input.len() && (*chars > 0 || *continuation > 0) {
+                    let byte = input[i];
+                    if *continuation > 0 && byte & 0xC0 == 0x80 {
+                        *continuation -= 1;
+                    } else if *chars == 0 {
+                        // The last character was truncated by a new one
+                        *continuation = 0;
+                        break;
+                    } else {
+                        *chars -= 1;
+                        *continuation = match byte {
+                            0xC0..=0xDF => 1,
+                            0xE0..=0xEF => 2,
+                            0xF0..=0xF7 => 3,
+                            _ => 0,
+                        };
                     }
+                    i += 1;
                 }
-
-                if chars_found == *count {
-                    let (capture, rest) = input.split_at(bytes_consumed);
-                    *input = rest;
-                    *self = VTCaptureInternal::None;
-                    Some(capture)
-                } else {
-                    None
+                let (capture, rest) = input.split_at(i);
+                if !capture.is_empty() {
+                    cb(capture);
                 }
+                *input = rest;
+                if *chars > 0 || *continuation > 0 {
+                    return None;
+                }
+                VTCaptureEnd::Complete
             }
-            VTCaptureInternal::Terminator(terminator, found) => {
-                // Ground state
-                if *found == 0 {
-                    if let Some(position) = input.iter().position(|&b| b == terminator[0]) {
-                        // Advance to first match position
-                        *found = 1;
-                        let unmatched = &input[..position];
-                        *input = &input[position + 1..];
-                        return Some(unmatched);
-                    } else {
-                        let unmatched = *input;
-                        *input = &[];
-                        return Some(unmatched);
+            VTCaptureInternal::Terminators {
+                terminators,
+                pending,
+                limit,
+                captured,
+                report,
+            } => loop {
+                if pending.is_empty() {
+                    // Fast path: skip to the first byte that could start a terminator
+                    let position = input
+                        .iter()
+                        .position(|b| terminators.iter().any(|t| t.first() == Some(b)))
+                        .unwrap_or(input.len());
+                    let emitted = emit(&input[..position], *limit, captured, &mut cb);
+                    *input = &input[emitted..];
+                    if emitted < position {
+                        break VTCaptureEnd::Overflow(vec![]);
                     }
                 }
 
-                // We've already found part of the terminator, so we can continue
-                while *found < terminator.len() {
-                    if input.is_empty() {
-                        return None;
+                // Wait for more input to complete or fail the match
+                let &byte = input.first()?;
+                pending.push(byte);
+                // A shorter terminator may complete inside a longer one's prefix
+                // (eg: "b" in "abc"), so check every suffix, longest first
+                let matched = (0..pending.len()).find_map(|start| {
+                    let index = terminators.iter().position(|t| **t == pending[start..])?;
+                    Some((start, index))
+                });
+                if let Some((start, index)) = matched {
+                    *input = &input[1..];
+                    let emitted = emit(&pending[..start], *limit, captured, &mut cb);
+                    if emitted < start {
+                        break VTCaptureEnd::Overflow(pending.split_off(emitted));
                     }
-
-                    if input[0] == terminator[*found] {
-                        *found += 1;
-                        *input = &input[1..];
-                    } else {
-                        // Failed a match, so return the part of the terminator we already matched
-                        let old_found = std::mem::take(found);
-                        return Some(&terminator[..old_found]);
+                    if *report {
+                        break VTCaptureEnd::Matched(index);
                     }
+                    break VTCaptureEnd::Complete;
                 }
-
-                // We've matched the entire terminator
-                *self = VTCaptureInternal::None;
-                None
+                if terminators.iter().any(|t| t.starts_with(pending)) {
+                    *input = &input[1..];
+                    continue;
+                }
+                pending.pop();
+
+                // Failed a match, so flush the shortest prefix of the pending bytes
+                // that leaves a (possibly empty) terminator prefix including this
+                // byte.
+                let flush = (1..pending.len())
+                    .find(|&k| {
+                        let rest = &pending[k..];
+                        terminators
+                            .iter()
+                            .any(|t| t.starts_with(rest) && t.get(rest.len()) == Some(&byte))
+                    })
+                    .unwrap_or(pending.len());
+                let emitted = emit(&pending[..flush], *limit, captured, &mut cb);
+                if emitted < flush {
+                    break VTCaptureEnd::Overflow(pending.split_off(emitted));
+                }
+                pending.drain(..flush);
+            },
+            VTCaptureInternal::Idle { limit, captured } => {
+                let emitted = emit(input, *limit, captured, &mut cb);
+                let overflow = emitted < input.len();
+                *input = &input[emitted..];
+                if !overflow {
+                    return None;
+                }
+                VTCaptureEnd::Overflow(vec![])
             }
+        };
+        *self = VTCaptureInternal::None;
+        Some(end)
+    }
+
+    /// Ends an idle capture, returning true if there was one.
+    pub fn idle(&mut self) -> bool {
+        if matches!(self, VTCaptureInternal::Idle { .. }) {
+            *self = VTCaptureInternal::None;
+            true
+        } else {
+            false
         }
     }
 }
@@ -174,11 +349,25 @@ impl<const INTEREST: u8> VTCapturePushParser<INTEREST> {
         self.parser.is_ground()
     }
 
+    /// Returns true if the parser is currently capturing input.
+    pub fn is_capturing(&self) -> bool {
+        !matches!(self.capture, VTCaptureInternal::None)
+    }
+
+    /// Notify the parser that the input has gone idle. This ends any
+    /// [`VTInputCapture::Idle`] capture.
     pub fn idle(&mut self) -> Option<VTCaptureEvent<'static>> {
+        if self.capture.idle() {
+            return Some(VTCaptureEvent::CaptureEnd);
+        }
         self.parser.idle().map(VTCaptureEvent::VTEvent)
     }
 
-    pub fn feed_with<F: VTInputCaptureCallback>(&mut self, mut input: &[u8], mut cb: F) {
+    pub fn feed_with<F: VTInputCaptureCallback>(&mut self, input: &[u8], mut cb: F) {
+        self.feed_with_inner(input, &mut cb);
+    }
+
+    fn feed_with_inner<F: VTInputCaptureCallback>(&mut self, mut input: &[u8], cb: &mut F) {
         while !input.is_empty() {
             match &mut self.capture {
                 VTCaptureInternal::None => {
@@ -187,34 +376,32 @@ impl<const INTEREST: u8> VTCapturePushParser<INTEREST> {
                         .parser
                         .feed_with_abortable(input, &mut |event: VTEvent| {
                             let capture_mode = cb.event(VTCaptureEvent::VTEvent(event));
-                            match capture_mode {
-                                VTInputCapture::None => {
-                                    // Stay in normal mode
-                                }
-                                VTInputCapture::Count(count) => {
-                                    self.capture = VTCaptureInternal::Count(count);
-                                }
-                                VTInputCapture::CountUtf8(count) => {
-                                    self.capture = VTCaptureInternal::CountUtf8(count);
-                                }
-                                VTInputCapture::Terminator(terminator) => {
-                                    self.capture = VTCaptureInternal::Terminator(terminator, 0);
-                                }
-                            }
-                            false // Don't abort parsing
+                            self.capture = capture_mode.into();
+                            // Abort parsing so the capture sees the following bytes
+                            matches!(self.capture, VTCaptureInternal::None)
                         });
 
                     input = &input[count..];
                 }
                 capture => {
                     // Capture mode - collect data until capture is complete
-                    if let Some(captured_data) = capture.feed(&mut input) {
-                        cb.event(VTCaptureEvent::Capture(captured_data));
-                    }
-
-                    // Check if capture is complete
-                    if matches!(self.capture, VTCaptureInternal::None) {
-                        cb.event(VTCaptureEvent::CaptureEnd);
+                    let end = capture.feed(&mut input, |data| {
+                        cb.event(VTCaptureEvent::Capture(data));
+                    });
+                    match end {
+                        None => {}
+                        Some(VTCaptureEnd::Complete) => {
+                            cb.event(VTCaptureEvent::CaptureEnd);
+                        }
+                        Some(VTCaptureEnd::Matched(index)) => {
+                            cb.event(VTCaptureEvent::CaptureMatched(index));
+                            cb.event(VTCaptureEvent::CaptureEnd);
+                        }
+                        Some(VTCaptureEnd::Overflow(leftover)) => {
+                            cb.event(VTCaptureEvent::CaptureOverflow);
+                            cb.event(VTCaptureEvent::CaptureEnd);
+                            self.feed_with_inner(&leftover, cb);
+                        }
                     }
                 }
             }
@@ -395,6 +582,193 @@ VTEvent(Raw('end'))"#
         }
     }
 
+    fn capture_terminators(input: &[u8], chunk_size: usize, limit: Option<usize>) -> String {
+        let mut output = String::new();
+        let mut parser = VTCapturePushParser::new();
+        // A terminator computed at runtime
+        let tag = format!("END{}", 1);
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_with(chunk, &mut |event: VTCaptureEvent| {
+                match event {
+                    VTCaptureEvent::Capture(data) => {
+                        output.push_str(&String::from_utf8_lossy(data));
+                        return VTInputCapture::None;
+                    }
+                    _ => output.push_str(&format!("|{event:?}|")),
+                }
+                match event {
+                    VTCaptureEvent::VTEvent(VTEvent::Csi(csi)) if csi.final_byte == b'X' => {
+                        VTInputCapture::Terminators {
+                            terminators: vec![
+                                Cow::Borrowed(b"\x07"),
+                                Cow::Borrowed(b"\x1b\\"),
+                                Cow::Owned(tag.clone().into_bytes()),
+                            ],
+                            limit,
+                        }
+                    }
+                    _ => VTInputCapture::None,
+                }
+            });
+        }
+        output
+    }
+
+    #[test]
+    fn test_capture_terminators() {
+        for chunk_size in 1..8 {
+            assert_eq!(
+                capture_terminators(
+                    b"\x1b[Xab\x1bc\x07d\x1b[Xe\x1b\\f\x1b[XgENEND1h",
+                    chunk_size,
+                    None
+                ),
+                "|VTEvent(Csi('', 'X'))|ab\x1bc|CaptureMatched(0)||CaptureEnd||VTEvent(Raw('d'))|\
+                 |VTEvent(Csi('', 'X'))|e|CaptureMatched(1)||CaptureEnd||VTEvent(Raw('f'))|\
+                 |VTEvent(Csi('', 'X'))|gEN|CaptureMatched(2)||CaptureEnd||VTEvent(Raw('h'))|",
+                "chunk_size={chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_capture_nested_terminators() {
+        let capture = |terminators: &[&'static [u8]], input: &[u8], chunk_size: usize| {
+            let mut output = String::new();
+            let mut parser = VTCapturePushParser::new();
+            for chunk in input.chunks(chunk_size) {
+                parser.feed_with(chunk, &mut |event: VTCaptureEvent| {
+                    match event {
+                        VTCaptureEvent::Capture(data)
+                        | VTCaptureEvent::VTEvent(VTEvent::Raw(data)) => {
+                            output.push_str(&String::from_utf8_lossy(data));
+                        }
+                        VTCaptureEvent::VTEvent(VTEvent::Csi(_)) => {
+                            return VTInputCapture::Terminators {
+                                terminators: terminators
+                                    .iter()
+                                    .map(|t| Cow::Borrowed(*t))
+                                    .collect(),
+                                limit: None,
+                            };
+                        }
+                        _ => output.push_str(&format!("|{event:?}|")),
+                    }
+                    VTInputCapture::None
+                });
+            }
+            output
+        };
+        for chunk_size in 1..8 {
+            // A short terminator inside a longer one's prefix
+            assert_eq!(
+                capture(&[b"abc", b"b"], b"\x1b[Xabdzz", chunk_size),
+                "a|CaptureMatched(1)||CaptureEnd|dzz",
+                "chunk_size={chunk_size}"
+            );
+            // A terminator that ends another, and one that overlaps its start
+            assert_eq!(
+                capture(&[b"aab", b"ab", b"ca"], b"\x1b[Xxaab\x1b[Xxcab", chunk_size),
+                "x|CaptureMatched(0)||CaptureEnd|x|CaptureMatched(2)||CaptureEnd|b",
+                "chunk_size={chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capture terminator is empty")]
+    fn test_capture_empty_terminator() {
+        let mut parser = VTCapturePushParser::new();
+        parser.feed_with(b"\x1b[Xab", &mut |_: VTCaptureEvent| {
+            VTInputCapture::Terminators {
+                terminators: vec![Cow::Borrowed(b"\x07"), Cow::Borrowed(b"")],
+                limit: None,
+            }
+        });
+    }
+
+    #[test]
+    fn test_capture_overflow() {
+        for chunk_size in 1..8 {
+            // Parsing resumes with the overflowing byte, including any bytes that
+            // were held as a partial terminator match
+            let raw = |output: String| output.replace("|VTEvent(Raw('", "").replace("'))|", "");
+            assert_eq!(
+                raw(capture_terminators(b"\x1b[Xabcdef", chunk_size, Some(3))),
+                "|VTEvent(Csi('', 'Xabc|CaptureOverflow||CaptureEnd|def",
+                "chunk_size={chunk_size}"
+            );
+            assert_eq!(
+                raw(capture_terminators(b"\x1b[XabENx", chunk_size, Some(3))),
+                "|VTEvent(Csi('', 'XabE|CaptureOverflow||CaptureEnd|Nx",
+                "chunk_size={chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_capture_idle() {
+        let mut output = String::new();
+        let mut parser = VTCapturePushParser::new();
+        let mut cb = |event: VTCaptureEvent| {
+            output.push_str(&format!("{event:?}\n"));
+            match event {
+                VTCaptureEvent::VTEvent(VTEvent::Csi(csi)) if csi.final_byte == b'X' => {
+                    VTInputCapture::Idle { limit: None }
+                }
+                _ => VTInputCapture::None,
+            }
+        };
+        parser.feed_with(b"\x1b[Xab", &mut cb);
+        parser.feed_with(b"\x1b[Y", &mut cb);
+        assert!(parser.is_capturing());
+        cb(parser.idle().unwrap());
+        parser.feed_with(b"c", &mut cb);
+        assert_eq!(
+            output.trim(),
+            r#"
+VTEvent(Csi('', 'X'))
+Capture([97, 98])
+Capture([27, 91, 89])
+CaptureEnd
+VTEvent(Raw('c'))
+"#
+            .trim()
+        );
+    }
+
+    #[test]
+    fn test_capture_count_split() {
+        let input = "\u{001b}[X🤖a✅\u{001b}[Yb".as_bytes();
+        for count in [VTInputCapture::Count(8), VTInputCapture::CountUtf8(3)] {
+            for chunk_size in 1..5 {
+                let mut captured = Vec::new();
+                let mut output = String::new();
+                let mut parser = VTCapturePushParser::new();
+                for chunk in input.chunks(chunk_size) {
+                    parser.feed_with(chunk, &mut |event: VTCaptureEvent| match event {
+                        VTCaptureEvent::Capture(data) => {
+                            captured.extend_from_slice(data);
+                            VTInputCapture::None
+                        }
+                        VTCaptureEvent::VTEvent(VTEvent::Csi(csi)) if csi.final_byte == b'X' => {
+                            count.clone()
+                        }
+                        event => {
+                            output.push_str(&format!("{event:?}\n"));
+                            VTInputCapture::None
+                        }
+                    });
+                }
+                assert_eq!(captured, "🤖a✅".as_bytes());
+                assert_eq!(
+                    output,
+                    "CaptureEnd\nVTEvent(Csi('', 'Y'))\nVTEvent(Raw('b'))\n"
+                );
+            }
+        }
+    }
+
     fn capture_chunk_size(input: &'static [u8; 32], chunk_size: usize) -> (Vec<u8>, String) {
         let mut output = String::new();
         let mut parser = VTCapturePushParser::new();
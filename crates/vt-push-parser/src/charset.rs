@@ -0,0 +1,322 @@
+//! DEC character set designation and translation.
+//!
+//! Applications designate character sets into the four slots G0 to G3 with
+//! `ESC ( F`, `ESC ) F`, `ESC * F` and `ESC + F` (or `ESC - F`, `ESC . F` and
+//! `ESC / F` for 96-character sets), and then select one of them for the
+//! printable range (GL):
+//!
+//! | Sequence  | Name | Effect                           |
+//! |-----------|------|----------------------------------|
+//! | `SI`      | LS0  | G0 into GL                       |
+//! | `SO`      | LS1  | G1 into GL                       |
+//! | `ESC n`   | LS2  | G2 into GL                       |
+//! | `ESC o`   | LS3  | G3 into GL                       |
+//! | `ESC N c` | SS2  | G2 for the single character `c`  |
+//! | `ESC O c` | SS3  | G3 for the single character `c`  |
+//!
+//! [`VTCharsets`] tracks this state and rewrites `Raw` text into Unicode, so
+//! that (for example) DEC Special Graphics line drawing appears as `┌──┐`
+//! rather than `lqqk`.
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::charset::VTCharsets;
+//! use vt_push_parser::event::VTEvent;
+//!
+//! let mut charsets = VTCharsets::new();
+//! let mut text = String::new();
+//! VTPushParser::decode_buffer(b"\x1b(0lqqk\x1b(B ok", |event| {
+//!     charsets.event(event, |event| {
+//!         if let VTEvent::Raw(raw) = event {
+//!             text.push_str(std::str::from_utf8(raw).unwrap());
+//!         }
+//!     });
+//! });
+//! assert_eq!(text, "┌──┐ ok");
+//! ```
+use crate::ascii::AsciiControl;
+use crate::event::{SS2, SS3, VTEvent};
+
+const SI: u8 = AsciiControl::Si as _;
+const SO: u8 = AsciiControl::So as _;
+
+/// The replacement for characters that are not defined in a character set.
+const UNDEFINED: char = '\u{fffd}';
+
+/// A character set that can be designated into G0 to G3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    /// US ASCII: `B`
+    #[default]
+    Ascii,
+    /// DEC Special Graphics (line drawing): `0`
+    DecSpecialGraphics,
+    /// United Kingdom: `A`
+    Uk,
+    /// DEC Supplemental Graphic: `%5`, or the user-preferred supplemental set
+    /// `<`
+    DecSupplemental,
+    /// DEC Technical: `>`
+    DecTechnical,
+    /// ISO Latin-1 Supplemental, a 96-character set: `A` (with `-`, `.` or
+    /// `/`)
+    Latin1,
+}
+
+impl Charset {
+    /// The character set for a 94-character designation (ie: the bytes after
+    /// `ESC (`, `ESC )`, `ESC *` or `ESC +`).
+    pub fn from_94(designation: &[u8]) -> Option<Self> {
+        Some(match designation {
+            b"B" => Charset::Ascii,
+            b"0" => Charset::DecSpecialGraphics,
+            b"A" => Charset::Uk,
+            b"%5" | b"<" => Charset::DecSupplemental,
+            b">" => Charset::DecTechnical,
+            _ => return None,
+        })
+    }
+
+    /// The character set for a 96-character designation (ie: the bytes after
+    /// `ESC -`, `ESC .` or `ESC /`).
+    pub fn from_96(designation: &[u8]) -> Option<Self> {
+        match designation {
+            b"A" => Some(Charset::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Translates a byte in the printable range (0x20 to 0x7f). Other bytes
+    /// are returned unchanged.
+    pub fn translate(self, b: u8) -> char {
+        if !(0x20..=0x7f).contains(&b) {
+            return b as char;
+        }
+        match self {
+            Charset::Ascii => b as char,
+            Charset::Uk if b == b'#' => '£',
+            Charset::Uk => b as char,
+            Charset::DecSpecialGraphics => match b {
+                0x5f..=0x7e => DEC_SPECIAL_GRAPHICS[(b - 0x5f) as usize],
+                _ => b as char,
+            },
+            Charset::DecSupplemental => match b {
+                // Space and DEL are not part of a 94-character set
+                0x20 | 0x7f => b as char,
+                0x24 | 0x26 | 0x2c..=0x2f | 0x34 | 0x38 | 0x3e | 0x50 | 0x5e | 0x70 | 0x7e => {
+                    UNDEFINED
+                }
+                0x28 => '¤',
+                0x57 => 'Œ',
+                0x5d => 'Ÿ',
+                0x77 => 'œ',
+                0x7d => 'ÿ',
+                // Otherwise the same as Latin-1
+                _ => char::from(b + 0x80),
+            },
+            Charset::DecTechnical => match b {
+                0x21..=0x7e => DEC_TECHNICAL[(b - 0x21) as usize],
+                _ => b as char,
+            },
+            Charset::Latin1 => char::from(b + 0x80),
+        }
+    }
+}
+
+/// DEC Special Graphics, from 0x5f to 0x7e.
+const DEC_SPECIAL_GRAPHICS: [char; 32] = [
+    '\u{a0}', '◆', '▒', '␉', '␌', '␍', '␊', '°', '±', '␤', '␋', '┘', '┐', '┌', '└', '┼', '⎺', '⎻',
+    '─', '⎼', '⎽', '├', '┤', '┴', '┬', '│', '≤', '≥', 'π', '≠', '£', '·',
+];
+
+/// DEC Technical, from 0x21 to 0x7e. The pieces of large summation and
+/// bracket characters from 0x31 to 0x3b have no Unicode equivalent.
+const DEC_TECHNICAL: [char; 94] = [
+    '⎷', '┌', '─', '⌠', '⌡', '│', '⎡', '⎣', '⎤', '⎦', '⎛', '⎝', '⎞', '⎠', '⎨', '⎬', UNDEFINED,
+    UNDEFINED, UNDEFINED, UNDEFINED, UNDEFINED, UNDEFINED, UNDEFINED, UNDEFINED, UNDEFINED,
+    UNDEFINED, UNDEFINED, '≤', '≠', '≥', '∫', '∴', '∝', '∞', '÷', 'Δ', '∇', 'Φ', 'Γ', '∼', '≃',
+    'Θ', '×', 'Λ', '⇔', '⇒', '≡', 'Π', 'Ψ', UNDEFINED, 'Σ', UNDEFINED, UNDEFINED, '√', 'Ω', 'Ξ',
+    'Υ', '⊂', '⊃', '∩', '∪', '∧', '∨', '¬', 'α', 'β', 'χ', 'δ', 'ε', 'φ', 'γ', 'η', 'ι', 'θ', 'κ',
+    'λ', UNDEFINED, 'ν', '∂', 'π', 'ψ', 'ρ', 'σ', 'τ', UNDEFINED, 'ƒ', 'ω', 'ξ', 'υ', 'ζ', '←',
+    '↑', '→', '↓',
+];
+
+/// Tracks character set designations and shifts, and translates text.
+#[derive(Debug, Clone, Default)]
+pub struct VTCharsets {
+    /// The character sets designated into G0 to G3.
+    designations: [Charset; 4],
+    /// The slot that is locked into GL.
+    gl: usize,
+    /// The translated text.
+    buffer: Vec<u8>,
+}
+
+impl VTCharsets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The character set designated into G0 to G3.
+    pub fn designation(&self, slot: usize) -> Charset {
+        self.designations[slot]
+    }
+
+    /// The slot (0 to 3) that is locked into GL.
+    pub fn gl(&self) -> usize {
+        self.gl
+    }
+
+    /// Resets all designations to ASCII and selects G0.
+    pub fn reset(&mut self) {
+        self.designations = Default::default();
+        self.gl = 0;
+    }
+
+    /// Translates a character in the printable range using the character set
+    /// in GL.
+    pub fn translate(&self, b: u8) -> char {
+        self.designations[self.gl].translate(b)
+    }
+
+    /// Handles an event. `Raw` text is translated using the character set in
+    /// GL, and `SS2` and `SS3` are translated into `Raw` text using G2 or G3.
+    /// All other events (including the designations and shifts themselves)
+    /// are passed through unchanged.
+    pub fn event(&mut self, event: VTEvent<'_>, mut cb: impl FnMut(VTEvent<'_>)) {
+        match event {
+            VTEvent::Raw(raw) if self.designations[self.gl] != Charset::Ascii => {
+                let charset = self.designations[self.gl];
+                self.buffer.clear();
+                let mut utf8 = [0; 4];
+                for &b in raw {
+                    if b < 0x80 {
+                        let c = charset.translate(b);
+                        self.buffer
+                            .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                    } else {
+                        self.buffer.push(b);
+                    }
+                }
+                cb(VTEvent::Raw(&self.buffer));
+            }
+            VTEvent::Ss2(SS2 { char }) => self.single_shift(2, char, event, cb),
+            VTEvent::Ss3(SS3 { char }) => self.single_shift(3, char, event, cb),
+            _ => {
+                self.update(&event);
+                cb(event);
+            }
+        }
+    }
+
+    fn single_shift(
+        &mut self,
+        slot: usize,
+        b: u8,
+        event: VTEvent<'_>,
+        mut cb: impl FnMut(VTEvent<'_>),
+    ) {
+        if !(0x20..=0x7f).contains(&b) {
+            cb(event);
+            return;
+        }
+        let mut utf8 = [0; 4];
+        let c = self.designations[slot].translate(b);
+        cb(VTEvent::Raw(c.encode_utf8(&mut utf8).as_bytes()));
+    }
+
+    /// Updates the designations and shifts from an event.
+    pub fn update(&mut self, event: &VTEvent<'_>) {
+        match event {
+            VTEvent::C0(SI) => self.gl = 0,
+            VTEvent::C0(SO) => self.gl = 1,
+            VTEvent::Esc(esc) if esc.private.is_none() => {
+                let Some((&first, rest)) = esc.intermediates.as_ref().split_first() else {
+                    match esc.final_byte {
+                        b'n' => self.gl = 2,
+                        b'o' => self.gl = 3,
+                        // RIS
+                        b'c' => self.reset(),
+                        _ => {}
+                    }
+                    return;
+                };
+                let mut designation = rest.to_vec();
+                designation.push(esc.final_byte);
+                let (slot, charset) = match first {
+                    b'(' | b')' | b'*' | b'+' => {
+                        ((first - b'(') as usize, Charset::from_94(&designation))
+                    }
+                    b'-' | b'.' | b'/' => ((first - b',') as usize, Charset::from_96(&designation)),
+                    _ => return,
+                };
+                if let Some(charset) = charset {
+                    self.designations[slot] = charset;
+                }
+            }
+            // DECSTR
+            VTEvent::Csi(csi)
+                if csi.private.is_none()
+                    && csi.intermediates.as_ref() == b"!"
+                    && csi.final_byte == b'p' =>
+            {
+                self.reset()
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VTPushParser;
+
+    fn translate(input: &[u8]) -> String {
+        let mut charsets = VTCharsets::new();
+        let mut text = vec![];
+        let mut parser = VTPushParser::new();
+        for chunk in input.chunks(3) {
+            parser.feed_with(chunk, |event: VTEvent| {
+                charsets.event(event, |event| match event {
+                    VTEvent::Raw(raw) => text.extend_from_slice(raw),
+                    VTEvent::C0(b'\n') => text.push(b'\n'),
+                    _ => {}
+                })
+            });
+        }
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn test_special_graphics() {
+        assert_eq!(
+            translate(b"\x1b(0lqwqk\nx x x\nmqvqj\x1b(B lqk"),
+            "┌─┬─┐\n│ │ │\n└─┴─┘ lqk"
+        );
+        // Designated into G1 and shifted in with SO
+        assert_eq!(translate(b"\x1b)0a\x0eaa\x0fa"), "a▒▒a");
+        // UTF-8 text is passed through
+        assert_eq!(translate("\x1b(0x✅x".as_bytes()), "│✅│");
+    }
+
+    #[test]
+    fn test_single_shift() {
+        assert_eq!(translate(b"\x1b*>\x1b+A\x1bNa\x1bO#b\x1bNz#"), "α£bζ#");
+        // Locking shifts
+        assert_eq!(translate(b"\x1b*>\x1bnDz\x1b(B\x0fz"), "Δζz");
+    }
+
+    #[test]
+    fn test_sets() {
+        assert_eq!(translate(b"\x1b(A#1"), "£1");
+        assert_eq!(translate(b"\x1b(%5(W}$"), "¤Œÿ\u{fffd}");
+        assert_eq!(translate(b"\x1b(<+"), "«");
+        assert_eq!(translate(b"\x1b-A\x0ei"), "é");
+        assert_eq!(translate(b"\x1b(>\x21{}"), "⎷←→");
+        // Reset by RIS and DECSTR
+        assert_eq!(translate(b"\x1b(0q\x1bcq\x1b(0q\x1b[!pq"), "─q─q");
+        // Unknown designations are ignored
+        assert_eq!(translate(b"\x1b(0q\x1b(Zq"), "──");
+    }
+}
@@ -0,0 +1,413 @@
+//! Color depth downgrading.
+//!
+//! Terminals that don't support 24-bit color may show `38;2;r;g;b` SGR
+//! sequences in the wrong color, or not at all. [`VTColorDowngrader`] rewrites
+//! the color parameters of SGR sequences to the nearest color that a
+//! [`VTColorDepth`] can show, and passes all other events through unchanged.
+//! With [`VTColorDepth::None`] (eg: for `NO_COLOR`), colors are removed but
+//! other attributes such as bold and underline are kept.
+//!
+//! Colors are matched by their distance in the Oklab color space, using the
+//! default xterm palette.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use vt_push_parser::color::{VTColorDepth, VTColorWriter};
+//!
+//! let mut writer = VTColorWriter::new(VTColorDepth::Ansi256, Vec::new());
+//! writer.write_all(b"\x1b[1;38;2;255;0;0mred\x1b[0m").unwrap();
+//! assert_eq!(writer.into_inner(), b"\x1b[1;38;5;196mred\x1b[0m");
+//! ```
+use std::io;
+use std::sync::OnceLock;
+
+use crate::event::{CSI, ParamBuf, ParamBufOwned, VTEvent};
+use crate::{VTEventCallback, VTPushParser};
+
+/// The colors that a terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VTColorDepth {
+    /// No colors.
+    None,
+    /// The 16 ANSI colors.
+    Ansi16,
+    /// The 256-color palette.
+    Ansi256,
+    /// 24-bit color.
+    TrueColor,
+}
+
+impl VTColorDepth {
+    /// Guesses the color depth of the terminal from the `NO_COLOR`,
+    /// `COLORTERM` and `TERM` environment variables.
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        if !var("NO_COLOR").is_empty() || term == "dumb" {
+            Self::None
+        } else if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi16
+        }
+    }
+}
+
+/// The colors of the default xterm palette.
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..16 => ANSI[index as usize],
+        16..232 => {
+            let i = (index - 16) as usize;
+            (CUBE[i / 36], CUBE[i / 6 % 6], CUBE[i % 6])
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+fn oklab((r, g, b): (u8, u8, u8)) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let l = (0.41222146 * r + 0.53633255 * g + 0.051445995 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+fn nearest(rgb: (u8, u8, u8), candidates: std::ops::Range<usize>) -> u8 {
+    static PALETTE: OnceLock<[[f32; 3]; 256]> = OnceLock::new();
+    let palette = PALETTE.get_or_init(|| std::array::from_fn(|i| oklab(palette_rgb(i as u8))));
+    let [l, a, b] = oklab(rgb);
+    let distance = |i: &usize| {
+        let [l2, a2, b2] = palette[*i];
+        (l - l2).powi(2) + (a - a2).powi(2) + (b - b2).powi(2)
+    };
+    candidates
+        .min_by(|x, y| distance(x).total_cmp(&distance(y)))
+        .unwrap() as u8
+}
+
+/// The nearest color in the 256-color palette, excluding the 16 ANSI colors
+/// (which are often changed by color schemes).
+pub fn nearest_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    nearest(rgb, 16..256)
+}
+
+/// The nearest of the 16 ANSI colors.
+pub fn nearest_ansi16(rgb: (u8, u8, u8)) -> u8 {
+    nearest(rgb, 0..16)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Parses an extended color after `38`, `48` or `58`, as the colon-separated
+/// values of the same parameter or the following parameters. Returns the
+/// color and the number of parameters used.
+fn parse_color(params: &[&[u8]]) -> Option<(Color, usize)> {
+    let value = |p: &[u8]| -> Option<u8> {
+        if p.is_empty() {
+            return Some(0);
+        }
+        std::str::from_utf8(p).ok()?.parse().ok()
+    };
+    let first = params.first()?;
+    if first.contains(&b':') {
+        let sub = first.split(|&b| b == b':').skip(1).collect::<Vec<_>>();
+        let color = match sub.as_slice() {
+            [b"5", n] => Color::Indexed(value(n)?),
+            // With and without the color space ID
+            [b"2", _, r, g, b] | [b"2", r, g, b] => Color::Rgb(value(r)?, value(g)?, value(b)?),
+            _ => return None,
+        };
+        return Some((color, 1));
+    }
+    match params.get(1..)? {
+        [b"5", n, ..] => Some((Color::Indexed(value(n)?), 3)),
+        [b"2", r, g, b, ..] => Some((Color::Rgb(value(r)?, value(g)?, value(b)?), 5)),
+        _ => None,
+    }
+}
+
+/// Downgrades the parameters of an SGR sequence, returning `None` if they are
+/// unchanged.
+fn downgrade_sgr(depth: VTColorDepth, params: ParamBuf<'_>) -> Option<Vec<Vec<u8>>> {
+    let params = params.into_iter().collect::<Vec<_>>();
+    let mut output = Vec::with_capacity(params.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < params.len() {
+        let code = params[i].split(|&b| b == b':').next().unwrap_or_default();
+        let code = std::str::from_utf8(code)
+            .ok()
+            .and_then(|s| s.parse::<u16>().ok());
+        match code {
+            Some(base @ (38 | 48 | 58)) => {
+                let Some((color, len)) = parse_color(&params[i..]) else {
+                    // Leave malformed colors, and everything after them, as-is
+                    output.extend(params[i..].iter().map(|p| p.to_vec()));
+                    break;
+                };
+                let replacement = match (depth, color) {
+                    (VTColorDepth::TrueColor, _) | (VTColorDepth::Ansi256, Color::Indexed(_)) => {
+                        None
+                    }
+                    (VTColorDepth::Ansi256, Color::Rgb(r, g, b)) => {
+                        Some(vec![base, 5, nearest_ansi256((r, g, b)) as u16])
+                    }
+                    (VTColorDepth::Ansi16, color) => {
+                        let index = match color {
+                            Color::Indexed(n) if n < 16 => n,
+                            Color::Indexed(n) => nearest_ansi16(palette_rgb(n)),
+                            Color::Rgb(r, g, b) => nearest_ansi16((r, g, b)),
+                        } as u16;
+                        // There is no 16-color form for the underline color
+                        match (base, index) {
+                            (58, _) => Some(vec![]),
+                            (_, 0..8) => Some(vec![base - 8 + index]),
+                            (_, _) => Some(vec![base + 52 + index - 8]),
+                        }
+                    }
+                    (VTColorDepth::None, _) => Some(vec![]),
+                };
+                match replacement {
+                    Some(replacement) => {
+                        changed = true;
+                        output.extend(replacement.iter().map(|n| n.to_string().into_bytes()));
+                    }
+                    None => output.extend(params[i..i + len].iter().map(|p| p.to_vec())),
+                }
+                i += len;
+            }
+            Some(30..=37 | 39 | 40..=47 | 49 | 59 | 90..=97 | 100..=107)
+                if depth == VTColorDepth::None =>
+            {
+                changed = true;
+                i += 1;
+            }
+            _ => {
+                output.push(params[i].to_vec());
+                i += 1;
+            }
+        }
+    }
+    changed.then_some(output)
+}
+
+/// A [`VTEventCallback`] that downgrades the colors of SGR sequences before
+/// passing events to another callback. See the [module documentation](self).
+#[derive(Debug)]
+pub struct VTColorDowngrader<C> {
+    depth: VTColorDepth,
+    cb: C,
+}
+
+impl<C: VTEventCallback> VTColorDowngrader<C> {
+    pub fn new(depth: VTColorDepth, cb: C) -> Self {
+        Self { depth, cb }
+    }
+
+    pub fn depth(&self) -> VTColorDepth {
+        self.depth
+    }
+
+    pub fn into_inner(self) -> C {
+        self.cb
+    }
+}
+
+impl<C: VTEventCallback> VTEventCallback for VTColorDowngrader<C> {
+    fn event(&mut self, event: VTEvent<'_>) {
+        let VTEvent::Csi(csi) = &event else {
+            return VTEventCallback::event(&mut self.cb, event);
+        };
+        if csi.final_byte != b'm' || csi.private.is_some() || !csi.intermediates.is_empty() {
+            return VTEventCallback::event(&mut self.cb, event);
+        }
+        let Some(params) = downgrade_sgr(self.depth, csi.params) else {
+            return VTEventCallback::event(&mut self.cb, event);
+        };
+        // Removing every parameter would make this a reset
+        if params.is_empty() {
+            return;
+        }
+        let params = ParamBufOwned::new(&params.iter().map(|p| p.as_slice()).collect::<Vec<_>>());
+        VTEventCallback::event(
+            &mut self.cb,
+            VTEvent::Csi(CSI {
+                private: None,
+                params: params.borrow(),
+                intermediates: csi.intermediates,
+                final_byte: b'm',
+            }),
+        );
+    }
+}
+
+impl<C: VTEventCallback> VTEventCallback for &mut VTColorDowngrader<C> {
+    fn event(&mut self, event: VTEvent<'_>) {
+        VTEventCallback::event(&mut **self, event)
+    }
+}
+
+/// A writer that downgrades the colors in the data written to it and writes
+/// the result to the underlying writer.
+///
+/// The data is parsed and re-encoded, so incomplete escape sequences at the
+/// end of the data are held until the next write, and invalid sequences may
+/// be re-encoded differently. Due to limitations of the [`std::io::Write`]
+/// interface, if the underlying writer returns an error, it may be uncertain
+/// how many bytes were written.
+pub struct VTColorWriter<W: io::Write> {
+    writer: W,
+    depth: VTColorDepth,
+    parser: VTPushParser,
+}
+
+impl<W: io::Write> VTColorWriter<W> {
+    pub fn new(depth: VTColorDepth, writer: W) -> Self {
+        Self {
+            writer,
+            depth,
+            parser: VTPushParser::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: io::Write> io::Write for VTColorWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut error = None;
+        let writer = &mut self.writer;
+        let downgrader = VTColorDowngrader::new(self.depth, |event: VTEvent| {
+            if error.is_none()
+                && let Err(e) = event.write_to(&mut *writer)
+            {
+                error = Some(e);
+            }
+        });
+        self.parser.feed_with(buf, downgrader);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn downgrade(depth: VTColorDepth, input: &[u8]) -> String {
+        let mut writer = VTColorWriter::new(depth, Vec::new());
+        for chunk in input.chunks(4) {
+            writer.write_all(chunk).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_nearest() {
+        assert_eq!(nearest_ansi256((255, 0, 0)), 196);
+        assert_eq!(nearest_ansi256((0, 0, 0)), 16);
+        assert_eq!(nearest_ansi256((128, 128, 128)), 244);
+        assert_eq!(nearest_ansi16((255, 0, 0)), 9);
+        assert_eq!(nearest_ansi16((200, 10, 10)), 1);
+        assert_eq!(nearest_ansi16((120, 120, 120)), 8);
+    }
+
+    #[test]
+    fn test_ansi256() {
+        let input = b"\x1b[38;2;255;0;0;48:2::0:0:255;4mx\x1b[38;5;42;58:2:1:2:3m\x1b[?1m";
+        assert_eq!(
+            downgrade(VTColorDepth::Ansi256, input),
+            "\x1b[38;5;196;48;5;21;4mx\x1b[38;5;42;58;5;232m\x1b[?1m"
+        );
+        assert_eq!(
+            downgrade(VTColorDepth::TrueColor, input),
+            String::from_utf8_lossy(input)
+        );
+    }
+
+    #[test]
+    fn test_ansi16() {
+        assert_eq!(
+            downgrade(
+                VTColorDepth::Ansi16,
+                b"\x1b[38;2;255;0;0;48;5;18;1mx\x1b[38;5;3;58;5;1m\x1b[31;100m"
+            ),
+            "\x1b[91;44;1mx\x1b[33m\x1b[31;100m"
+        );
+    }
+
+    #[test]
+    fn test_none() {
+        assert_eq!(
+            downgrade(
+                VTColorDepth::None,
+                b"\x1b[1;31;48;2;1;2;3;4mbold\x1b[38;5;1m\x1b[39;49m\x1b[m\x1b[0;32m"
+            ),
+            "\x1b[1;4mbold\x1b[m\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(
+            downgrade(VTColorDepth::Ansi16, b"\x1b[1;38;2;255m\x1b[38;9;1;31m"),
+            "\x1b[1;38;2;255m\x1b[38;9;1;31m"
+        );
+    }
+}
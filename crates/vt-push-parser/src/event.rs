@@ -345,6 +345,92 @@ impl<'a> NumericParamBuf<'a> {
     }
 }
 
+/// A zero-copy view of the `;`-separated fields of an OSC string (eg: the
+/// data of an `OscEnd` event, or an OSC string collected from `OscData`).
+///
+/// The first field is the numeric command, if any. Use
+/// [`OscParams::remainder`] for a trailing field that may itself contain
+/// semicolons (eg: the URI of OSC 8).
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct OscParams<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> IntoIterator for OscParams<'a> {
+    type Item = &'a [u8];
+    type IntoIter = std::iter::Take<std::slice::Split<'a, u8, fn(&u8) -> bool>>;
+    fn into_iter(self) -> Self::IntoIter {
+        let fn1: fn(&u8) -> bool = |c: &u8| *c == b';';
+        self.data.split(fn1).take(self.len())
+    }
+}
+
+impl<'a> OscParams<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        OscParams { data }
+    }
+
+    pub const fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The numeric command in the first field, if it is a number.
+    pub fn command(&self) -> Option<u16> {
+        let command = self.get(0)?;
+        if command.is_empty() || !command.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(command).ok()?.parse().ok()
+    }
+
+    /// The number of fields. An empty string has no fields.
+    pub fn len(&self) -> usize {
+        if self.data.is_empty() {
+            0
+        } else {
+            self.data.iter().filter(|c| **c == b';').count() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a [u8]> {
+        self.into_iter().nth(index)
+    }
+
+    pub fn try_parse<T: std::str::FromStr>(&self, index: usize) -> Option<T> {
+        self.get(index).and_then(|p| {
+            std::str::from_utf8(p)
+                .ok()
+                .and_then(|s| s.parse::<T>().ok())
+        })
+    }
+
+    /// The rest of the string starting at the given field, including any
+    /// further separators.
+    pub fn remainder(&self, index: usize) -> Option<&'a [u8]> {
+        if index >= self.len() {
+            return None;
+        }
+        let start = if index == 0 {
+            0
+        } else {
+            self.data
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == b';')
+                .nth(index - 1)?
+                .0
+                + 1
+        };
+        Some(&self.data[start..])
+    }
+}
+
 /// A union of all possible events that can be emitted by the parser, with
 /// borrowed data.
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
@@ -378,7 +464,15 @@ pub enum VTEvent<'a> {
     DcsCancel,
 
     // OSC stream
-    OscStart,
+    /// Emitted once the OSC command has been read, ie: at the first byte
+    /// after its digits, so an OSC that ends the input before then has not
+    /// emitted anything yet. An OSC cancelled by CAN or SUB before that point
+    /// emits `OscStart { command: None }` and `OscCancel`.
+    OscStart {
+        /// The numeric command before the first `;`, if any. The command is
+        /// also included in the OSC data.
+        command: Option<u16>,
+    },
     OscData(&'a [u8]),
     OscEnd {
         data: &'a [u8],
@@ -416,7 +510,10 @@ impl<'a> std::fmt::Debug for VTEvent<'a> {
                 Ok(())
             }
             DcsCancel => write!(f, "DcsCancel"),
-            OscStart => write!(f, "OscStart"),
+            OscStart { command: None } => write!(f, "OscStart"),
+            OscStart {
+                command: Some(command),
+            } => write!(f, "OscStart({command})"),
             OscData(s) | OscEnd { data: s, .. } => {
                 if matches!(self, OscEnd { .. }) {
                     write!(f, "OscEnd('")?;
@@ -478,7 +575,7 @@ impl<'a> VTEvent<'a> {
             DcsData(s) => s.len(),
             DcsEnd(s) => s.len() + 2,
             DcsCancel => 1,
-            OscStart => 2,
+            OscStart { .. } => 2,
             OscData(s) => s.len(),
             OscEnd { data, used_bel } => {
                 if *used_bel {
@@ -611,7 +708,7 @@ impl<'a> VTEvent<'a> {
                 buf[0] = ESC;
                 buf[1] = ST_FINAL;
             }
-            OscStart => {
+            OscStart { .. } => {
                 buf[0] = ESC;
                 buf[1] = OSC;
             }
@@ -757,7 +854,7 @@ impl<'a> VTEvent<'a> {
                 writer.write_all(&[ESC, ST_FINAL])?;
                 len += 2;
             }
-            OscStart => {
+            OscStart { .. } => {
                 writer.write_all(&[ESC, OSC])?;
                 len = 2;
             }
@@ -790,16 +887,11 @@ impl<'a> VTEvent<'a> {
                 intermediates: csi.intermediates,
                 final_byte: csi.final_byte,
             }),
-            DcsStart(dcs_start) => VTOwnedEvent::DcsStart(DCSOwned {
-                private: dcs_start.private,
-                params: dcs_start.params.to_owned(),
-                intermediates: dcs_start.intermediates,
-                final_byte: dcs_start.final_byte,
-            }),
+            DcsStart(dcs_start) => VTOwnedEvent::DcsStart(dcs_start.to_owned()),
             DcsData(s) => VTOwnedEvent::DcsData(s.to_vec()),
             DcsEnd(s) => VTOwnedEvent::DcsEnd(s.to_vec()),
             DcsCancel => VTOwnedEvent::DcsCancel,
-            OscStart => VTOwnedEvent::OscStart,
+            OscStart { command } => VTOwnedEvent::OscStart { command: *command },
             OscData(s) => VTOwnedEvent::OscData(s.to_vec()),
             OscEnd { data, used_bel } => VTOwnedEvent::OscEnd {
                 data: data.to_vec(),
@@ -896,7 +988,7 @@ pub enum VTOwnedEvent {
     DcsData(Vec<u8>),
     DcsEnd(Vec<u8>),
     DcsCancel,
-    OscStart,
+    OscStart { command: Option<u16> },
     OscData(Vec<u8>),
     OscEnd { data: Vec<u8>, used_bel: bool },
     OscCancel,
@@ -923,16 +1015,11 @@ impl VTOwnedEvent {
                 intermediates: csi.intermediates,
                 final_byte: csi.final_byte,
             }),
-            VTOwnedEvent::DcsStart(dcs_start) => VTEvent::DcsStart(DCS {
-                private: dcs_start.private,
-                params: dcs_start.params.borrow(),
-                intermediates: dcs_start.intermediates,
-                final_byte: dcs_start.final_byte,
-            }),
+            VTOwnedEvent::DcsStart(dcs_start) => VTEvent::DcsStart(dcs_start.borrow()),
             VTOwnedEvent::DcsData(s) => VTEvent::DcsData(s),
             VTOwnedEvent::DcsEnd(s) => VTEvent::DcsEnd(s),
             VTOwnedEvent::DcsCancel => VTEvent::DcsCancel,
-            VTOwnedEvent::OscStart => VTEvent::OscStart,
+            VTOwnedEvent::OscStart { command } => VTEvent::OscStart { command: *command },
             VTOwnedEvent::OscData(s) => VTEvent::OscData(s),
             VTOwnedEvent::OscEnd { data, used_bel } => VTEvent::OscEnd {
                 data,
@@ -1064,6 +1151,17 @@ pub struct DCS<'a> {
     pub final_byte: u8,
 }
 
+impl<'a> DCS<'a> {
+    pub fn to_owned(&self) -> DCSOwned {
+        DCSOwned {
+            private: self.private,
+            params: self.params.to_owned(),
+            intermediates: self.intermediates,
+            final_byte: self.final_byte,
+        }
+    }
+}
+
 impl<'a> std::fmt::Debug for DCS<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "DcsStart(")?;
@@ -1116,6 +1214,17 @@ pub struct DCSOwned {
     pub final_byte: u8,
 }
 
+impl DCSOwned {
+    pub fn borrow(&self) -> DCS<'_> {
+        DCS {
+            private: self.private,
+            params: self.params.borrow(),
+            intermediates: self.intermediates,
+            final_byte: self.final_byte,
+        }
+    }
+}
+
 impl std::fmt::Debug for DCSOwned {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "DcsStart(")?;
@@ -1168,4 +1277,31 @@ mod test {
             &[1, 2, 3]
         );
     }
+
+    #[test]
+    fn test_osc_params() {
+        let params = OscParams::new(b"8;id=1;http://x/?a=1;b=2");
+        assert_eq!(params.command(), Some(8));
+        assert_eq!(params.len(), 4);
+        assert_eq!(params.get(1), Some(&b"id=1"[..]));
+        assert_eq!(params.get(4), None);
+        assert_eq!(params.try_parse::<u8>(0), Some(8));
+        assert_eq!(params.remainder(2), Some(&b"http://x/?a=1;b=2"[..]));
+        assert_eq!(params.remainder(4), None);
+        assert_eq!(
+            params.into_iter().collect::<Vec<_>>(),
+            [&b"8"[..], b"id=1", b"http://x/?a=1", b"b=2"]
+        );
+
+        assert_eq!(OscParams::new(b"133;A").command(), Some(133));
+        assert_eq!(OscParams::new(b"title").command(), None);
+        assert_eq!(OscParams::new(b"+1;x").command(), None);
+        assert_eq!(OscParams::new(b";").len(), 2);
+        assert_eq!(OscParams::new(b";").remainder(1), Some(&b""[..]));
+        let empty = OscParams::new(b"");
+        assert!(empty.is_empty());
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.into_iter().count(), 0);
+        assert_eq!(empty.command(), None);
+    }
 }
@@ -0,0 +1,249 @@
+//! Fine-grained event filtering.
+//!
+//! The `INTEREST` bits of a [`VTPushParser`](crate::VTPushParser) select whole
+//! categories of events. A [`VTFilter`] narrows these down to individual CSI
+//! final bytes (optionally per private marker) and OSC command numbers. The
+//! parser consults the filter before building an event, and sequences that
+//! are rejected early (eg: a CSI private marker with no accepted finals, or an
+//! OSC command number) are consumed without buffering.
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::event::VTEvent;
+//! use vt_push_parser::filter::VTFilter;
+//!
+//! let mut parser = VTPushParser::new();
+//! parser.set_filter(Some(VTFilter::none().with_csi(b'm').with_csi(b'H').with_osc(8)));
+//! let mut events = vec![];
+//! parser.feed_with(
+//!     b"\x1b[1m\x1b[2J\x1b[?25l\x1b]0;title\x07\x1b]8;;http://x\x07",
+//!     |event: VTEvent| events.push(format!("{event:?}")),
+//! );
+//! assert_eq!(events, ["Csi('1', '', 'm')", "OscStart(8)", "OscEnd('8;;http://x')"]);
+//! ```
+
+/// A set of bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    const ALL: Self = Self([u64::MAX; 4]);
+
+    fn insert(&mut self, b: u8) {
+        self.0[b as usize / 64] |= 1 << (b % 64);
+    }
+
+    fn contains(&self, b: u8) -> bool {
+        self.0[b as usize / 64] & (1 << (b % 64)) != 0
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0 == [0; 4]
+    }
+}
+
+/// The private markers that a CSI sequence may start with.
+const PRIVATE_MARKERS: [u8; 4] = [b'<', b'=', b'>', b'?'];
+
+/// Accepted CSI final bytes and OSC commands. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VTFilter {
+    /// The accepted CSI finals with no private marker, then for each of
+    /// [`PRIVATE_MARKERS`].
+    csi: [ByteSet; 5],
+    /// The accepted OSC command numbers, sorted, or `None` to accept all OSC
+    /// strings.
+    osc: Option<Vec<u16>>,
+}
+
+impl Default for VTFilter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl VTFilter {
+    /// A filter that accepts everything.
+    pub fn all() -> Self {
+        Self {
+            csi: [ByteSet::ALL; 5],
+            osc: None,
+        }
+    }
+
+    /// A filter that rejects all CSI sequences and OSC strings.
+    pub fn none() -> Self {
+        Self {
+            csi: [ByteSet::default(); 5],
+            osc: Some(vec![]),
+        }
+    }
+
+    fn csi_index(private: Option<u8>) -> Option<usize> {
+        match private {
+            None => Some(0),
+            Some(p) => PRIVATE_MARKERS.iter().position(|&m| m == p).map(|i| i + 1),
+        }
+    }
+
+    /// Accept CSI sequences with this final byte, with or without a private
+    /// marker.
+    pub fn with_csi(mut self, final_byte: u8) -> Self {
+        for set in &mut self.csi {
+            set.insert(final_byte);
+        }
+        self
+    }
+
+    /// Accept CSI sequences with this private marker (eg: `Some(b'?')`) and
+    /// final byte.
+    pub fn with_csi_private(mut self, private: Option<u8>, final_byte: u8) -> Self {
+        if let Some(i) = Self::csi_index(private) {
+            self.csi[i].insert(final_byte);
+        }
+        self
+    }
+
+    /// Accept all CSI sequences.
+    pub fn with_all_csi(mut self) -> Self {
+        self.csi = [ByteSet::ALL; 5];
+        self
+    }
+
+    /// Accept OSC strings with this command number.
+    pub fn with_osc(mut self, command: u16) -> Self {
+        if let Some(osc) = &mut self.osc
+            && let Err(i) = osc.binary_search(&command)
+        {
+            osc.insert(i, command);
+        }
+        self
+    }
+
+    /// Accept all OSC strings, including those without a command number.
+    pub fn with_all_osc(mut self) -> Self {
+        self.osc = None;
+        self
+    }
+
+    /// Whether a CSI sequence is accepted.
+    pub fn accepts_csi(&self, private: Option<u8>, final_byte: u8) -> bool {
+        Self::csi_index(private).is_some_and(|i| self.csi[i].contains(final_byte))
+    }
+
+    /// Whether any CSI sequence with this private marker is accepted.
+    pub fn accepts_csi_private(&self, private: Option<u8>) -> bool {
+        Self::csi_index(private).is_some_and(|i| !self.csi[i].is_empty())
+    }
+
+    /// Whether any CSI sequence is accepted.
+    pub fn accepts_any_csi(&self) -> bool {
+        self.csi.iter().any(|set| !set.is_empty())
+    }
+
+    /// Whether OSC strings are filtered by their command number.
+    pub fn filters_osc(&self) -> bool {
+        self.osc.is_some()
+    }
+
+    /// Whether any OSC string is accepted.
+    pub fn accepts_any_osc(&self) -> bool {
+        self.osc.as_ref().is_none_or(|osc| !osc.is_empty())
+    }
+
+    /// Whether an OSC string with this command number (or `None` if it
+    /// doesn't start with a number) is accepted.
+    pub fn accepts_osc(&self, command: Option<u16>) -> bool {
+        match (&self.osc, command) {
+            (None, _) => true,
+            (Some(osc), Some(command)) => osc.binary_search(&command).is_ok(),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VTPushParser;
+    use crate::event::VTEvent;
+
+    fn parse(filter: VTFilter, input: &[u8]) -> Vec<String> {
+        let mut parser = VTPushParser::new();
+        parser.set_filter(Some(filter));
+        let mut events = vec![];
+        for chunk in input.chunks(2) {
+            parser.feed_with(chunk, |event: VTEvent| events.push(format!("{event:?}")));
+        }
+        events
+    }
+
+    #[test]
+    fn test_csi() {
+        let filter = VTFilter::none()
+            .with_csi(b'm')
+            .with_csi_private(Some(b'?'), b'h');
+        assert_eq!(
+            parse(
+                filter,
+                b"\x1b[31ma\x1b[?25h\x1b[4h\x1b[>1;2m\x1b[?25l\x1b[=5h\x1b[Hb"
+            ),
+            [
+                "Csi('31', '', 'm')",
+                "Raw('a')",
+                "Csi('?', '25', '', 'h')",
+                "Csi('>', '1', '2', '', 'm')",
+                "Raw('b')"
+            ]
+        );
+        // No CSI sequences at all
+        assert_eq!(
+            parse(
+                VTFilter::none().with_all_osc(),
+                b"\x1b[1mx\x1b[?1h\x1b]0;t\x07"
+            ),
+            ["Raw('x')", "OscStart(0)", "OscData('0;')", "OscEnd('t')"]
+        );
+    }
+
+    #[test]
+    fn test_osc() {
+        let filter = VTFilter::none().with_osc(8).with_osc(133);
+        assert_eq!(
+            parse(
+                filter.clone(),
+                b"\x1b]0;title\x07\x1b]133;A\x1b\\\x1b]13;x\x07\x1b]8\x07\x1b]L\x07\x1b]1330;x\x1b\\"
+            ),
+            [
+                "OscStart(133)",
+                "OscData('133')",
+                "OscData(';')",
+                "OscData('A')",
+                "OscEnd('')",
+                "OscStart(8)",
+                "OscEnd('8')",
+            ]
+        );
+        // Rejected OSC strings are cancelled as usual, and don't affect the
+        // text that follows
+        assert_eq!(
+            parse(filter.clone(), b"\x1b]2;a\x18b\x1b]2;\x1b\x1b\\c"),
+            ["Raw('b')", "Raw('c')"]
+        );
+        assert_eq!(parse(filter, b"\x1b]13\x18d"), ["Raw('d')"]);
+    }
+
+    #[test]
+    fn test_accepts() {
+        let filter = VTFilter::none().with_csi_private(Some(b'>'), b'c');
+        assert!(filter.accepts_csi(Some(b'>'), b'c'));
+        assert!(!filter.accepts_csi(None, b'c'));
+        assert!(!filter.accepts_csi(Some(b'!'), b'c'));
+        assert!(filter.accepts_csi_private(Some(b'>')));
+        assert!(!filter.accepts_csi_private(Some(b'?')));
+        assert!(!filter.accepts_any_osc());
+        assert!(VTFilter::all().accepts_osc(None));
+        assert!(!VTFilter::none().with_osc(1).accepts_osc(None));
+    }
+}
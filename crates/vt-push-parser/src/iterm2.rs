@@ -0,0 +1,547 @@
+//! iTerm2 OSC 1337 extensions.
+//!
+//! iTerm2 uses `OSC 1337 ; Command ST` for its proprietary extensions. The
+//! most common is the inline image protocol, which transfers a file as a
+//! header of `key=value` arguments followed by a base64-encoded body:
+//!
+//! ```text
+//! OSC 1337 ; File = name=<base64>;size=<bytes>;inline=1 : <base64> BEL
+//! ```
+//!
+//! [`ITerm2Decoder`] is fed with the OSC events from the parser. It parses the
+//! header of a `File` command and then decodes the body as it arrives, so the
+//! file is never held in memory. The other commands are decoded into an
+//! [`ITerm2Command`] once the OSC string ends.
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::iterm2::{ITerm2Decoder, ITerm2Event};
+//!
+//! let mut decoder = ITerm2Decoder::new();
+//! let mut file = vec![];
+//! VTPushParser::decode_buffer(b"\x1b]1337;File=inline=1;size=5:aGVsbG8=\x07", |event| {
+//!     decoder.event(&event, |event| match event {
+//!         ITerm2Event::FileStart(header) => assert_eq!(header.size, Some(5)),
+//!         ITerm2Event::FileData(data) => file.extend_from_slice(data),
+//!         _ => {}
+//!     });
+//! });
+//! assert_eq!(file, b"hello");
+//! ```
+use std::fmt;
+use std::io;
+
+use crate::event::{OscParams, VTEvent};
+
+/// The OSC command number of iTerm2's extensions.
+pub const ITERM2_OSC: u16 = 1337;
+
+/// The maximum length of a file header or a command other than `File` that
+/// will be assembled.
+const MAX_COMMAND_LEN: usize = 4096;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// An incremental base64 decoder. Bytes outside of the base64 alphabet
+/// (including padding) are skipped.
+#[derive(Debug, Clone, Copy, Default)]
+struct Base64Decoder {
+    bits: u32,
+    len: u8,
+}
+
+impl Base64Decoder {
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &b in input {
+            let value = match b {
+                b'A'..=b'Z' => b - b'A',
+                b'a'..=b'z' => b - b'a' + 26,
+                b'0'..=b'9' => b - b'0' + 52,
+                b'+' | b'-' => 62,
+                b'/' | b'_' => 63,
+                _ => continue,
+            };
+            self.bits = self.bits << 6 | value as u32;
+            self.len += 6;
+            if self.len >= 8 {
+                self.len -= 8;
+                out.push((self.bits >> self.len) as u8);
+            }
+        }
+    }
+
+    fn decode_all(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() / 4 * 3);
+        Self::default().decode(input, &mut out);
+        out
+    }
+}
+
+/// The width or height of an inline image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ITerm2Dimension {
+    /// `N`: a number of character cells.
+    Cells(u32),
+    /// `Npx`: a number of pixels.
+    Pixels(u32),
+    /// `N%`: a percentage of the session's width or height.
+    Percent(u32),
+    /// `auto`: the image's own size.
+    Auto,
+}
+
+impl ITerm2Dimension {
+    pub fn parse(value: &str) -> Option<Self> {
+        if value == "auto" {
+            Some(Self::Auto)
+        } else if let Some(px) = value.strip_suffix("px") {
+            px.parse().ok().map(Self::Pixels)
+        } else if let Some(percent) = value.strip_suffix('%') {
+            percent.parse().ok().map(Self::Percent)
+        } else {
+            value.parse().ok().map(Self::Cells)
+        }
+    }
+}
+
+impl fmt::Display for ITerm2Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cells(n) => write!(f, "{n}"),
+            Self::Pixels(n) => write!(f, "{n}px"),
+            Self::Percent(n) => write!(f, "{n}%"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// The header of a `File` command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ITerm2File {
+    /// The file name, which is base64-encoded on the wire.
+    pub name: Option<String>,
+    /// The size of the file in bytes.
+    pub size: Option<u64>,
+    pub width: Option<ITerm2Dimension>,
+    pub height: Option<ITerm2Dimension>,
+    pub preserve_aspect_ratio: Option<bool>,
+    /// Whether the file is displayed inline rather than downloaded.
+    pub inline: bool,
+    /// Any other arguments, in order.
+    pub other: Vec<(String, String)>,
+}
+
+impl ITerm2File {
+    /// Parses the arguments of a `File` command, between `File=` and `:`.
+    pub fn parse(args: &[u8]) -> Self {
+        let mut file = Self::default();
+        let args = String::from_utf8_lossy(args);
+        for arg in args.split(';').filter(|arg| !arg.is_empty()) {
+            let (key, value) = arg.split_once('=').unwrap_or((arg, ""));
+            match key {
+                "name" => {
+                    let name = Base64Decoder::decode_all(value.as_bytes());
+                    file.name = Some(String::from_utf8_lossy(&name).into_owned());
+                }
+                "size" => file.size = value.parse().ok(),
+                "width" => file.width = ITerm2Dimension::parse(value),
+                "height" => file.height = ITerm2Dimension::parse(value),
+                "preserveAspectRatio" => file.preserve_aspect_ratio = Some(value != "0"),
+                "inline" => file.inline = value == "1",
+                _ => file.other.push((key.to_owned(), value.to_owned())),
+            }
+        }
+        file
+    }
+
+    /// Writes the `File` command with the given file contents.
+    pub fn write_to(&self, body: &[u8], writer: impl io::Write) -> io::Result<usize> {
+        let mut args = vec![];
+        if let Some(name) = &self.name {
+            args.push(format!("name={}", base64_encode(name.as_bytes())));
+        }
+        if let Some(size) = self.size {
+            args.push(format!("size={size}"));
+        }
+        if let Some(width) = self.width {
+            args.push(format!("width={width}"));
+        }
+        if let Some(height) = self.height {
+            args.push(format!("height={height}"));
+        }
+        if let Some(preserve) = self.preserve_aspect_ratio {
+            args.push(format!("preserveAspectRatio={}", preserve as u8));
+        }
+        if self.inline {
+            args.push("inline=1".to_owned());
+        }
+        for (key, value) in &self.other {
+            args.push(format!("{key}={value}"));
+        }
+        let command = format!("File={}:{}", args.join(";"), base64_encode(body));
+        write_osc(writer, command.as_bytes())
+    }
+}
+
+/// An OSC 1337 command other than `File`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ITerm2Command {
+    /// `SetMark`: set a mark at the cursor.
+    SetMark,
+    /// `CurrentDir=dir`: report the current directory.
+    CurrentDir(String),
+    /// `SetUserVar=name=base64`: set a user variable. The value is decoded.
+    SetUserVar { name: String, value: Vec<u8> },
+    /// `RemoteHost=user@host`: report the current user and host.
+    RemoteHost { user: Option<String>, host: String },
+    /// Any other command, without the leading `1337;`.
+    Other(Vec<u8>),
+}
+
+impl ITerm2Command {
+    /// Parses a command, without the leading `1337;`.
+    pub fn parse(command: &[u8]) -> Self {
+        let Ok(text) = std::str::from_utf8(command) else {
+            return Self::Other(command.to_vec());
+        };
+        let (name, value) = match text.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (text, None),
+        };
+        match (name, value) {
+            ("SetMark", None) => Self::SetMark,
+            ("CurrentDir", Some(dir)) => Self::CurrentDir(dir.to_owned()),
+            ("SetUserVar", Some(var)) => match var.split_once('=') {
+                Some((name, value)) => Self::SetUserVar {
+                    name: name.to_owned(),
+                    value: Base64Decoder::decode_all(value.as_bytes()),
+                },
+                None => Self::Other(command.to_vec()),
+            },
+            ("RemoteHost", Some(remote)) => match remote.rsplit_once('@') {
+                Some((user, host)) => Self::RemoteHost {
+                    user: Some(user.to_owned()),
+                    host: host.to_owned(),
+                },
+                None => Self::RemoteHost {
+                    user: None,
+                    host: remote.to_owned(),
+                },
+            },
+            _ => Self::Other(command.to_vec()),
+        }
+    }
+
+    pub fn write_to(&self, writer: impl io::Write) -> io::Result<usize> {
+        let command = match self {
+            Self::SetMark => "SetMark".to_owned(),
+            Self::CurrentDir(dir) => format!("CurrentDir={dir}"),
+            Self::SetUserVar { name, value } => {
+                format!("SetUserVar={name}={}", base64_encode(value))
+            }
+            Self::RemoteHost {
+                user: Some(user),
+                host,
+            } => format!("RemoteHost={user}@{host}"),
+            Self::RemoteHost { user: None, host } => format!("RemoteHost={host}"),
+            Self::Other(command) => return write_osc(writer, command),
+        };
+        write_osc(writer, command.as_bytes())
+    }
+}
+
+fn write_osc(mut writer: impl io::Write, command: &[u8]) -> io::Result<usize> {
+    let data = [format!("{ITERM2_OSC};").as_bytes(), command].concat();
+    let start = VTEvent::OscStart {
+        command: Some(ITERM2_OSC),
+    };
+    let end = VTEvent::OscEnd {
+        data: &data,
+        used_bel: true,
+    };
+    Ok(start.write_to(&mut writer)? + end.write_to(&mut writer)?)
+}
+
+/// An event from an [`ITerm2Decoder`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ITerm2Event<'a> {
+    /// The header of a `File` command. The body follows as `FileData`.
+    FileStart(ITerm2File),
+    /// The next decoded bytes of the file.
+    FileData(&'a [u8]),
+    /// The end of the file.
+    FileEnd,
+    /// The OSC string was cancelled before the end of the file.
+    FileCancel,
+    /// Any other command.
+    Command(ITerm2Command),
+}
+
+#[derive(Debug, Default)]
+enum DecoderState {
+    /// Not in an OSC 1337 string.
+    #[default]
+    Idle,
+    /// Collecting the command, or the header of a `File` command.
+    Command(Vec<u8>),
+    /// Decoding the body of a `File` command.
+    File(Base64Decoder),
+}
+
+/// Decodes OSC 1337 commands from a stream of [`VTEvent`]s. See the [module
+/// documentation](self).
+#[derive(Debug, Default)]
+pub struct ITerm2Decoder {
+    state: DecoderState,
+    /// The decoded file data, reused between events.
+    buffer: Vec<u8>,
+}
+
+impl ITerm2Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles an event from the parser.
+    pub fn event(&mut self, event: &VTEvent<'_>, mut cb: impl FnMut(ITerm2Event<'_>)) {
+        match event {
+            VTEvent::OscStart { command } => {
+                self.state = if *command == Some(ITERM2_OSC) {
+                    DecoderState::Command(Vec::new())
+                } else {
+                    DecoderState::Idle
+                };
+            }
+            VTEvent::OscData(data) => self.data(data, &mut cb),
+            VTEvent::OscEnd { data, .. } => {
+                self.data(data, &mut cb);
+                match std::mem::take(&mut self.state) {
+                    DecoderState::Idle => {}
+                    DecoderState::Command(command) => {
+                        if let Some(command) = OscParams::new(&command).remainder(1) {
+                            cb(ITerm2Event::Command(ITerm2Command::parse(command)));
+                        }
+                    }
+                    DecoderState::File(_) => cb(ITerm2Event::FileEnd),
+                }
+            }
+            VTEvent::OscCancel => {
+                if let DecoderState::File(_) = std::mem::take(&mut self.state) {
+                    cb(ITerm2Event::FileCancel);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn data(&mut self, mut data: &[u8], cb: &mut impl FnMut(ITerm2Event<'_>)) {
+        if let DecoderState::Command(command) = &mut self.state {
+            const FILE: &[u8] = b"1337;File=";
+            // Only a `File` command has a body after the first `:`, and only
+            // the header before it counts towards the limit
+            let end = if FILE.starts_with(&command[..command.len().min(FILE.len())]) {
+                data.iter().position(|&b| b == b':')
+            } else {
+                None
+            };
+            let header = end.unwrap_or(data.len());
+            if command.len() + header > MAX_COMMAND_LEN {
+                self.state = DecoderState::Idle;
+                return;
+            }
+            command.extend_from_slice(&data[..header]);
+            let Some(end) = end else {
+                return;
+            };
+            if !command.starts_with(FILE) {
+                // eg: `1337;Fi:le` is not a `File` command
+                if command.len() + data.len() - end > MAX_COMMAND_LEN {
+                    self.state = DecoderState::Idle;
+                    return;
+                }
+                command.extend_from_slice(&data[end..]);
+                return;
+            }
+            cb(ITerm2Event::FileStart(ITerm2File::parse(
+                &command[FILE.len()..],
+            )));
+            self.state = DecoderState::File(Base64Decoder::default());
+            data = &data[end + 1..];
+        }
+        if let DecoderState::File(decoder) = &mut self.state {
+            self.buffer.clear();
+            decoder.decode(data, &mut self.buffer);
+            if !self.buffer.is_empty() {
+                cb(ITerm2Event::FileData(&self.buffer));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VTPushParser;
+
+    fn decode(input: &[u8], chunk_size: usize) -> Vec<String> {
+        let mut decoder = ITerm2Decoder::new();
+        let mut parser = VTPushParser::new();
+        let mut events = vec![];
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_with(chunk, |event: VTEvent| {
+                decoder.event(&event, |event| events.push(format!("{event:?}")))
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn test_file() {
+        let input = b"\x1b]1337;File=name=dGVzdC5wbmc=;size=11;width=10px;height=auto;inline=1;x=y:aGVsbG8gd29ybGQ=\x07";
+        let header = ITerm2File {
+            name: Some("test.png".to_owned()),
+            size: Some(11),
+            width: Some(ITerm2Dimension::Pixels(10)),
+            height: Some(ITerm2Dimension::Auto),
+            preserve_aspect_ratio: None,
+            inline: true,
+            other: vec![("x".to_owned(), "y".to_owned())],
+        };
+        for chunk_size in 1..=input.len() {
+            let mut decoder = ITerm2Decoder::new();
+            let mut parser = VTPushParser::new();
+            let mut events = vec![];
+            let mut body = vec![];
+            for chunk in input.chunks(chunk_size) {
+                parser.feed_with(chunk, |event: VTEvent| {
+                    decoder.event(&event, |event| match event {
+                        ITerm2Event::FileData(data) => body.extend_from_slice(data),
+                        event => events.push(format!("{event:?}")),
+                    })
+                });
+            }
+            assert_eq!(
+                events,
+                [
+                    format!("{:?}", ITerm2Event::FileStart(header.clone())),
+                    "FileEnd".to_owned()
+                ]
+            );
+            assert_eq!(body, b"hello world");
+        }
+
+        let mut output = vec![];
+        header.write_to(b"hello world", &mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_file_body() {
+        let mut body = vec![];
+        let mut decoder = ITerm2Decoder::new();
+        let input = b"\x1b]1337;File=:AAEC/f7/\x1b\\\x1b]1337;File=:YWJj\x18";
+        let mut ends = vec![];
+        let mut parser = VTPushParser::new();
+        for chunk in input.chunks(3) {
+            parser.feed_with(chunk, |event: VTEvent| {
+                decoder.event(&event, |event| match event {
+                    ITerm2Event::FileData(data) => body.extend_from_slice(data),
+                    ITerm2Event::FileStart(_) => {}
+                    event => ends.push(format!("{event:?}")),
+                })
+            });
+        }
+        // The data buffered by the parser when the string is cancelled is
+        // never seen
+        assert_eq!(body, [0, 1, 2, 253, 254, 255, b'a', b'b']);
+        assert_eq!(ends, ["FileEnd", "FileCancel"]);
+    }
+
+    #[test]
+    fn test_large_file() {
+        // A body larger than the command limit in a single chunk
+        let mut input = b"\x1b]1337;File=inline=1:".to_vec();
+        input.extend(std::iter::repeat_n(b'A', 8000));
+        input.push(0x07);
+        let mut decoder = ITerm2Decoder::new();
+        let mut parser = VTPushParser::new();
+        let (mut starts, mut len) = (0, 0);
+        parser.feed_with(&input, |event: VTEvent| {
+            decoder.event(&event, |event| match event {
+                ITerm2Event::FileStart(_) => starts += 1,
+                ITerm2Event::FileData(data) => len += data.len(),
+                _ => {}
+            })
+        });
+        assert_eq!((starts, len), (1, 6000));
+
+        // A header larger than the limit is still ignored
+        let mut input = b"\x1b]1337;File=name=".to_vec();
+        input.extend(std::iter::repeat_n(b'A', MAX_COMMAND_LEN));
+        input.extend_from_slice(b":AAAA\x07");
+        assert_eq!(decode(&input, input.len()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_commands() {
+        let commands = [
+            ITerm2Command::SetMark,
+            ITerm2Command::CurrentDir("/home/user".to_owned()),
+            ITerm2Command::SetUserVar {
+                name: "gitBranch".to_owned(),
+                value: b"main".to_vec(),
+            },
+            ITerm2Command::RemoteHost {
+                user: Some("user".to_owned()),
+                host: "example.com".to_owned(),
+            },
+            ITerm2Command::Other(b"ClearScrollback".to_vec()),
+        ];
+        let mut output = vec![];
+        for command in &commands {
+            command.write_to(&mut output).unwrap();
+        }
+        assert_eq!(
+            output,
+            b"\x1b]1337;SetMark\x07\x1b]1337;CurrentDir=/home/user\x07\x1b]1337;SetUserVar=gitBranch=bWFpbg==\x07\x1b]1337;RemoteHost=user@example.com\x07\x1b]1337;ClearScrollback\x07"
+        );
+        // Other OSC strings are ignored
+        output.extend_from_slice(b"\x1b]133;A\x07\x1b]1337;Fi:le\x07");
+        let expected = commands
+            .iter()
+            .map(|command| format!("Command({command:?})"))
+            .chain(["Command(Other([70, 105, 58, 108, 101]))".to_owned()])
+            .collect::<Vec<_>>();
+        assert_eq!(decode(&output, 4), expected);
+    }
+
+    #[test]
+    fn test_base64() {
+        for len in 0..10u8 {
+            let data = (0..len).map(|i| i.wrapping_mul(37)).collect::<Vec<u8>>();
+            let encoded = base64_encode(&data);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(Base64Decoder::decode_all(encoded.as_bytes()), data);
+        }
+        assert_eq!(base64_encode(b"hello"), "aGVsbG8=");
+    }
+}
@@ -28,6 +28,24 @@
 //! let mut parser = VTPushParser::new_with_interest::<VT_PARSER_INTEREST_CSI>();
 //! ```
 //!
+//! If the interest needs to change while parsing, include
+//! [`VT_PARSER_INTEREST_DYNAMIC`] (or use [`VTDynamicPushParser`]) and call
+//! [`VTPushParser::set_interest`]. The runtime mask is combined with the
+//! `INTEREST` parameter, so parsers without the dynamic bit keep their
+//! compile-time filtering.
+//!
+//! ```rust
+//! use vt_push_parser::{VTDynamicPushParser, VTPushParser, VT_PARSER_INTEREST_NONE};
+//! use vt_push_parser::event::VTEvent;
+//!
+//! let mut parser: VTDynamicPushParser = VTPushParser::new_dynamic();
+//! let mut events = vec![];
+//! parser.feed_with(b"\x1b[1mA", |event: VTEvent| events.push(format!("{event:?}")));
+//! parser.set_interest(VT_PARSER_INTEREST_NONE);
+//! parser.feed_with(b"\x1b[2mB", |event: VTEvent| events.push(format!("{event:?}")));
+//! assert_eq!(events, ["Csi('1', '', 'm')", "Raw('A')", "Raw('B')"]);
+//! ```
+//!
 //! ## Input parsing
 //!
 //! This crate is designed to be used for parsing terminal output, but it can
@@ -40,9 +58,20 @@
 //! work in this way.
 pub mod ascii;
 pub mod capture;
+pub mod charset;
+pub mod color;
 pub mod event;
+pub mod filter;
 pub mod iter;
+pub mod iterm2;
+pub mod passthrough;
+pub mod query;
+pub mod responder;
+pub mod sanitizer;
+mod scan;
 pub mod signature;
+pub mod text;
+pub mod tmux;
 
 use smallvec::SmallVec;
 
@@ -64,7 +93,11 @@ const PM: u8 = b'^';
 const SOS: u8 = b'X';
 const ST_FINAL: u8 = b'\\';
 
+/// The longest OSC command number that is recognized.
+const OSC_COMMAND_MAX_DIGITS: usize = 5;
+
 use crate::event::{Param, ParamBuf, Params};
+use crate::filter::VTFilter;
 
 /// Receives a single [`VTEvent`].
 #[allow(private_bounds)]
@@ -129,6 +162,11 @@ enum VTAction<'a> {
     Hold(VTEmit),
     /// Cancel the current buffer.
     Cancel(VTEmit),
+    /// The OSC command was read and accepted by the filter. Emit `OscStart`
+    /// and the command digits, then process the current byte again.
+    OscStart(Option<u16>),
+    /// Emit the OSC command digits, then process the current byte again.
+    OscCommandData,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -174,6 +212,7 @@ def_pattern!(is_final => 0x40..=0x7e);
 def_pattern!(is_priv => b'<' | b'=' | b'>' | b'?');
 def_pattern!(is_priv_no_q => b'<' | b'=' | b'>');
 def_pattern!(is_digit => b'0'..=b'9');
+def_pattern!(is_esc_final => 0x30..=0x7e);
 
 macro_rules! byte_predicate {
     (|$p:ident| $body:block) => {{
@@ -193,6 +232,14 @@ const ENDS_CSI: [bool; 256] =
 
 const ENDS_GROUND: [bool; 256] = byte_predicate!(|b| { is_c0(b) || b == DEL });
 
+const ENDS_OSC_STRING: [bool; 256] = byte_predicate!(|b| { !is_printable(b) });
+
+const ENDS_DCS_PASSTHROUGH: [bool; 256] =
+    byte_predicate!(|b| { b == CAN || b == SUB || b == ESC || b == DEL });
+
+const ENDS_SOS_PM_APC_STRING: [bool; 256] =
+    byte_predicate!(|b| { b == CAN || b == SUB || b == ESC });
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum State {
     Ground,
@@ -211,8 +258,12 @@ enum State {
     DcsIgnoreEsc,
     DcsPassthrough,
     DcsEsc,
+    OscCommand,
+    OscCommandData,
     OscString,
     OscEsc,
+    OscIgnore,
+    OscIgnoreEsc,
     SosPmApcString,
     SpaEsc,
 }
@@ -237,6 +288,11 @@ pub const VT_PARSER_INTEREST_ALL: u8 = VT_PARSER_INTEREST_CSI
     | VT_PARSER_INTEREST_ESCAPE_RECOVERY
     | VT_PARSER_INTEREST_OTHER;
 
+/// Allow the interest to be changed at runtime with
+/// [`VTPushParser::set_interest`]. The other `INTEREST` bits are an upper bound
+/// on the runtime interest.
+pub const VT_PARSER_INTEREST_DYNAMIC: u8 = 1 << 6;
+
 /// Default interest level.
 pub const VT_PARSER_INTEREST_DEFAULT: u8 = VT_PARSER_INTEREST_CSI
     | VT_PARSER_INTEREST_DCS
@@ -275,8 +331,16 @@ pub struct VTPushParser<const INTEREST: u8 = VT_PARSER_INTEREST_DEFAULT> {
     cur_param: Param,
     priv_prefix: Option<u8>,
     held_byte: Option<u8>,
+    /// The runtime interest, if `INTEREST` includes
+    /// [`VT_PARSER_INTEREST_DYNAMIC`].
+    interest: u8,
+    filter: Option<Box<VTFilter>>,
 }
 
+/// A parser whose interest can be changed at runtime.
+pub type VTDynamicPushParser =
+    VTPushParser<{ VT_PARSER_INTEREST_ALL | VT_PARSER_INTEREST_DYNAMIC }>;
+
 impl Default for VTPushParser {
     fn default() -> Self {
         Self::new()
@@ -297,6 +361,12 @@ impl VTPushParser {
     pub const fn new_with_interest<const INTEREST: u8>() -> VTPushParser<INTEREST> {
         VTPushParser::new_with()
     }
+
+    /// Create a parser whose interest can be changed at runtime, initially
+    /// interested in all events.
+    pub const fn new_dynamic() -> VTDynamicPushParser {
+        VTPushParser::new_with()
+    }
 }
 
 /// Emit the EscInvalid event
@@ -366,9 +436,40 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             cur_param: SmallVec::new_const(),
             priv_prefix: None,
             held_byte: None,
+            interest: INTEREST,
+            filter: None,
         }
     }
 
+    /// The interest of the parser: the `INTEREST` parameter, combined with
+    /// the runtime interest if the parser is dynamic.
+    #[inline(always)]
+    pub fn interest(&self) -> u8 {
+        if INTEREST & VT_PARSER_INTEREST_DYNAMIC == 0 {
+            INTEREST
+        } else {
+            INTEREST & (self.interest | VT_PARSER_INTEREST_DYNAMIC)
+        }
+    }
+
+    /// Set the runtime interest. This takes effect from the next sequence,
+    /// and does not reset the parser's state. This has no effect unless
+    /// `INTEREST` includes [`VT_PARSER_INTEREST_DYNAMIC`].
+    pub fn set_interest(&mut self, interest: u8) {
+        self.interest = interest;
+    }
+
+    /// The fine-grained filter, if any.
+    pub fn filter(&self) -> Option<&VTFilter> {
+        self.filter.as_deref()
+    }
+
+    /// Set a fine-grained filter for CSI and OSC events, in addition to the
+    /// interest. This takes effect from the next sequence.
+    pub fn set_filter(&mut self, filter: Option<VTFilter>) {
+        self.filter = filter.map(Box::new);
+    }
+
     // =====================
     // Callback-driven API
     // =====================
@@ -453,12 +554,17 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             buffer_idx: usize,
             current_emit: Option<VTEmit>,
             hold: bool,
+            /// The start of the OSC command digits, if they all are in this
+            /// input.
+            osc_command_idx: Option<usize>,
         }
 
         let mut state = FeedState {
             buffer_idx: 0,
             current_emit: None,
             hold: self.held_byte.is_some(),
+            osc_command_idx: (self.st == State::OscCommand && self.cur_param.is_empty())
+                .then_some(0),
         };
 
         let mut held_byte = self.held_byte.take();
@@ -468,15 +574,10 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             // Fast path for the common case of no ANSI escape sequences.
             if self.st == State::Ground {
                 let start = i;
-                loop {
-                    if i >= input.len() {
-                        cb.event(VTEvent::Raw(&input[start..]));
-                        return input.len();
-                    }
-                    if ENDS_GROUND[input[i] as usize] {
-                        break;
-                    }
-                    i += 1;
+                i = scan::find(input, i, scan::c0_or_del, &ENDS_GROUND);
+                if i >= input.len() {
+                    cb.event(VTEvent::Raw(&input[start..]));
+                    return input.len();
                 }
 
                 if start != i && cb.event(VTEvent::Raw(&input[start..i])).abort() {
@@ -493,14 +594,9 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
 
             // Fast path: search for the CSI final
             if self.st == State::CsiIgnore {
-                loop {
-                    if i >= input.len() {
-                        return input.len();
-                    }
-                    if ENDS_CSI[input[i] as usize] {
-                        break;
-                    }
-                    i += 1;
+                i = scan::find(input, i, scan::csi_ignore, &ENDS_CSI);
+                if i >= input.len() {
+                    return input.len();
                 }
 
                 if input[i] == ESC {
@@ -512,10 +608,31 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 continue;
             }
 
+            // Fast path: skip the string bytes that would only extend the
+            // current buffer (or be ignored)
+            i = match (self.st, state.current_emit) {
+                (State::OscString, Some(VTEmit::Osc)) => {
+                    scan::find(input, i, scan::not_printable, &ENDS_OSC_STRING)
+                }
+                (State::DcsPassthrough, Some(VTEmit::Dcs)) => {
+                    scan::find(input, i, scan::c0_or_del, &ENDS_DCS_PASSTHROUGH)
+                }
+                (State::SosPmApcString, None) => {
+                    scan::find(input, i, scan::c0, &ENDS_SOS_PM_APC_STRING)
+                }
+                _ => i,
+            };
+            if i >= input.len() {
+                break;
+            }
+
             let action = self.push_with(input[i]);
 
             match action {
                 VTAction::None => {
+                    if self.st == State::OscCommand && input[i] == OSC {
+                        state.osc_command_idx = Some(i + 1);
+                    }
                     if let Some(emit) = state.current_emit {
                         // We received a DEL during an emit, so we need to partially emit our buffer
                         let range = state.buffer_idx..(i - state.hold as usize);
@@ -544,18 +661,24 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     }
                 }
                 VTAction::End(VTEnd::Dcs) => {
-                    state.current_emit = None;
                     let hold = std::mem::take(&mut state.hold);
-                    let range = state.buffer_idx..(i.saturating_sub(hold as usize));
+                    let range = if state.current_emit.take().is_some() {
+                        state.buffer_idx..(i.saturating_sub(hold as usize))
+                    } else {
+                        i..i
+                    };
                     if cb.event(VTEvent::DcsEnd(&input[range])).abort() {
                         return i + 1;
                     }
                     held_byte = None;
                 }
                 VTAction::End(VTEnd::Osc { used_bel }) => {
-                    state.current_emit = None;
                     let hold = std::mem::take(&mut state.hold);
-                    let range = state.buffer_idx..(i.saturating_sub(hold as usize));
+                    let range = if state.current_emit.take().is_some() {
+                        state.buffer_idx..(i.saturating_sub(hold as usize))
+                    } else {
+                        i..i
+                    };
                     if cb
                         .event(VTEvent::OscEnd {
                             data: &input[range],
@@ -592,6 +715,37 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                         state.current_emit = Some(emit);
                     }
                 }
+                VTAction::OscStart(command) => {
+                    // The command digits are part of the OSC data. If they are
+                    // all in this input with nothing between them (eg: a DEL),
+                    // buffer them with the rest of the string, otherwise emit
+                    // them before the next byte.
+                    let digits = self.cur_param.len();
+                    if digits > 0 {
+                        if let Some(start) = state.osc_command_idx.take()
+                            && start + digits == i
+                        {
+                            state.buffer_idx = start;
+                            state.current_emit = Some(VTEmit::Osc);
+                        } else {
+                            self.st = State::OscCommandData;
+                        }
+                    }
+                    if cb.event(VTEvent::OscStart { command }).abort() {
+                        if state.current_emit.take().is_some() {
+                            self.st = State::OscCommandData;
+                        }
+                        return i;
+                    }
+                    // Process this byte again as part of the OSC string
+                    continue;
+                }
+                VTAction::OscCommandData => {
+                    if cb.event(VTEvent::OscData(&self.cur_param)).abort() {
+                        return i;
+                    }
+                    continue;
+                }
                 VTAction::Cancel(emit) => {
                     state.current_emit = None;
                     state.hold = false;
@@ -645,14 +799,14 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             }
             State::EscInt => {
                 self.st = State::Ground;
-                if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                     None
                 } else {
                     Some(invalid!(self.priv_prefix, self.ints))
                 }
             }
             State::EscSs2 | State::EscSs3 => {
-                if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                     self.st = State::Ground;
                     None
                 } else {
@@ -701,10 +855,55 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
         }
     }
 
+    /// Whether the filter rejects all CSI sequences with this private marker.
+    fn csi_rejected(&self, private: Option<u8>) -> bool {
+        self.filter
+            .as_ref()
+            .is_some_and(|filter| !filter.accepts_csi_private(private))
+    }
+
+    /// The state to enter after the first parameter byte of a CSI sequence.
+    fn csi_param_state(&self, private: Option<u8>) -> State {
+        if self.csi_rejected(private) {
+            State::CsiIgnore
+        } else {
+            State::CsiParam
+        }
+    }
+
+    /// Finish reading the OSC command at the first byte that is not part of
+    /// it, and either start or ignore the OSC string.
+    fn end_osc_command(&mut self, b: u8) -> VTAction<'_> {
+        // The command must be followed by a separator or the end of the string
+        let command = matches!(b, b';' | BEL | ESC)
+            .then(|| std::str::from_utf8(&self.cur_param).ok()?.parse().ok())
+            .flatten();
+        let accepted = self
+            .filter
+            .as_ref()
+            .is_none_or(|filter| filter.accepts_osc(command));
+        if accepted {
+            self.st = State::OscString;
+            VTAction::OscStart(command)
+        } else {
+            self.st = match b {
+                BEL | CAN | SUB => State::Ground,
+                ESC => State::OscIgnoreEsc,
+                _ => State::OscIgnore,
+            };
+            VTAction::None
+        }
+    }
+
     fn emit_csi(&mut self, final_byte: u8) -> VTAction<'_> {
         self.finish_params_if_any();
 
         let privp = self.priv_prefix.take();
+        if let Some(filter) = &self.filter
+            && !filter.accepts_csi(privp, final_byte)
+        {
+            return VTAction::None;
+        }
         VTAction::Event(VTEvent::Csi(CSI {
             private: privp,
             params: ParamBuf {
@@ -792,7 +991,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             Escape => {
                 CAN | SUB => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(b))
@@ -802,7 +1001,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 // we move to ground state here instead.
                 DEL => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(b))
@@ -832,7 +1031,9 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     }))
                 }
                 CSI => {
-                    if INTEREST & VT_PARSER_INTEREST_CSI == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_CSI == 0
+                        || self.filter.as_ref().is_some_and(|f| !f.accepts_any_csi())
+                    {
                         self.st = CsiIgnore;
                     } else {
                         self.st = CsiEntry;
@@ -840,7 +1041,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     VTAction::None
                 }
                 DCS => {
-                    if INTEREST & VT_PARSER_INTEREST_DCS == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_DCS == 0 {
                         self.st = DcsIgnore;
                     } else {
                         self.st = DcsEntry;
@@ -848,8 +1049,12 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     VTAction::None
                 }
                 OSC => {
-                    self.st = OscString;
-                    VTAction::Event(VTEvent::OscStart)
+                    if self.filter.as_ref().is_some_and(|f| !f.accepts_any_osc()) {
+                        self.st = OscIgnore;
+                    } else {
+                        self.st = OscCommand;
+                    }
+                    VTAction::None
                 }
                 SS2 => {
                     self.st = EscSs2;
@@ -877,7 +1082,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 }
                 _ => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(b))
@@ -887,7 +1092,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             EscInt => {
                 CAN | SUB => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(self.priv_prefix, self.ints, b))
@@ -897,7 +1102,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 // we move to ground state here instead.
                 DEL => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(self.priv_prefix, self.ints, b))
@@ -906,7 +1111,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 c if is_intermediate(c) => {
                     if !self.ints.push(c) {
                         self.st = Ground;
-                        if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                        if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                             VTAction::None
                         } else {
                             VTAction::Event(invalid!(self.priv_prefix, self.ints, b))
@@ -915,7 +1120,9 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                         VTAction::None
                     }
                 }
-                c if is_final(c) || is_digit(c) => {
+                // After an intermediate, any of 0x30..=0x7e is a final byte
+                // (eg: `ESC ( >` designates the DEC Technical character set)
+                c if is_esc_final(c) => {
                     self.st = Ground;
                     VTAction::Event(VTEvent::Esc(Esc {
                         intermediates: self.ints,
@@ -927,7 +1134,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 // to recover from this state.
                 ESC => {
                     self.st = Escape;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(self.priv_prefix, self.ints))
@@ -935,7 +1142,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 }
                 _ => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(self.priv_prefix, self.ints, c))
@@ -945,7 +1152,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             EscSs2 => {
                 CAN | SUB => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(SS2, b))
@@ -955,7 +1162,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 // to recover from this state.
                 ESC => {
                     self.st = Escape;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(SS2))
@@ -969,7 +1176,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
             EscSs3 => {
                 CAN | SUB => {
                     self.st = Ground;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(SS3, b))
@@ -979,7 +1186,7 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 // to recover from this state.
                 ESC => {
                     self.st = Escape;
-                    if INTEREST & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
+                    if self.interest() & VT_PARSER_INTEREST_ESCAPE_RECOVERY == 0 {
                         VTAction::None
                     } else {
                         VTAction::Event(invalid!(SS3))
@@ -1004,22 +1211,22 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                 c if is_any_c0(c) => VTAction::Event(VTEvent::C0(c)),
                 c if is_priv(c) => {
                     self.priv_prefix = Some(c);
-                    self.st = CsiParam;
+                    self.st = self.csi_param_state(Some(c));
                     VTAction::None
                 }
                 c if is_digit(c) => {
                     self.cur_param.push(c);
-                    self.st = CsiParam;
+                    self.st = self.csi_param_state(None);
                     VTAction::None
                 }
                 b';' => {
                     self.next_param();
-                    self.st = CsiParam;
+                    self.st = self.csi_param_state(None);
                     VTAction::None
                 }
                 b':' => {
                     self.cur_param.push(b':');
-                    self.st = CsiParam;
+                    self.st = self.csi_param_state(None);
                     VTAction::None
                 }
                 c if is_intermediate(c) => {
@@ -1294,6 +1501,27 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     VTAction::Buffer(VTEmit::Dcs)
                 }
             }
+            OscCommand => {
+                // The command is unknown, but the string must still be
+                // reported as cancelled
+                CAN | SUB => self.end_osc_command(b),
+                DEL => VTAction::None,
+                c if is_digit(c) => {
+                    if self.cur_param.len() < OSC_COMMAND_MAX_DIGITS {
+                        self.cur_param.push(c);
+                        VTAction::None
+                    } else {
+                        self.end_osc_command(c)
+                    }
+                }
+                _ => self.end_osc_command(b),
+            }
+            OscCommandData => {
+                _ => {
+                    self.st = OscString;
+                    VTAction::OscCommandData
+                }
+            }
             OscString => {
                 CAN | SUB => {
                     self.st = Ground;
@@ -1323,6 +1551,28 @@ impl<const INTEREST: u8> VTPushParser<INTEREST> {
                     VTAction::Buffer(VTEmit::Osc)
                 }
             }
+            OscIgnore => {
+                CAN | SUB | BEL => {
+                    self.st = Ground;
+                    VTAction::None
+                }
+                ESC => {
+                    self.st = OscIgnoreEsc;
+                    VTAction::None
+                }
+                _ => VTAction::None,
+            }
+            OscIgnoreEsc => {
+                ST_FINAL => {
+                    self.st = Ground;
+                    VTAction::None
+                }
+                DEL | ESC => VTAction::None,
+                _ => {
+                    self.st = OscIgnore;
+                    VTAction::None
+                }
+            }
             SosPmApcString => {
                 CAN | SUB => {
                     self.st = Ground;
@@ -1383,10 +1633,30 @@ mod tests {
         VTPushParser::decode_buffer(b"\x1bP", |e| result.push_str(&format!("{e:?}\n")));
         assert_eq!(result.trim(), "");
 
-        // Test incomplete OSC
+        // Test incomplete OSC: OscStart waits for the command to be read
         let mut result = String::new();
         VTPushParser::decode_buffer(b"\x1b]", |e| result.push_str(&format!("{e:?}\n")));
-        assert_eq!(result.trim(), "OscStart");
+        assert_eq!(result.trim(), "");
+
+        // Test OSC cancelled before and during the command
+        for input in [&b"\x1b]\x18"[..], b"\x1b]11\x18", b"\x1b]11\x1a"] {
+            let mut result = String::new();
+            VTPushParser::decode_buffer(input, |e| result.push_str(&format!("{e:?}\n")));
+            assert_eq!(result.trim(), "OscStart\nOscCancel", "{input:?}");
+        }
+
+        // Digits held from an earlier feed are emitted as data before the cancel
+        let mut parser = VTPushParser::new();
+        let mut result = String::new();
+        for chunk in [&b"\x1b]11"[..], b"\x18"] {
+            parser.feed_with(chunk, |e: VTEvent| result.push_str(&format!("{e:?}\n")));
+        }
+        assert_eq!(result.trim(), "OscStart\nOscData('11')\nOscCancel");
+
+        // Test incomplete OSC after the command
+        let mut result = String::new();
+        VTPushParser::decode_buffer(b"\x1b]52;", |e| result.push_str(&format!("{e:?}\n")));
+        assert_eq!(result.trim(), "OscStart(52)\nOscData('52;')");
     }
 
     #[test]
@@ -1410,6 +1680,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_osc_command_split() {
+        // The command digits are part of the data however the input is split,
+        // including when a DEL is ignored between them
+        let input = b"x\x1b]1\x7f2;a\x07\x1b]52;b\x07";
+        for split in 0..input.len() {
+            let mut parser = VTPushParser::new();
+            let mut data = Vec::new();
+            let mut commands = Vec::new();
+            for chunk in [&input[..split], &input[split..]] {
+                parser.feed_with(chunk, |event: VTEvent| match event {
+                    VTEvent::OscStart { command } => commands.push(command),
+                    VTEvent::OscData(d) => data.extend_from_slice(d),
+                    VTEvent::OscEnd { data: d, .. } => {
+                        data.extend_from_slice(d);
+                        data.push(b'|');
+                    }
+                    _ => {}
+                });
+            }
+            assert_eq!(commands, [Some(12), Some(52)], "split at {split}");
+            assert_eq!(data, b"12;a|52;b|", "split at {split}");
+        }
+    }
+
     #[test]
     fn test_dcs_payload_passthrough() {
         // Test cases for DCS payload passthrough behavior
@@ -1511,6 +1806,21 @@ mod tests {
         assert_eq!(output.trim(), "OscStart\nOscData('X')\nOscEnd('')");
     }
 
+    #[test]
+    fn empty_strings_after_text() {
+        assert_eq!(
+            collect_debug(b"x\x1b]\x07y\x1bPq\x1b\\"),
+            [
+                "Raw('x')",
+                "OscStart",
+                "OscEnd('')",
+                "Raw('y')",
+                "DcsStart('', q)",
+                "DcsEnd('')"
+            ]
+        );
+    }
+
     #[test]
     fn dcs_header_with_colon_is_ignored_case1() {
         // ESC P 1:2 q ... ST   -> colon inside header params (invalid)
@@ -1654,6 +1964,39 @@ mod tests {
         assert_eq!(ev[2], "Raw('def')");
     }
 
+    #[test]
+    fn dynamic_interest() {
+        let mut parser = VTPushParser::new_dynamic();
+        let mut events = vec![];
+        let mut feed = |parser: &mut VTDynamicPushParser, input: &[u8]| {
+            parser.feed_with(input, |event: VTEvent| events.push(format!("{event:?}")));
+        };
+        feed(&mut parser, b"\x1b[1ma\x1bP1$r");
+        // The DCS in progress is unaffected, but the CSI is ignored
+        parser.set_interest(VT_PARSER_INTEREST_OSC);
+        feed(&mut parser, b"m\x1b\\\x1b[2mb\x1b]0;t\x07\x1bP1$rm\x1b\\");
+        parser.set_interest(VT_PARSER_INTEREST_ALL);
+        feed(&mut parser, b"\x1b[3m");
+        assert_eq!(
+            events,
+            [
+                "Csi('1', '', 'm')",
+                "Raw('a')",
+                "DcsStart('1', '$', r)",
+                "DcsEnd('m')",
+                "Raw('b')",
+                "OscStart(0)",
+                "OscEnd('0;t')",
+                "Csi('3', '', 'm')",
+            ]
+        );
+
+        // The runtime interest has no effect on parsers that are not dynamic
+        let mut parser = VTPushParser::new();
+        parser.set_interest(VT_PARSER_INTEREST_NONE);
+        assert_eq!(parser.interest(), VT_PARSER_INTEREST_DEFAULT);
+    }
+
     /// Brute force sweep of all three-byte sequences to ensure we can recover
     /// from all invalid escape sequences (unless CSI/OSC/DCS/SOS/PM/APC).
     #[test]
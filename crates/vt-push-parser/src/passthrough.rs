@@ -0,0 +1,380 @@
+//! tmux and GNU screen DCS passthrough.
+//!
+//! Terminal multiplexers forward escape sequences to the outer terminal when
+//! they are wrapped in a DCS string:
+//!
+//! | Multiplexer | Wrapped sequence                   | Notes                          |
+//! |-------------|------------------------------------|--------------------------------|
+//! | tmux        | `ESC P tmux; inner ESC \`          | `ESC` in `inner` is doubled    |
+//! | GNU screen  | `ESC P inner ESC \`                | `inner` may not contain `ST`   |
+//!
+//! A plain [`VTPushParser`] sees these as opaque (or, for screen, broken) DCS
+//! strings. [`VTPassthroughParser`] detects the passthrough wrapper, unwraps it
+//! and feeds the inner bytes through a nested parser so that consumers see the
+//! real inner events.
+//!
+//! ```rust
+//! use vt_push_parser::event::VTEvent;
+//! use vt_push_parser::passthrough::{VTPassthrough, VTPassthroughParser};
+//!
+//! let mut parser = VTPassthroughParser::new();
+//! let mut events = vec![];
+//! parser.feed_with_tagged(b"a\x1bPtmux;\x1b\x1b]0;title\x1b\x1b\\\x1b\\", |tag, event: VTEvent| {
+//!     events.push((tag, format!("{event:?}")));
+//! });
+//! assert_eq!(events, [
+//!     (None, "Raw('a')".to_owned()),
+//!     (Some(VTPassthrough::Tmux), "OscStart(0)".to_owned()),
+//!     (Some(VTPassthrough::Tmux), "OscData('0;title')".to_owned()),
+//!     (Some(VTPassthrough::Tmux), "OscEnd('')".to_owned()),
+//! ]);
+//! ```
+use std::io;
+
+use crate::event::VTEvent;
+use crate::{VTEventCallback, VTPushParser};
+
+const ESC: u8 = 0x1b;
+
+/// The opening of a tmux passthrough DCS.
+const TMUX_OPEN: &[u8] = b"\x1bPtmux;";
+/// The opening of a screen passthrough DCS: the DCS body starts with `ESC`.
+const SCREEN_OPEN: &[u8] = b"\x1bP\x1b";
+
+/// The multiplexer that wrapped an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTPassthrough {
+    /// `ESC P tmux; ... ESC \`
+    Tmux,
+    /// `ESC P ESC ... ESC \`
+    Screen,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    /// Outside of a passthrough DCS. The outer parser handles the input.
+    #[default]
+    Outer,
+    /// Inside a passthrough DCS. The inner parser handles the unwrapped
+    /// input.
+    Passthrough(VTPassthrough),
+}
+
+/// A parser that unwraps tmux and screen passthrough DCS strings.
+///
+/// Events outside of a passthrough are parsed as usual. Events inside a
+/// passthrough are parsed by a nested parser, which keeps its state across
+/// passthrough strings so that sequences split across several of them (as
+/// screen does for long sequences) are reassembled.
+#[derive(Default)]
+pub struct VTPassthroughParser {
+    outer: VTPushParser,
+    inner: VTPushParser,
+    state: State,
+    /// A partial passthrough opening, held back from the outer parser until
+    /// it can be matched.
+    opening: Vec<u8>,
+    /// Inside a passthrough, whether the last byte was an `ESC`.
+    pending_esc: bool,
+    /// Whether a screen passthrough ended in the middle of an inner sequence,
+    /// so the next DCS continues it.
+    screen_continues: bool,
+}
+
+impl VTPassthroughParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Outer bytes between two screen passthrough strings mean that the
+    /// next DCS is not a continuation, so the partial inner sequence is
+    /// dropped.
+    fn end_screen_continuation(&mut self) {
+        if std::mem::take(&mut self.screen_continues) {
+            self.inner = VTPushParser::new();
+        }
+    }
+
+    /// Whether the parser is inside a passthrough DCS.
+    pub fn passthrough(&self) -> Option<VTPassthrough> {
+        match self.state {
+            State::Outer => None,
+            State::Passthrough(kind) => Some(kind),
+        }
+    }
+
+    /// Feed bytes into the parser, without distinguishing between inner and
+    /// outer events.
+    pub fn feed_with<F: VTEventCallback>(&mut self, input: &[u8], mut cb: F) {
+        self.feed_with_tagged(input, |_, event| VTEventCallback::event(&mut cb, event));
+    }
+
+    /// Feed bytes into the parser. Events are tagged with the multiplexer
+    /// that wrapped them, or `None` for events outside of a passthrough.
+    pub fn feed_with_tagged(
+        &mut self,
+        mut input: &[u8],
+        mut cb: impl FnMut(Option<VTPassthrough>, VTEvent<'_>),
+    ) {
+        while !input.is_empty() {
+            match self.state {
+                State::Outer if !self.opening.is_empty() => {
+                    self.opening.push(input[0]);
+                    input = &input[1..];
+                    if self.opening == TMUX_OPEN {
+                        self.start(VTPassthrough::Tmux);
+                    } else if self.opening == b"\x1bP" && self.screen_continues {
+                        self.start(VTPassthrough::Screen);
+                    } else if self.opening == SCREEN_OPEN {
+                        self.start(VTPassthrough::Screen);
+                        // The ESC belongs to the inner sequence
+                        self.pending_esc = true;
+                    } else if !TMUX_OPEN.starts_with(&self.opening) {
+                        self.end_screen_continuation();
+                        self.outer
+                            .feed_with(&self.opening, |event: VTEvent| cb(None, event));
+                        self.opening.clear();
+                    }
+                }
+                State::Outer => {
+                    let Some(esc) = input.iter().position(|&b| b == ESC) else {
+                        self.end_screen_continuation();
+                        self.outer
+                            .feed_with(input, |event: VTEvent| cb(None, event));
+                        return;
+                    };
+                    if esc > 0 {
+                        self.end_screen_continuation();
+                    }
+                    self.outer
+                        .feed_with(&input[..esc], |event: VTEvent| cb(None, event));
+                    if self.outer.is_ground() {
+                        self.opening.push(ESC);
+                    } else {
+                        self.outer
+                            .feed_with(&[ESC], |event: VTEvent| cb(None, event));
+                    }
+                    input = &input[esc + 1..];
+                }
+                State::Passthrough(kind) if self.pending_esc => {
+                    self.pending_esc = false;
+                    let tag = Some(kind);
+                    match (kind, input[0]) {
+                        (_, b'\\') => {
+                            self.state = State::Outer;
+                            self.screen_continues =
+                                kind == VTPassthrough::Screen && !self.inner.is_ground();
+                            input = &input[1..];
+                        }
+                        (VTPassthrough::Tmux, ESC) => {
+                            self.inner
+                                .feed_with(&[ESC], |event: VTEvent| cb(tag, event));
+                            input = &input[1..];
+                        }
+                        (VTPassthrough::Tmux, _) => {
+                            // A lone ESC ends a malformed passthrough, and
+                            // starts a new outer sequence
+                            self.state = State::Outer;
+                            self.opening.push(ESC);
+                        }
+                        (VTPassthrough::Screen, _) => {
+                            self.inner
+                                .feed_with(&[ESC], |event: VTEvent| cb(tag, event));
+                        }
+                    }
+                }
+                State::Passthrough(kind) => {
+                    let tag = Some(kind);
+                    let Some(esc) = input.iter().position(|&b| b == ESC) else {
+                        self.inner.feed_with(input, |event: VTEvent| cb(tag, event));
+                        return;
+                    };
+                    self.inner
+                        .feed_with(&input[..esc], |event: VTEvent| cb(tag, event));
+                    self.pending_esc = true;
+                    input = &input[esc + 1..];
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, kind: VTPassthrough) {
+        self.state = State::Passthrough(kind);
+        self.opening.clear();
+    }
+}
+
+/// Wraps events in a tmux passthrough DCS, doubling each `ESC`.
+pub fn write_tmux_passthrough(
+    events: &[VTEvent<'_>],
+    mut writer: impl io::Write,
+) -> io::Result<usize> {
+    let mut inner = Vec::new();
+    for event in events {
+        event.write_to(&mut inner)?;
+    }
+
+    writer.write_all(TMUX_OPEN)?;
+    let mut len = TMUX_OPEN.len();
+    for chunk in inner.split_inclusive(|&b| b == ESC) {
+        writer.write_all(chunk)?;
+        len += chunk.len();
+        if chunk.last() == Some(&ESC) {
+            writer.write_all(&[ESC])?;
+            len += 1;
+        }
+    }
+    writer.write_all(b"\x1b\\")?;
+    Ok(len + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8], chunk_size: usize) -> Vec<(Option<VTPassthrough>, String)> {
+        let mut parser = VTPassthroughParser::new();
+        let mut events = vec![];
+        for chunk in input.chunks(chunk_size) {
+            parser.feed_with_tagged(chunk, |tag, event| {
+                events.push((tag, format!("{event:?}")));
+            });
+        }
+        events
+    }
+
+    /// Parses the input in every chunk size, checking that the events are
+    /// the same (after merging raw text and data).
+    fn parse_all(input: &[u8]) -> Vec<(Option<VTPassthrough>, String)> {
+        let expected = parse(input, input.len());
+        for chunk_size in 1..input.len() {
+            let events = parse(input, chunk_size);
+            let strip = |events: &[(Option<VTPassthrough>, String)]| {
+                events
+                    .iter()
+                    .filter(|(_, e)| {
+                        !e.starts_with("Raw")
+                            && !e.starts_with("OscData")
+                            && !e.starts_with("DcsData")
+                    })
+                    .map(|(t, e)| (*t, e.split('(').next().unwrap().to_owned()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(strip(&events), strip(&expected), "chunk size {chunk_size}");
+        }
+        expected
+    }
+
+    #[test]
+    fn test_tmux() {
+        let tmux = Some(VTPassthrough::Tmux);
+        assert_eq!(
+            parse_all(b"a\x1bPtmux;\x1b\x1b]52;c;aGk=\x1b\x1b\\\x1b\\b\x1b[1m"),
+            [
+                (None, "Raw('a')".to_owned()),
+                (tmux, "OscStart(52)".to_owned()),
+                (tmux, "OscData('52;c;aGk=')".to_owned()),
+                (tmux, "OscEnd('')".to_owned()),
+                (None, "Raw('b')".to_owned()),
+                (None, "Csi('1', '', 'm')".to_owned()),
+            ]
+        );
+        // Other DCS strings are passed through to the outer parser
+        assert_eq!(
+            parse_all(b"\x1bPtmuy;\x1b\\"),
+            [
+                (None, "DcsStart('', t)".to_owned()),
+                (None, "DcsData('muy')".to_owned()),
+                (None, "DcsData(';')".to_owned()),
+                (None, "DcsEnd('')".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tmux_malformed() {
+        let tmux = Some(VTPassthrough::Tmux);
+        // A lone ESC ends the passthrough
+        assert_eq!(
+            parse_all(b"\x1bPtmux;\x1b\x1b[2J\x1b[H"),
+            [
+                (tmux, "Csi('2', '', 'J')".to_owned()),
+                (None, "Csi('', 'H')".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_screen() {
+        let screen = Some(VTPassthrough::Screen);
+        assert_eq!(
+            parse_all(b"\x1bP\x1b]0;title\x07\x1b\\\x1bP\x1b[5 q\x1b\\x"),
+            [
+                (screen, "OscStart(0)".to_owned()),
+                (screen, "OscEnd('0;title')".to_owned()),
+                (screen, "Csi('5', ' ', 'q')".to_owned()),
+                (None, "Raw('x')".to_owned()),
+            ]
+        );
+        // screen splits long sequences across several passthrough strings
+        assert_eq!(
+            parse_all(b"\x1bP\x1b[3\x1b\\\x1bP8m\x1b\\"),
+            [(screen, "Csi('38', '', 'm')".to_owned())]
+        );
+        // Outer text means that the next DCS is not a continuation
+        assert_eq!(
+            parse_all(b"\x1bP\x1b[3\x1b\\x\x1bPq#0\x1b\\"),
+            [
+                (None, "Raw('x')".to_owned()),
+                (None, "DcsStart('', q)".to_owned()),
+                (None, "DcsData('#0')".to_owned()),
+                (None, "DcsEnd('')".to_owned()),
+            ]
+        );
+        assert_eq!(
+            parse_all(b"\x1bP\x1b[3\x1b\\\x1b[m\x1bPq\x1b\\"),
+            [
+                (None, "Csi('', 'm')".to_owned()),
+                (None, "DcsStart('', q)".to_owned()),
+                (None, "DcsEnd('')".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untagged() {
+        let mut parser = VTPassthroughParser::new();
+        let mut events = vec![];
+        parser.feed_with(b"\x1bPtmux;\x1b\x1b[1m\x1b\\", |event: VTEvent| {
+            events.push(format!("{event:?}"));
+        });
+        assert_eq!(events, ["Csi('1', '', 'm')"]);
+    }
+
+    #[test]
+    fn test_wrap_tmux() {
+        let mut output = vec![];
+        let mut events = vec![];
+        VTPushParser::decode_buffer(b"\x1b]0;title\x1b\\\x1b[1m", |event| {
+            events.push(event.to_owned());
+        });
+        let events = events.iter().map(|e| e.borrow()).collect::<Vec<_>>();
+        let len = write_tmux_passthrough(&events, &mut output).unwrap();
+        assert_eq!(
+            output,
+            b"\x1bPtmux;\x1b\x1b]0;title\x1b\x1b\\\x1b\x1b[1m\x1b\\"
+        );
+        assert_eq!(len, output.len());
+
+        let tmux = Some(VTPassthrough::Tmux);
+        assert_eq!(
+            parse_all(&output),
+            [
+                (tmux, "OscStart(0)".to_owned()),
+                (tmux, "OscData('0;title')".to_owned()),
+                (tmux, "OscEnd('')".to_owned()),
+                (tmux, "Csi('1', '', 'm')".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! XTGETTCAP and DECRQSS queries and replies.
+//!
+//! Both queries are sent as DCS strings, and the terminal answers with a DCS
+//! string of its own:
+//!
+//! | Query     | Sequence          | Reply                                     |
+//! |-----------|-------------------|-------------------------------------------|
+//! | XTGETTCAP | `DCS + q hex ST`  | `DCS 1 + r hex=hex ST` or `DCS 0 + r ST`  |
+//! | DECRQSS   | `DCS $ q Pt ST`   | `DCS 1 $ r setting ST` or `DCS 0 $ r ST`  |
+//!
+//! [`DcsReplyDecoder`] assembles the streamed `DcsStart`, `DcsData` and
+//! `DcsEnd` events into a [`DcsReply`].
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::query::{DcsReply, DcsReplyDecoder, DecrqssSetting};
+//!
+//! let mut decoder = DcsReplyDecoder::default();
+//! let mut replies = vec![];
+//! VTPushParser::decode_buffer(b"\x1bP1$r2 q\x1b\\", |event| {
+//!     replies.extend(decoder.event(&event));
+//! });
+//! assert_eq!(replies, [DcsReply::Setting(Some(Box::new(DecrqssSetting::CursorStyle(2))))]);
+//! ```
+use std::io;
+
+use crate::VTPushParser;
+use crate::event::{CSIOwned, DCS, DCSOwned, ParamBufOwned, VTEvent, VTIntermediate, VTOwnedEvent};
+
+/// The maximum length of a reply that will be assembled.
+const MAX_REPLY_LEN: usize = 4096;
+
+/// An XTGETTCAP query for one or more terminfo capabilities: `DCS + q Pt ST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XtGetTcap<'a>(pub &'a [&'a str]);
+
+impl XtGetTcap<'_> {
+    pub fn write_to(&self, mut writer: impl io::Write) -> io::Result<usize> {
+        let names = self
+            .0
+            .iter()
+            .map(hex::encode_upper)
+            .collect::<Vec<_>>()
+            .join(";");
+        write_dcs(&mut writer, b'+', b'q', names.as_bytes())
+    }
+}
+
+/// A DECRQSS query for a setting (eg: `m` for SGR, `r` for DECSTBM or ` q` for
+/// DECSCUSR): `DCS $ q Pt ST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decrqss<'a>(pub &'a [u8]);
+
+impl Decrqss<'_> {
+    pub fn write_to(&self, mut writer: impl io::Write) -> io::Result<usize> {
+        write_dcs(&mut writer, b'$', b'q', self.0)
+    }
+}
+
+fn write_dcs(
+    mut writer: impl io::Write,
+    intermediate: u8,
+    final_byte: u8,
+    data: &[u8],
+) -> io::Result<usize> {
+    let start = VTOwnedEvent::DcsStart(DCSOwned {
+        private: None,
+        params: ParamBufOwned::empty(),
+        intermediates: VTIntermediate::one(intermediate),
+        final_byte,
+    });
+    Ok(start.borrow().write_to(&mut writer)? + VTEvent::DcsEnd(data).write_to(&mut writer)?)
+}
+
+/// A decoded reply to an XTGETTCAP or DECRQSS query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DcsReply {
+    /// An XTGETTCAP reply. Each capability has a value unless it is a boolean
+    /// capability. Terminals may list the names of the capabilities they
+    /// don't recognize in an invalid reply.
+    TermCap {
+        valid: bool,
+        capabilities: Vec<(String, Option<Vec<u8>>)>,
+    },
+    /// A DECRQSS reply, or `None` if the setting was not recognized.
+    Setting(Option<Box<DecrqssSetting>>),
+}
+
+/// A setting reported by DECRQSS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecrqssSetting {
+    /// SGR: `Ps ; ... m`
+    Sgr(ParamBufOwned),
+    /// DECSTBM, the top and bottom margins: `Pt ; Pb r`
+    ScrollRegion(u16, u16),
+    /// DECSCUSR, the cursor style: `Ps SP q`
+    CursorStyle(u16),
+    /// Any other setting, as a CSI sequence.
+    Other(CSIOwned),
+}
+
+impl DecrqssSetting {
+    /// Parses the setting from the body of a CSI sequence (ie: without the
+    /// leading `CSI`).
+    pub fn parse(setting: &[u8]) -> Option<Self> {
+        let mut csi = None;
+        let mut parser = VTPushParser::new();
+        parser.feed_with(b"\x1b[", |_: VTEvent| {});
+        parser.feed_with(setting, |event: VTEvent| {
+            if let VTEvent::Csi(_) = event {
+                csi = Some(event.to_owned());
+            }
+        });
+        let VTOwnedEvent::Csi(csi) = csi? else {
+            return None;
+        };
+
+        let numeric = csi.params.numeric();
+        let param = |i: usize| numeric.get(i).and_then(|p| p.sole());
+        Some(
+            match (csi.private, csi.intermediates.as_ref(), csi.final_byte) {
+                (None, [], b'm') => DecrqssSetting::Sgr(csi.params),
+                (None, [], b'r') if csi.params.len() == 2 => match (param(0), param(1)) {
+                    (Some(top), Some(bottom)) => DecrqssSetting::ScrollRegion(top, bottom),
+                    _ => DecrqssSetting::Other(csi),
+                },
+                (None, b" ", b'q') if csi.params.len() <= 1 => {
+                    DecrqssSetting::CursorStyle(param(0).unwrap_or(0))
+                }
+                _ => DecrqssSetting::Other(csi),
+            },
+        )
+    }
+}
+
+impl DcsReply {
+    /// Decodes a complete reply, returning `None` if this is not an XTGETTCAP
+    /// or DECRQSS reply, or it is malformed.
+    pub fn decode(dcs: &DCS<'_>, data: &[u8]) -> Option<Self> {
+        let kind = ReplyKind::from_dcs(dcs)?;
+        let valid = dcs.params.try_parse::<u8>(0)? == 1;
+        match kind {
+            ReplyKind::TermCap => {
+                let mut capabilities = Vec::new();
+                for capability in data.split(|&b| b == b';').filter(|c| !c.is_empty()) {
+                    let mut parts = capability.splitn(2, |&b| b == b'=');
+                    let name = String::from_utf8(hex::decode(parts.next()?).ok()?).ok()?;
+                    let value = match parts.next() {
+                        Some(value) => Some(hex::decode(value).ok()?),
+                        None => None,
+                    };
+                    capabilities.push((name, value));
+                }
+                Some(DcsReply::TermCap {
+                    valid,
+                    capabilities,
+                })
+            }
+            ReplyKind::Setting if !valid => Some(DcsReply::Setting(None)),
+            ReplyKind::Setting => Some(DcsReply::Setting(Some(Box::new(DecrqssSetting::parse(
+                data,
+            )?)))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplyKind {
+    TermCap,
+    Setting,
+}
+
+impl ReplyKind {
+    fn from_dcs(dcs: &DCS<'_>) -> Option<Self> {
+        if dcs.private.is_some() || dcs.params.len() != 1 || dcs.final_byte != b'r' {
+            return None;
+        }
+        match dcs.intermediates.as_ref() {
+            b"+" => Some(ReplyKind::TermCap),
+            b"$" => Some(ReplyKind::Setting),
+            _ => None,
+        }
+    }
+}
+
+/// Assembles XTGETTCAP and DECRQSS replies from a stream of [`VTEvent`]s.
+#[derive(Debug, Default)]
+pub struct DcsReplyDecoder {
+    /// The reply being assembled.
+    reply: Option<(DCSOwned, Vec<u8>)>,
+}
+
+impl DcsReplyDecoder {
+    /// Handles an event, returning a reply once one has been completed.
+    pub fn event(&mut self, event: &VTEvent<'_>) -> Option<DcsReply> {
+        match event {
+            VTEvent::DcsStart(dcs) => {
+                self.reply = ReplyKind::from_dcs(dcs).map(|_| (dcs.to_owned(), Vec::new()));
+                None
+            }
+            VTEvent::DcsData(data) => {
+                if let Some((_, buffer)) = &mut self.reply {
+                    buffer.extend_from_slice(data);
+                    if buffer.len() > MAX_REPLY_LEN {
+                        self.reply = None;
+                    }
+                }
+                None
+            }
+            VTEvent::DcsEnd(data) => {
+                let (dcs, mut buffer) = self.reply.take()?;
+                buffer.extend_from_slice(data);
+                DcsReply::decode(&dcs.borrow(), &buffer)
+            }
+            VTEvent::DcsCancel => {
+                self.reply = None;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<DcsReply> {
+        let mut decoder = DcsReplyDecoder::default();
+        let mut parser = VTPushParser::new();
+        let mut replies = vec![];
+        for chunk in input.chunks(2) {
+            parser.feed_with(chunk, |event: VTEvent| {
+                replies.extend(decoder.event(&event))
+            });
+        }
+        replies
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut output = vec![];
+        XtGetTcap(&["RGB", "Ms"]).write_to(&mut output).unwrap();
+        Decrqss(b" q").write_to(&mut output).unwrap();
+        assert_eq!(output, b"\x1bP+q524742;4D73\x1b\\\x1bP$q q\x1b\\");
+    }
+
+    #[test]
+    fn test_xtgettcap() {
+        assert_eq!(
+            decode(b"\x1bP1+r524742=382F382F38;616D\x1b\\\x1bP0+r\x1b\\\x1bP0+r5463\x1b\\"),
+            [
+                DcsReply::TermCap {
+                    valid: true,
+                    capabilities: vec![
+                        ("RGB".to_owned(), Some(b"8/8/8".to_vec())),
+                        ("am".to_owned(), None)
+                    ],
+                },
+                DcsReply::TermCap {
+                    valid: false,
+                    capabilities: vec![],
+                },
+                DcsReply::TermCap {
+                    valid: false,
+                    capabilities: vec![("Tc".to_owned(), None)],
+                },
+            ]
+        );
+        // Malformed hex and other DCS strings are ignored
+        assert_eq!(decode(b"\x1bP1+r5247zz\x1b\\\x1bPq#0;2;0;0;0\x1b\\"), []);
+    }
+
+    #[test]
+    fn test_decrqss() {
+        assert_eq!(
+            decode(b"\x1bP1$r0;1;38:2::255:0:0m\x1b\\\x1bP1$r1;24r\x1b\\\x1bP1$r4 q\x1b\\"),
+            [
+                DcsReply::Setting(Some(Box::new(DecrqssSetting::Sgr(ParamBufOwned::new(&[
+                    b"0",
+                    b"1",
+                    b"38:2::255:0:0"
+                ]))))),
+                DcsReply::Setting(Some(Box::new(DecrqssSetting::ScrollRegion(1, 24)))),
+                DcsReply::Setting(Some(Box::new(DecrqssSetting::CursorStyle(4)))),
+            ]
+        );
+        assert_eq!(
+            decode(b"\x1bP1$r62;1\"p\x1b\\\x1bP0$r\x1b\\"),
+            [
+                DcsReply::Setting(Some(Box::new(DecrqssSetting::Other(CSIOwned {
+                    private: None,
+                    params: ParamBufOwned::new(&[b"62", b"1"]),
+                    intermediates: VTIntermediate::one(b'"'),
+                    final_byte: b'p',
+                })))),
+                DcsReply::Setting(None),
+            ]
+        );
+    }
+}
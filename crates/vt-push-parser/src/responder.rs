@@ -0,0 +1,462 @@
+//! A fake terminal that answers queries.
+//!
+//! [`VTResponder`] watches an application's output for terminal queries and
+//! generates the replies that a real terminal would send, as described by a
+//! [`TerminalProfile`]. This is useful for testing terminal applications
+//! headlessly.
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::responder::VTResponder;
+//!
+//! let mut parser = VTPushParser::new();
+//! let mut responder = VTResponder::default();
+//! responder.profile_mut().cursor = (4, 9);
+//! parser.feed_with(b"hello\x1b[6n", &mut responder);
+//! assert_eq!(responder.take_responses(), b"\x1b[5;10R");
+//! ```
+//!
+//! Supported queries:
+//!
+//! | Query                 | Sequence                  | Reply                            |
+//! |-----------------------|---------------------------|----------------------------------|
+//! | DSR status            | `CSI 5 n`                 | `CSI 0 n`                        |
+//! | DSR cursor position   | `CSI 6 n`, `CSI ? 6 n`    | `CSI r ; c R`, `CSI ? r ; c R`   |
+//! | DA1                   | `CSI c`                   | `CSI ? Ps ; ... c`               |
+//! | DA2                   | `CSI > c`                 | `CSI > Pp ; Pv ; Pc c`           |
+//! | DA3                   | `CSI = c`                 | `DCS ! \| unit ST`               |
+//! | DECRQM                | `CSI ? Ps $ p`            | `CSI ? Ps ; Pm $ y`              |
+//! | XTVERSION             | `CSI > q`                 | `DCS > \| name ST`               |
+//! | DECRQSS               | `DCS $ q Pt ST`           | `DCS 1 $ r setting ST`           |
+//! | Default colors        | `OSC 10 ; ? ST`, `OSC 11` | `OSC 10 ; rgb:r/g/b ST`          |
+//! | Text area size        | `CSI 14 t`, `CSI 18 t`    | `CSI 4 ; h ; w t`, `CSI 8 ; r ; c t` |
+//! | Kitty keyboard flags  | `CSI ? u`                 | `CSI ? flags u`                  |
+use crate::VTEventCallback;
+use crate::event::{CSIOwned, DCSOwned, ParamBufOwned, VTEvent, VTIntermediate, VTOwnedEvent};
+
+/// The maximum length of a query string (eg: a DECRQSS request) that will be
+/// assembled. Longer strings are ignored.
+const MAX_QUERY_LEN: usize = 256;
+
+/// The identity and state of the fake terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TerminalProfile {
+    /// The primary device attributes (DA1). The first attribute is the
+    /// conformance level (eg: 62 for VT220).
+    pub device_attributes: Vec<u16>,
+    /// The terminal type, firmware version and ROM cartridge (DA2).
+    pub terminal_id: (u16, u16, u16),
+    /// The terminal unit ID (DA3), as eight hex digits.
+    pub unit_id: String,
+    /// The terminal name and version (XTVERSION).
+    pub version: String,
+    /// The size of the screen in rows and columns.
+    pub size: (u16, u16),
+    /// The size of a character cell in pixels, as height and width.
+    pub cell_size: (u16, u16),
+    /// The zero-based cursor position, as row and column.
+    pub cursor: (u16, u16),
+    /// DEC private modes and their DECRPM states (1 = set, 2 = reset, 3 =
+    /// permanently set, 4 = permanently reset). Modes that are not listed are
+    /// reported as not recognized.
+    pub modes: Vec<(u16, u8)>,
+    /// Settings reported by DECRQSS, as the request and the reply (eg: `m`
+    /// and `0m`). Settings that are not listed are reported as invalid.
+    pub settings: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The default foreground color, with 16 bits per channel.
+    pub foreground: (u16, u16, u16),
+    /// The default background color, with 16 bits per channel.
+    pub background: (u16, u16, u16),
+    /// The kitty keyboard protocol flags, if the protocol is supported.
+    pub kitty_keyboard: Option<u16>,
+}
+
+impl Default for TerminalProfile {
+    /// A VT220-compatible xterm-like terminal with an 80x24 screen.
+    fn default() -> Self {
+        Self {
+            device_attributes: vec![62, 22],
+            terminal_id: (1, 0, 0),
+            unit_id: "00000000".to_owned(),
+            version: concat!("vt-push-parser(", env!("CARGO_PKG_VERSION"), ")").to_owned(),
+            size: (24, 80),
+            cell_size: (16, 8),
+            cursor: (0, 0),
+            modes: vec![(1, 2), (7, 1), (25, 1), (1049, 2), (2004, 2)],
+            settings: vec![
+                (b"m".to_vec(), b"0m".to_vec()),
+                (b"r".to_vec(), b"1;24r".to_vec()),
+            ],
+            foreground: (0xffff, 0xffff, 0xffff),
+            background: (0, 0, 0),
+            kitty_keyboard: None,
+        }
+    }
+}
+
+/// A [`VTEventCallback`] that answers terminal queries.
+///
+/// Replies are accumulated and can be retrieved with
+/// [`VTResponder::take_responses`].
+#[derive(Debug, Default)]
+pub struct VTResponder {
+    profile: TerminalProfile,
+    responses: Vec<u8>,
+    /// The DCS or OSC string being accumulated.
+    string: Option<(StringKind, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringKind {
+    Decrqss,
+    Osc,
+}
+
+impl VTResponder {
+    pub fn new(profile: TerminalProfile) -> Self {
+        Self {
+            profile,
+            responses: Vec::new(),
+            string: None,
+        }
+    }
+
+    pub fn profile(&self) -> &TerminalProfile {
+        &self.profile
+    }
+
+    pub fn profile_mut(&mut self) -> &mut TerminalProfile {
+        &mut self.profile
+    }
+
+    /// The replies generated so far.
+    pub fn responses(&self) -> &[u8] {
+        &self.responses
+    }
+
+    /// Takes the replies generated so far.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    fn respond(&mut self, event: VTEvent<'_>) {
+        let start = self.responses.len();
+        self.responses.resize(start + event.byte_len(), 0);
+        let len = event
+            .encode(&mut self.responses[start..])
+            .expect("byte_len should be sufficient");
+        self.responses.truncate(start + len);
+    }
+
+    fn respond_csi(
+        &mut self,
+        private: Option<u8>,
+        params: &[u16],
+        intermediate: Option<u8>,
+        final_byte: u8,
+    ) {
+        let params = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let params = params.iter().map(|p| p.as_bytes()).collect::<Vec<_>>();
+        let csi = VTOwnedEvent::Csi(CSIOwned {
+            private,
+            params: ParamBufOwned::new(&params),
+            intermediates: intermediate.map(VTIntermediate::one).unwrap_or_default(),
+            final_byte,
+        });
+        self.respond(csi.borrow());
+    }
+
+    fn respond_dcs(
+        &mut self,
+        private: Option<u8>,
+        param: Option<&[u8]>,
+        intermediate: Option<u8>,
+        final_byte: u8,
+        data: &[u8],
+    ) {
+        let dcs = DCSOwned {
+            private,
+            params: ParamBufOwned::new(param.as_slice()),
+            intermediates: intermediate.map(VTIntermediate::one).unwrap_or_default(),
+            final_byte,
+        };
+        self.respond(VTEvent::DcsStart(dcs.borrow()));
+        self.respond(VTEvent::DcsEnd(data));
+    }
+
+    fn handle_csi(
+        &mut self,
+        private: Option<u8>,
+        params: &[Option<u16>],
+        intermediates: VTIntermediate,
+        final_byte: u8,
+    ) {
+        let param = |i: usize| params.get(i).copied().flatten().unwrap_or(0);
+        let profile = &self.profile;
+        match (private, intermediates.is_empty(), final_byte) {
+            // DSR
+            (None, true, b'n') if params.len() == 1 && param(0) == 5 => {
+                self.respond_csi(None, &[0], None, b'n')
+            }
+            (None | Some(b'?'), true, b'n') if params.len() == 1 && param(0) == 6 => {
+                let (row, col) = profile.cursor;
+                self.respond_csi(
+                    private,
+                    &[row.saturating_add(1), col.saturating_add(1)],
+                    None,
+                    b'R',
+                )
+            }
+            // DA1, DA2, DA3
+            (None, true, b'c') if param(0) == 0 => {
+                let attributes = profile.device_attributes.clone();
+                self.respond_csi(Some(b'?'), &attributes, None, b'c')
+            }
+            (Some(b'>'), true, b'c') if param(0) == 0 => {
+                let (pp, pv, pc) = profile.terminal_id;
+                self.respond_csi(Some(b'>'), &[pp, pv, pc], None, b'c')
+            }
+            (Some(b'='), true, b'c') if param(0) == 0 => {
+                let unit_id = profile.unit_id.clone();
+                self.respond_dcs(None, None, Some(b'!'), b'|', unit_id.as_bytes())
+            }
+            // DECRQM
+            (Some(b'?'), false, b'p')
+                if intermediates.const_eq(&VTIntermediate::one(b'$')) && params.len() == 1 =>
+            {
+                let mode = param(0);
+                let state = profile
+                    .modes
+                    .iter()
+                    .find(|(m, _)| *m == mode)
+                    .map(|(_, state)| *state as u16)
+                    .unwrap_or(0);
+                self.respond_csi(Some(b'?'), &[mode, state], Some(b'$'), b'y')
+            }
+            // XTVERSION
+            (Some(b'>'), true, b'q') if param(0) == 0 => {
+                let version = profile.version.clone();
+                self.respond_dcs(Some(b'>'), None, None, b'|', version.as_bytes())
+            }
+            // Window size reports
+            (None, true, b't') if params.len() == 1 && param(0) == 14 => {
+                let (rows, cols) = profile.size;
+                let (height, width) = profile.cell_size;
+                self.respond_csi(
+                    None,
+                    &[4, rows.saturating_mul(height), cols.saturating_mul(width)],
+                    None,
+                    b't',
+                )
+            }
+            (None, true, b't') if params.len() == 1 && param(0) == 18 => {
+                let (rows, cols) = profile.size;
+                self.respond_csi(None, &[8, rows, cols], None, b't')
+            }
+            // Kitty keyboard flags
+            (Some(b'?'), true, b'u') if params.is_empty() => {
+                if let Some(flags) = profile.kitty_keyboard {
+                    self.respond_csi(Some(b'?'), &[flags], None, b'u')
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Appends to the string being accumulated, dropping it if it's too long.
+    fn append(&mut self, data: &[u8]) {
+        if let Some((_, string)) = &mut self.string {
+            string.extend_from_slice(data);
+            if string.len() > MAX_QUERY_LEN {
+                self.string = None;
+            }
+        }
+    }
+
+    fn handle_string(&mut self, kind: StringKind, data: &[u8], used_bel: bool) {
+        match kind {
+            StringKind::Decrqss => {
+                let reply = self
+                    .profile
+                    .settings
+                    .iter()
+                    .find(|(request, _)| request == data)
+                    .map(|(_, reply)| reply.clone());
+                match reply {
+                    Some(reply) => self.respond_dcs(None, Some(b"1"), Some(b'$'), b'r', &reply),
+                    None => self.respond_dcs(None, Some(b"0"), Some(b'$'), b'r', &[]),
+                }
+            }
+            StringKind::Osc => {
+                let (command, (r, g, b)) = match data {
+                    b"10;?" => (10, self.profile.foreground),
+                    b"11;?" => (11, self.profile.background),
+                    _ => return,
+                };
+                let reply = format!("{command};rgb:{r:04x}/{g:04x}/{b:04x}");
+                self.respond(VTEvent::OscStart {
+                    command: Some(command),
+                });
+                self.respond(VTEvent::OscEnd {
+                    data: reply.as_bytes(),
+                    used_bel,
+                });
+            }
+        }
+    }
+}
+
+impl VTEventCallback for VTResponder {
+    fn event(&mut self, event: VTEvent<'_>) {
+        match event {
+            VTEvent::Csi(csi) => {
+                let mut params = [None; 4];
+                let count = csi.params.len();
+                if count > params.len() {
+                    return;
+                }
+                for (i, param) in csi.params.numeric().into_iter().enumerate() {
+                    params[i] = param.sole();
+                }
+                self.handle_csi(
+                    csi.private,
+                    &params[..count],
+                    csi.intermediates,
+                    csi.final_byte,
+                );
+            }
+            VTEvent::DcsStart(dcs) => {
+                let decrqss = dcs.private.is_none()
+                    && dcs.params.is_empty()
+                    && dcs.intermediates.const_eq(&VTIntermediate::one(b'$'))
+                    && dcs.final_byte == b'q';
+                self.string = decrqss.then(|| (StringKind::Decrqss, Vec::new()));
+            }
+            // Only the default color queries are answered
+            VTEvent::OscStart { command } => {
+                self.string =
+                    matches!(command, Some(10 | 11)).then(|| (StringKind::Osc, Vec::new()))
+            }
+            VTEvent::DcsData(data) | VTEvent::OscData(data) => self.append(data),
+            VTEvent::DcsEnd(data) => {
+                self.append(data);
+                if let Some((kind, string)) = self.string.take() {
+                    self.handle_string(kind, &string, false);
+                }
+            }
+            VTEvent::OscEnd { data, used_bel } => {
+                self.append(data);
+                if let Some((kind, string)) = self.string.take() {
+                    self.handle_string(kind, &string, used_bel);
+                }
+            }
+            VTEvent::DcsCancel | VTEvent::OscCancel => self.string = None,
+            _ => {}
+        }
+    }
+}
+
+impl VTEventCallback for &mut VTResponder {
+    fn event(&mut self, event: VTEvent<'_>) {
+        VTEventCallback::event(&mut **self, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VTPushParser;
+
+    fn respond(profile: TerminalProfile, output: &[u8]) -> String {
+        let mut parser = VTPushParser::new();
+        let mut responder = VTResponder::new(profile);
+        // Queries may be split across writes
+        for chunk in output.chunks(3) {
+            parser.feed_with(chunk, &mut responder);
+        }
+        String::from_utf8(responder.take_responses()).unwrap()
+    }
+
+    #[test]
+    fn test_queries() {
+        let profile = TerminalProfile {
+            version: "test(1)".to_owned(),
+            cursor: (2, 3),
+            kitty_keyboard: Some(1),
+            ..Default::default()
+        };
+        let cases: &[(&[u8], &str)] = &[
+            (b"\x1b[5n", "\x1b[0n"),
+            (b"\x1b[6n", "\x1b[3;4R"),
+            (b"\x1b[?6n", "\x1b[?3;4R"),
+            (b"\x1b[c", "\x1b[?62;22c"),
+            (b"\x1b[0c", "\x1b[?62;22c"),
+            (b"\x1b[>c", "\x1b[>1;0;0c"),
+            (b"\x1b[=c", "\x1bP!|00000000\x1b\\"),
+            (b"\x1b[?2004$p", "\x1b[?2004;2$y"),
+            (b"\x1b[?2026$p", "\x1b[?2026;0$y"),
+            (b"\x1b[>q", "\x1bP>|test(1)\x1b\\"),
+            (b"\x1bP$qm\x1b\\", "\x1bP1$r0m\x1b\\"),
+            (b"\x1bP$qx\x1b\\", "\x1bP0$r\x1b\\"),
+            (b"\x1b]10;?\x1b\\", "\x1b]10;rgb:ffff/ffff/ffff\x1b\\"),
+            (b"\x1b]11;?\x07", "\x1b]11;rgb:0000/0000/0000\x07"),
+            (b"\x1b[14t", "\x1b[4;384;640t"),
+            (b"\x1b[18t", "\x1b[8;24;80t"),
+            (b"\x1b[?u", "\x1b[?1u"),
+            // Not queries
+            (b"\x1b[2J\x1b]0;?\x07\x1b[1;2n", ""),
+        ];
+        for (query, reply) in cases {
+            assert_eq!(
+                respond(profile.clone(), query),
+                *reply,
+                "{}",
+                String::from_utf8_lossy(query)
+            );
+        }
+
+        // Kitty keyboard queries are ignored if the protocol isn't supported
+        assert_eq!(respond(TerminalProfile::default(), b"\x1b[?u"), "");
+        // Reports saturate rather than overflow
+        let profile = TerminalProfile {
+            cursor: (u16::MAX, u16::MAX),
+            size: (u16::MAX, u16::MAX),
+            ..Default::default()
+        };
+        assert_eq!(
+            respond(profile, b"\x1b[6n\x1b[14t"),
+            "\x1b[65535;65535R\x1b[4;65535;65535t"
+        );
+    }
+
+    #[test]
+    fn test_long_strings() {
+        let mut parser = VTPushParser::new();
+        let mut responder = VTResponder::default();
+        // Strings that aren't queries are never accumulated
+        parser.feed_with(b"\x1b]1337;File=inline=1:", &mut responder);
+        parser.feed_with(&[b'A'; 1000], &mut responder);
+        assert!(responder.string.is_none());
+        parser.feed_with(b"\x07", &mut responder);
+
+        // Queries that are too long are dropped
+        let mut query = b"\x1bP$q".to_vec();
+        query.resize(MAX_QUERY_LEN + 100, b'm');
+        query.extend_from_slice(b"\x1b\\\x1b]11;?\x07");
+        for chunk in query.chunks(64) {
+            parser.feed_with(chunk, &mut responder);
+        }
+        assert_eq!(
+            responder.take_responses(),
+            b"\x1b]11;rgb:0000/0000/0000\x07"
+        );
+    }
+
+    #[test]
+    fn test_interleaved_output() {
+        let mut parser = VTPushParser::new();
+        let mut responder = VTResponder::default();
+        parser.feed_with(b"\x1b[1mtext\x1b[5n\x1b[0mmore\x1b[c", &mut responder);
+        assert_eq!(responder.responses(), b"\x1b[0n\x1b[?62;22c");
+    }
+}
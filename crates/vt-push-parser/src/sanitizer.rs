@@ -0,0 +1,412 @@
+//! A sanitizer for untrusted terminal output.
+//!
+//! Output from an untrusted program can do much more than draw text: it can
+//! set the window title, write to the clipboard (OSC 52), make the terminal
+//! type a reply into the input (eg: DECRQSS or title reporting), disguise a
+//! hyperlink, or end a bracketed paste early.
+//!
+//! [`VTSanitizer`] re-encodes the events that a [`VTSanitizerPolicy`] allows
+//! and removes everything else, keeping a record of what was removed. C0
+//! controls that are not allowed are replaced with their visible forms from
+//! the Control Pictures block (eg: `␛` for ESC).
+//!
+//! ```rust
+//! use vt_push_parser::VTPushParser;
+//! use vt_push_parser::sanitizer::{VTRemoved, VTSanitizer};
+//!
+//! let mut parser = VTPushParser::new();
+//! let mut sanitizer = VTSanitizer::default();
+//! parser.feed_with(b"\x1b[1mok\x1b]52;c;cm0gLXJm\x07\x1b[0m\x07", &mut sanitizer);
+//! assert_eq!(sanitizer.take_output(), "\x1b[1mok\x1b[0m␇".as_bytes());
+//! assert_eq!(sanitizer.take_removed(), [VTRemoved::Osc(Some(52)), VTRemoved::C0(0x07)]);
+//! ```
+use crate::event::{DCSOwned, OscParams, VTEvent, VTOwnedEvent};
+use crate::text::{VTTextDecoder, VTTextEvent};
+use crate::{DEL, VTEventCallback};
+
+/// The longest OSC 8 hyperlink that will be kept.
+const MAX_HYPERLINK_LEN: usize = 4096;
+
+/// Replaces C1 controls and invalid UTF-8 in the text.
+const REPLACEMENT: &str = "\u{fffd}";
+
+/// The OSC command number of hyperlinks.
+const HYPERLINK_OSC: u16 = 8;
+
+/// The sequences that a [`VTSanitizer`] keeps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VTSanitizerPolicy {
+    /// Keep SGR (`CSI ... m`), which sets colors and text attributes.
+    pub sgr: bool,
+    /// Keep cursor motion: CUU, CUD, CUF, CUB, CNL, CPL, CHA, CUP, HVP, VPA
+    /// and HPA, and saving and restoring the cursor with DECSC and DECRC.
+    pub cursor: bool,
+    /// Keep erasing the screen or line (ED and EL).
+    pub erase: bool,
+    /// Keep OSC 8 hyperlinks to URIs with one of these schemes (eg:
+    /// `https`). The text of other hyperlinks is kept without the link.
+    pub hyperlink_schemes: Vec<String>,
+    /// The C0 controls to keep, as a bitmask of `1 << byte`. DEL is never
+    /// kept.
+    pub controls: u32,
+    /// Replace other C0 controls with their visible forms, rather than
+    /// removing them.
+    pub visible_controls: bool,
+}
+
+impl Default for VTSanitizerPolicy {
+    /// Keeps SGR, cursor motion, BS, HT, LF and CR.
+    fn default() -> Self {
+        Self {
+            sgr: true,
+            cursor: true,
+            erase: false,
+            hyperlink_schemes: vec![],
+            controls: 1 << 0x08 | 1 << 0x09 | 1 << 0x0a | 1 << 0x0d,
+            visible_controls: true,
+        }
+    }
+}
+
+impl VTSanitizerPolicy {
+    fn allows(&self, event: &VTEvent<'_>) -> bool {
+        match event {
+            VTEvent::Csi(csi) if csi.private.is_none() && csi.intermediates.is_empty() => {
+                match csi.final_byte {
+                    b'm' => self.sgr,
+                    b'A' | b'B' | b'C' | b'D' | b'E' | b'F' | b'G' | b'H' | b'f' | b'd' | b'`' => {
+                        self.cursor
+                    }
+                    b'J' | b'K' => self.erase,
+                    _ => false,
+                }
+            }
+            VTEvent::Esc(esc) if esc.private.is_none() && esc.intermediates.is_empty() => {
+                matches!(esc.final_byte, b'7' | b'8') && self.cursor
+            }
+            _ => false,
+        }
+    }
+
+    fn allows_hyperlink(&self, uri: &[u8]) -> bool {
+        // Closing a hyperlink is always allowed
+        if uri.is_empty() {
+            return true;
+        }
+        let Some(colon) = uri.iter().position(|&b| b == b':') else {
+            return false;
+        };
+        self.hyperlink_schemes
+            .iter()
+            .any(|scheme| scheme.as_bytes().eq_ignore_ascii_case(&uri[..colon]))
+    }
+}
+
+/// Something that was removed by a [`VTSanitizer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VTRemoved {
+    /// A C0 control.
+    C0(u8),
+    /// A C1 control in the text, as its code point (eg: `0x9b` for CSI).
+    C1(u8),
+    /// An escape sequence or CSI sequence.
+    Sequence(VTOwnedEvent),
+    /// An OSC string, by its command number. This includes hyperlinks that
+    /// are not allowed.
+    Osc(Option<u16>),
+    /// A DCS string.
+    Dcs(DCSOwned),
+}
+
+/// A [`VTEventCallback`] that re-encodes the allowed events. See the [module
+/// documentation](self).
+///
+/// The sanitized output is accumulated and can be retrieved with
+/// [`VTSanitizer::take_output`]. A character that is split between two `Raw`
+/// events is held back until the rest of it arrives, so call
+/// [`VTSanitizer::finish`] at the end of the input.
+#[derive(Debug, Default)]
+pub struct VTSanitizer {
+    policy: VTSanitizerPolicy,
+    decoder: VTTextDecoder,
+    output: Vec<u8>,
+    removed: Vec<VTRemoved>,
+    /// The OSC 8 hyperlink being accumulated.
+    hyperlink: Option<Vec<u8>>,
+}
+
+impl VTSanitizer {
+    pub fn new(policy: VTSanitizerPolicy) -> Self {
+        Self {
+            policy,
+            decoder: VTTextDecoder::new(),
+            output: Vec::new(),
+            removed: Vec::new(),
+            hyperlink: None,
+        }
+    }
+
+    pub fn policy(&self) -> &VTSanitizerPolicy {
+        &self.policy
+    }
+
+    /// Take the sanitized output.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Take the record of what was removed.
+    pub fn take_removed(&mut self) -> Vec<VTRemoved> {
+        std::mem::take(&mut self.removed)
+    }
+
+    /// Replaces an incomplete character at the end of the input with U+FFFD.
+    pub fn finish(&mut self) {
+        let mut decoder = std::mem::take(&mut self.decoder);
+        decoder.flush(|event| self.text_event(event));
+    }
+
+    fn write(&mut self, event: VTEvent<'_>) {
+        event
+            .write_to(&mut self.output)
+            .expect("writing to a Vec can't fail");
+    }
+
+    /// Writes a C0 control, or its visible form if it isn't allowed.
+    fn control(&mut self, c0: u8) {
+        let keep = 1u32
+            .checked_shl(c0 as u32)
+            .is_some_and(|bit| self.policy.controls & bit != 0);
+        if keep {
+            self.output.push(c0);
+            return;
+        }
+        if self.policy.visible_controls {
+            // DEL's picture follows SP's, rather than being at 0x2400 + 0x7f
+            let picture = if c0 == DEL {
+                0x2421
+            } else {
+                0x2400 + c0 as u32
+            };
+            let visible = char::from_u32(picture).unwrap();
+            self.output
+                .extend_from_slice(visible.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        self.removed.push(VTRemoved::C0(c0));
+    }
+
+    /// Writes text, replacing C1 controls with U+FFFD. The parser leaves HT,
+    /// LF and CR in the text.
+    fn text(&mut self, mut text: &str) {
+        while let Some(i) = text.find(|c| matches!(c, '\0'..='\x1f' | '\u{80}'..='\u{9f}')) {
+            let c = text[i..].chars().next().unwrap();
+            self.output.extend_from_slice(&text.as_bytes()[..i]);
+            if c < ' ' {
+                self.control(c as u8);
+            } else {
+                self.output.extend_from_slice(REPLACEMENT.as_bytes());
+                self.removed.push(VTRemoved::C1(c as u8));
+            }
+            text = &text[i + c.len_utf8()..];
+        }
+        self.output.extend_from_slice(text.as_bytes());
+    }
+
+    /// Replaces bytes that are not valid UTF-8 with U+FFFD.
+    fn invalid(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if (0x80..=0x9f).contains(&b) {
+                self.removed.push(VTRemoved::C1(b));
+            }
+        }
+        self.output.extend_from_slice(REPLACEMENT.as_bytes());
+    }
+
+    fn text_event(&mut self, event: VTTextEvent<'_>) {
+        match event {
+            VTTextEvent::Text(text) => self.text(text),
+            VTTextEvent::InvalidUtf8(bytes) => self.invalid(bytes),
+            VTTextEvent::Event(event) => self.sequence(event),
+        }
+    }
+
+    fn hyperlink(&mut self, data: Vec<u8>, used_bel: bool) {
+        let uri = OscParams::new(&data).remainder(2).unwrap_or_default();
+        if self.policy.allows_hyperlink(uri) {
+            self.write(VTEvent::OscStart {
+                command: Some(HYPERLINK_OSC),
+            });
+            self.write(VTEvent::OscEnd {
+                data: &data,
+                used_bel,
+            });
+        } else {
+            self.removed.push(VTRemoved::Osc(Some(HYPERLINK_OSC)));
+        }
+    }
+
+    /// Handles an event other than text.
+    fn sequence(&mut self, event: VTEvent<'_>) {
+        match event {
+            VTEvent::Raw(_) => unreachable!("text is decoded first"),
+            VTEvent::C0(c0) => self.control(c0),
+            VTEvent::DcsStart(dcs) => self.removed.push(VTRemoved::Dcs(dcs.to_owned())),
+            VTEvent::DcsData(_) | VTEvent::DcsEnd(_) | VTEvent::DcsCancel => {}
+            VTEvent::OscStart { command } => {
+                if command == Some(HYPERLINK_OSC) {
+                    self.hyperlink = Some(Vec::new());
+                } else {
+                    self.removed.push(VTRemoved::Osc(command));
+                }
+            }
+            VTEvent::OscData(data) => {
+                if let Some(hyperlink) = &mut self.hyperlink {
+                    hyperlink.extend_from_slice(data);
+                    if hyperlink.len() > MAX_HYPERLINK_LEN {
+                        self.hyperlink = None;
+                        self.removed.push(VTRemoved::Osc(Some(HYPERLINK_OSC)));
+                    }
+                }
+            }
+            VTEvent::OscEnd { data, used_bel } => {
+                if let Some(mut hyperlink) = self.hyperlink.take() {
+                    hyperlink.extend_from_slice(data);
+                    self.hyperlink(hyperlink, used_bel);
+                }
+            }
+            VTEvent::OscCancel => self.hyperlink = None,
+            event if self.policy.allows(&event) => self.write(event),
+            event => self.removed.push(VTRemoved::Sequence(event.to_owned())),
+        }
+    }
+}
+
+impl VTEventCallback for VTSanitizer {
+    fn event(&mut self, event: VTEvent<'_>) {
+        let mut decoder = std::mem::take(&mut self.decoder);
+        decoder.event(event, |event| self.text_event(event));
+        self.decoder = decoder;
+    }
+}
+
+impl VTEventCallback for &mut VTSanitizer {
+    fn event(&mut self, event: VTEvent<'_>) {
+        VTEventCallback::event(&mut **self, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VTPushParser;
+
+    fn sanitize(policy: VTSanitizerPolicy, input: &[u8]) -> (String, Vec<String>) {
+        let mut parser = VTPushParser::new();
+        let mut sanitizer = VTSanitizer::new(policy);
+        for chunk in input.chunks(3) {
+            parser.feed_with(chunk, &mut sanitizer);
+        }
+        sanitizer.finish();
+        let removed = sanitizer
+            .take_removed()
+            .iter()
+            .map(|removed| format!("{removed:?}"))
+            .collect();
+        (String::from_utf8(sanitizer.take_output()).unwrap(), removed)
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let (output, removed) = sanitize(
+            VTSanitizerPolicy::default(),
+            b"\x1b]0;evil\x07\x1b[31;1mred\x1b[0m\r\n\x1b[2;3H\x1b7x\x1b8\x1b[2J\x1b[201~\x1bP$qm\x1b\\\x1b[21t\x1b[?2004l\x1bcy\x1b\x1b[A",
+        );
+        assert_eq!(
+            output,
+            "\x1b[31;1mred\x1b[0m\r\n\x1b[2;3H\x1b7x\x1b8y␛\x1b[A"
+        );
+        assert_eq!(
+            removed,
+            [
+                "Osc(Some(0))",
+                "Sequence(Csi('2', '', 'J'))",
+                "Sequence(Csi('201', '', '~'))",
+                "Dcs(DcsStart(, '$', q))",
+                "Sequence(Csi('21', '', 't'))",
+                "Sequence(Csi('?', '2004', '', 'l'))",
+                "Sequence(Esc('', c))",
+                "C0(27)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_controls() {
+        let (output, removed) = sanitize(VTSanitizerPolicy::default(), b"a\x07\tb\x00c\x0e");
+        assert_eq!(output, "a␇\tb␀c␎");
+        assert_eq!(removed, ["C0(7)", "C0(0)", "C0(14)"]);
+
+        let policy = VTSanitizerPolicy {
+            visible_controls: false,
+            controls: 1 << 0x07,
+            ..Default::default()
+        };
+        let (output, _) = sanitize(policy, b"a\x07\tb");
+        assert_eq!(output, "a\x07b");
+        let (output, removed) = sanitize(VTSanitizerPolicy::default(), b"a\x7fb");
+        assert_eq!(output, "a\u{2421}b");
+        assert_eq!(removed, ["C0(127)"]);
+        let policy = VTSanitizerPolicy {
+            visible_controls: false,
+            controls: u32::MAX,
+            ..Default::default()
+        };
+        let (output, _) = sanitize(policy, b"a\x7fb");
+        assert_eq!(output, "ab");
+    }
+
+    #[test]
+    fn test_c1_controls() {
+        let (output, removed) =
+            sanitize(VTSanitizerPolicy::default(), "a\u{9b}31mb\u{e9}".as_bytes());
+        assert_eq!(output, "a\u{fffd}31mb\u{e9}");
+        assert_eq!(removed, ["C1(155)"]);
+        let (output, removed) = sanitize(VTSanitizerPolicy::default(), b"a\x9b2Jb\xff");
+        assert_eq!(output, "a\u{fffd}2Jb\u{fffd}");
+        assert_eq!(removed, ["C1(155)"]);
+    }
+
+    #[test]
+    fn test_split_characters() {
+        let mut parser = VTPushParser::new();
+        let mut sanitizer = VTSanitizer::default();
+        for chunk in [&b"caf\xc3"[..], b"\xa9 ok \xe2\x9c", b"\x85\x1b[1m\xc3"] {
+            parser.feed_with(chunk, &mut sanitizer);
+        }
+        sanitizer.finish();
+        assert_eq!(
+            String::from_utf8(sanitizer.take_output()).unwrap(),
+            "caf\u{e9} ok \u{2705}\x1b[1m\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        let input =
+            b"\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\ \x1b]8;id=1;file:///etc/passwd\x07x\x1b]8;;\x07";
+        let (output, removed) = sanitize(VTSanitizerPolicy::default(), input);
+        assert_eq!(output, "link\x1b]8;;\x1b\\ x\x1b]8;;\x07");
+        assert_eq!(removed, ["Osc(Some(8))", "Osc(Some(8))"]);
+
+        let policy = VTSanitizerPolicy {
+            hyperlink_schemes: vec!["https".to_owned()],
+            ..Default::default()
+        };
+        let (output, removed) = sanitize(policy, input);
+        assert_eq!(
+            output,
+            "\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\ x\x1b]8;;\x07"
+        );
+        assert_eq!(removed, ["Osc(Some(8))"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Word-at-a-time scanning for the byte that ends a run of text or string
+//! payload.
+//!
+//! Each scan loads eight bytes at a time and flags candidate bytes with the
+//! usual SWAR bit tricks, then confirms the first candidate against the
+//! parser's lookup table. A predicate may flag more bytes than the table
+//! accepts (eg: TAB in ground text), but must never miss one.
+//!
+//! The tricks below may also flag bytes above (ie: later than) a true match
+//! because of carries and borrows between lanes, but never below one, so the
+//! lowest flagged lane of a little-endian word is always a true match.
+
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// Flags the bytes less than `n` (which must be at most 0x80).
+#[inline(always)]
+const fn less_than(x: u64, n: u8) -> u64 {
+    x.wrapping_sub(LO * n as u64) & !x & HI
+}
+
+/// Flags the bytes greater than `n` (which must be at most 0x7f).
+#[inline(always)]
+const fn greater_than(x: u64, n: u8) -> u64 {
+    (x.wrapping_add(LO * (0x7f - n) as u64) | x) & HI
+}
+
+/// Flags the bytes equal to `n`.
+#[inline(always)]
+const fn equal_to(x: u64, n: u8) -> u64 {
+    less_than(x ^ (LO * n as u64), 1)
+}
+
+/// C0 controls and DEL, which end ground text and DCS passthrough.
+#[inline(always)]
+pub(crate) const fn c0_or_del(x: u64) -> u64 {
+    less_than(x, 0x20) | equal_to(x, 0x7f)
+}
+
+/// C0 controls and anything but CSI parameter bytes, which end an ignored
+/// CSI sequence.
+#[inline(always)]
+pub(crate) const fn csi_ignore(x: u64) -> u64 {
+    less_than(x, 0x20) | greater_than(x, 0x3f)
+}
+
+/// Anything but printable ASCII, which ends a run of OSC string bytes.
+#[inline(always)]
+pub(crate) const fn not_printable(x: u64) -> u64 {
+    less_than(x, 0x20) | greater_than(x, 0x7e)
+}
+
+/// C0 controls, which end a SOS/PM/APC string.
+#[inline(always)]
+pub(crate) const fn c0(x: u64) -> u64 {
+    less_than(x, 0x20)
+}
+
+/// Returns the index of the first byte at or after `i` for which `table` is
+/// true, or the length of the input if there is none.
+#[inline(always)]
+pub(crate) fn find(
+    input: &[u8],
+    mut i: usize,
+    candidates: impl Fn(u64) -> u64,
+    table: &[bool; 256],
+) -> usize {
+    while let Some(word) = input.get(i..i + 8) {
+        let found = candidates(u64::from_le_bytes(word.try_into().unwrap()));
+        if found == 0 {
+            i += 8;
+            continue;
+        }
+        i += found.trailing_zeros() as usize / 8;
+        if table[input[i] as usize] {
+            return i;
+        }
+        i += 1;
+    }
+    input[i..]
+        .iter()
+        .position(|b| table[*b as usize])
+        .map_or(input.len(), |n| i + n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(candidates: impl Fn(u64) -> u64 + Copy, table: &[bool; 256]) {
+        // Every byte value, in every lane, behind every other byte value
+        for before in 0..=255u8 {
+            for b in 0..=255u8 {
+                for lane in 0..8 {
+                    let mut input = [b'a'; 19];
+                    input[..lane + 1].fill(before);
+                    input[lane + 1] = b;
+                    let expected = input
+                        .iter()
+                        .position(|b| table[*b as usize])
+                        .unwrap_or(input.len());
+                    assert_eq!(
+                        find(&input, 0, candidates, table),
+                        expected,
+                        "{before:#x} then {b:#x} in lane {}",
+                        lane + 1
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan() {
+        let table = |f: fn(u8) -> bool| std::array::from_fn(|b| f(b as u8));
+        check(c0_or_del, &table(|b| b < 0x20 || b == 0x7f));
+        check(c0_or_del, &table(|b| b == 0x1b));
+        check(csi_ignore, &table(|b| !(0x20..=0x3f).contains(&b)));
+        check(not_printable, &table(|b| !(0x20..=0x7e).contains(&b)));
+        check(c0, &table(|b| b == 0x18 || b == 0x1a || b == 0x1b));
+    }
+}
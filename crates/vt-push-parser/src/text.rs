@@ -0,0 +1,245 @@
+//! UTF-8 aware text events.
+//!
+//! [`VTEvent::Raw`] carries arbitrary bytes, and a multi-byte character that
+//! is split between two calls to [`VTPushParser::feed_with`] arrives as two
+//! separate `Raw` events. [`VTTextParser`] holds back an incomplete character
+//! at the end of a `Raw` event until the rest of it arrives, and emits
+//! [`VTTextEvent::Text`] for valid text and [`VTTextEvent::InvalidUtf8`] for
+//! bytes that are not valid UTF-8.
+//!
+//! Text that is already complete is borrowed directly from the input: only
+//! the bytes of a split character are copied.
+//!
+//! ```rust
+//! use vt_push_parser::text::{VTTextEvent, VTTextParser};
+//!
+//! let mut parser = VTTextParser::new();
+//! let mut text = vec![];
+//! let input = "✅ done".as_bytes();
+//! for chunk in [&input[..2], &input[2..]] {
+//!     parser.feed_with(chunk, |event| {
+//!         if let VTTextEvent::Text(s) = event {
+//!             text.push(s.to_owned());
+//!         }
+//!     });
+//! }
+//! assert_eq!(text, ["✅", " done"]);
+//! ```
+use crate::event::VTEvent;
+use crate::{VT_PARSER_INTEREST_DEFAULT, VTPushParser};
+
+/// An event from a [`VTTextParser`] or [`VTTextDecoder`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum VTTextEvent<'a> {
+    /// Valid UTF-8 text.
+    Text(&'a str),
+    /// Bytes that are not valid UTF-8, including an incomplete character that
+    /// was interrupted by another event.
+    InvalidUtf8(&'a [u8]),
+    /// Any event other than [`VTEvent::Raw`].
+    Event(VTEvent<'a>),
+}
+
+/// Decodes the `Raw` events from a parser into UTF-8 text, holding back
+/// incomplete characters between events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VTTextDecoder {
+    /// An incomplete character from the end of the last `Raw` event.
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl VTTextDecoder {
+    pub const fn new() -> Self {
+        Self {
+            partial: [0; 4],
+            partial_len: 0,
+        }
+    }
+
+    /// Whether an incomplete character is being held back.
+    pub fn has_partial(&self) -> bool {
+        self.partial_len > 0
+    }
+
+    /// Handles an event from the parser.
+    pub fn event(&mut self, event: VTEvent<'_>, mut cb: impl FnMut(VTTextEvent<'_>)) {
+        match event {
+            VTEvent::Raw(raw) => self.raw(raw, &mut cb),
+            event => {
+                self.flush(&mut cb);
+                cb(VTTextEvent::Event(event));
+            }
+        }
+    }
+
+    /// Emits an incomplete character as invalid (eg: at the end of the
+    /// input).
+    pub fn flush(&mut self, mut cb: impl FnMut(VTTextEvent<'_>)) {
+        if self.partial_len > 0 {
+            let len = std::mem::take(&mut self.partial_len);
+            cb(VTTextEvent::InvalidUtf8(&self.partial[..len]));
+        }
+    }
+
+    fn raw(&mut self, mut raw: &[u8], cb: &mut impl FnMut(VTTextEvent<'_>)) {
+        // Complete the held back character, one byte at a time
+        while self.partial_len > 0 {
+            let Some((&b, rest)) = raw.split_first() else {
+                return;
+            };
+            self.partial[self.partial_len] = b;
+            match std::str::from_utf8(&self.partial[..self.partial_len + 1]) {
+                Ok(s) => {
+                    cb(VTTextEvent::Text(s));
+                    self.partial_len = 0;
+                    raw = rest;
+                }
+                Err(e) if e.error_len().is_none() => {
+                    self.partial_len += 1;
+                    raw = rest;
+                }
+                // This byte can't continue the character, so it is decoded
+                // separately
+                Err(_) => self.flush(&mut *cb),
+            }
+        }
+
+        let mut chunks = raw.utf8_chunks().peekable();
+        while let Some(chunk) = chunks.next() {
+            if !chunk.valid().is_empty() {
+                cb(VTTextEvent::Text(chunk.valid()));
+            }
+            let invalid = chunk.invalid();
+            if invalid.is_empty() {
+                continue;
+            }
+            let incomplete = chunks.peek().is_none()
+                && std::str::from_utf8(invalid).is_err_and(|e| e.error_len().is_none());
+            if incomplete {
+                self.partial[..invalid.len()].copy_from_slice(invalid);
+                self.partial_len = invalid.len();
+            } else {
+                cb(VTTextEvent::InvalidUtf8(invalid));
+            }
+        }
+    }
+}
+
+/// A parser that emits UTF-8 text rather than raw bytes. See the [module
+/// documentation](self).
+pub struct VTTextParser<const INTEREST: u8 = VT_PARSER_INTEREST_DEFAULT> {
+    parser: VTPushParser<INTEREST>,
+    decoder: VTTextDecoder,
+}
+
+impl Default for VTTextParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VTTextParser {
+    pub const fn new() -> Self {
+        Self::new_with_interest()
+    }
+}
+
+impl<const INTEREST: u8> VTTextParser<INTEREST> {
+    pub const fn new_with_interest() -> Self {
+        Self {
+            parser: VTPushParser::new_with_interest::<INTEREST>(),
+            decoder: VTTextDecoder::new(),
+        }
+    }
+
+    /// Feed bytes into the parser.
+    pub fn feed_with(&mut self, input: &[u8], mut cb: impl FnMut(VTTextEvent<'_>)) {
+        let decoder = &mut self.decoder;
+        self.parser
+            .feed_with(input, |event: VTEvent| decoder.event(event, &mut cb));
+    }
+
+    /// Emits any incomplete character at the end of the input as invalid.
+    pub fn finish(&mut self, cb: impl FnMut(VTTextEvent<'_>)) {
+        self.decoder.flush(cb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = VTTextParser::new();
+        let mut events = vec![];
+        for chunk in chunks {
+            parser.feed_with(chunk, |event| events.push(format!("{event:?}")));
+        }
+        parser.finish(|event| events.push(format!("{event:?}")));
+        events
+    }
+
+    #[test]
+    fn test_split_characters() {
+        let input = "a✅b🛜".as_bytes();
+        for split in 0..=input.len() {
+            let text = parse(&[&input[..split], &input[split..]])
+                .iter()
+                .map(|e| {
+                    e.strip_prefix("Text(\"")
+                        .unwrap()
+                        .strip_suffix("\")")
+                        .unwrap()
+                })
+                .collect::<String>();
+            assert_eq!(text, "a✅b🛜", "split at {split}");
+        }
+        // Split into single bytes
+        let chunks = input.chunks(1).collect::<Vec<_>>();
+        assert_eq!(
+            parse(&chunks),
+            ["Text(\"a\")", "Text(\"✅\")", "Text(\"b\")", "Text(\"🛜\")"]
+        );
+    }
+
+    #[test]
+    fn test_zero_copy() {
+        let input = "plain text".as_bytes();
+        let mut parser = VTTextParser::new();
+        parser.feed_with(input, |event| {
+            let VTTextEvent::Text(text) = event else {
+                panic!("unexpected {event:?}");
+            };
+            assert_eq!(text.as_ptr(), input.as_ptr());
+        });
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            parse(&[b"a\xffb\xe2\x9c", b"c"]),
+            [
+                "Text(\"a\")",
+                "InvalidUtf8([255])",
+                "Text(\"b\")",
+                "InvalidUtf8([226, 156])",
+                "Text(\"c\")"
+            ]
+        );
+        // An incomplete character interrupted by an escape sequence
+        assert_eq!(
+            parse(&[b"\xe2\x9c\x1b[1m\x85"]),
+            [
+                "InvalidUtf8([226, 156])",
+                "Event(Csi('1', '', 'm'))",
+                "InvalidUtf8([133])"
+            ]
+        );
+        // An incomplete character at the end of the input
+        assert_eq!(
+            parse(&[b"x\xf0\x9f", b"\x9b"]),
+            ["Text(\"x\")", "InvalidUtf8([240, 159, 155])"]
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! tmux control mode (`tmux -CC`) output.
+//!
+//! In control mode, tmux writes line-based notifications instead of drawing
+//! the screen. Command replies are wrapped in `%begin` and `%end` (or
+//! `%error`) lines, and the output of each pane is sent as `%output %pane
+//! data`, where `data` is the pane's terminal output with control characters
+//! and backslashes escaped as `\ooo` octal.
+//!
+//! [`TmuxControlParser`] demultiplexes the notifications, unescapes the pane
+//! output and parses each pane's output with its own [`VTPushParser`].
+//!
+//! ```rust
+//! use vt_push_parser::tmux::{TmuxControlEvent, TmuxControlParser};
+//!
+//! let mut parser = TmuxControlParser::new();
+//! let mut events = vec![];
+//! parser.feed_with(b"%output %1 \\033[1mhi\n%output %2 ok\n", |event| {
+//!     if let TmuxControlEvent::Output { pane, event } = event {
+//!         events.push((pane, format!("{event:?}")));
+//!     }
+//! });
+//! assert_eq!(events, [
+//!     (1, "Csi('1', '', 'm')".to_owned()),
+//!     (1, "Raw('hi')".to_owned()),
+//!     (2, "Raw('ok')".to_owned()),
+//! ]);
+//! ```
+use std::collections::HashMap;
+
+use crate::VTPushParser;
+use crate::event::VTEvent;
+
+/// The DCS that tmux writes when entering control mode from `tmux -CC`.
+const CONTROL_MODE_START: &[u8] = b"\x1bP1000p";
+/// The ST that tmux writes when leaving control mode from `tmux -CC`.
+const CONTROL_MODE_END: &[u8] = b"\x1b\\";
+
+/// An event from tmux control mode.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TmuxControlEvent<'a> {
+    /// An event from a pane's output (`%output` or `%extended-output`).
+    Output { pane: u32, event: VTEvent<'a> },
+    /// The reply to a command, from `%begin` to `%end` or `%error`. The
+    /// output lines are separated by `\n`.
+    Reply {
+        time: u64,
+        number: u64,
+        success: bool,
+        output: &'a [u8],
+    },
+    /// Any other notification.
+    Notification(TmuxNotification<'a>),
+}
+
+/// A tmux control mode notification. Windows (`@1`), panes (`%1`) and
+/// sessions (`$1`) are identified by their numeric IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TmuxNotification<'a> {
+    /// `%layout-change @window layout visible-layout flags`
+    LayoutChange {
+        window: u32,
+        layout: &'a [u8],
+        visible_layout: Option<&'a [u8]>,
+        flags: Option<&'a [u8]>,
+    },
+    /// `%window-add @window`
+    WindowAdd { window: u32 },
+    /// `%window-close @window`
+    WindowClose { window: u32 },
+    /// `%window-renamed @window name`
+    WindowRenamed { window: u32, name: &'a [u8] },
+    /// `%window-pane-changed @window %pane`
+    WindowPaneChanged { window: u32, pane: u32 },
+    /// `%unlinked-window-add @window`
+    UnlinkedWindowAdd { window: u32 },
+    /// `%unlinked-window-close @window`
+    UnlinkedWindowClose { window: u32 },
+    /// `%session-changed $session name`
+    SessionChanged { session: u32, name: &'a [u8] },
+    /// `%session-renamed name`
+    SessionRenamed { name: &'a [u8] },
+    /// `%session-window-changed $session @window`
+    SessionWindowChanged { session: u32, window: u32 },
+    /// `%sessions-changed`
+    SessionsChanged,
+    /// `%pane-mode-changed %pane`
+    PaneModeChanged { pane: u32 },
+    /// `%pause %pane`
+    Pause { pane: u32 },
+    /// `%continue %pane`
+    Continue { pane: u32 },
+    /// `%exit [reason]`
+    Exit { reason: Option<&'a [u8]> },
+    /// A notification that is not recognized or is malformed, as its name
+    /// (without the `%`) and arguments.
+    Other { name: &'a [u8], args: &'a [u8] },
+}
+
+/// A command reply being assembled.
+#[derive(Debug)]
+struct Block {
+    time: u64,
+    number: u64,
+    output: Vec<u8>,
+}
+
+/// A parser for tmux control mode output.
+#[derive(Default)]
+pub struct TmuxControlParser {
+    /// The current, incomplete line.
+    line: Vec<u8>,
+    /// The unescaped pane output.
+    output: Vec<u8>,
+    block: Option<Block>,
+    panes: HashMap<u32, VTPushParser>,
+}
+
+impl TmuxControlParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The parser for a pane's output, if the pane has produced output.
+    pub fn pane(&self, pane: u32) -> Option<&VTPushParser> {
+        self.panes.get(&pane)
+    }
+
+    /// Discards the parser for a pane (eg: after it has been closed).
+    pub fn remove_pane(&mut self, pane: u32) {
+        self.panes.remove(&pane);
+    }
+
+    /// Feed bytes into the parser. Events are emitted as each line is
+    /// completed.
+    pub fn feed_with(&mut self, input: &[u8], mut cb: impl FnMut(TmuxControlEvent<'_>)) {
+        for line in input.split_inclusive(|&b| b == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.last() == Some(&b'\n') {
+                let mut line = std::mem::take(&mut self.line);
+                self.line(&line, &mut cb);
+                line.clear();
+                self.line = line;
+            }
+        }
+    }
+
+    fn line(&mut self, line: &[u8], cb: &mut impl FnMut(TmuxControlEvent<'_>)) {
+        let mut line = line.strip_suffix(b"\n").unwrap_or(line);
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+        line = line.strip_prefix(CONTROL_MODE_START).unwrap_or(line);
+        line = line.strip_prefix(CONTROL_MODE_END).unwrap_or(line);
+
+        let (name, args) = split_word(line);
+        if let Some(block) = &mut self.block {
+            // Inside a block, only the matching %end or %error is special
+            let success = match name {
+                b"%end" => true,
+                b"%error" => false,
+                _ => {
+                    if !block.output.is_empty() {
+                        block.output.push(b'\n');
+                    }
+                    block.output.extend_from_slice(line);
+                    return;
+                }
+            };
+            // %end time number flags
+            let number = split_word(split_word(args).1).0;
+            if number != block.number.to_string().as_bytes() {
+                block.output.push(b'\n');
+                block.output.extend_from_slice(line);
+                return;
+            }
+            let block = self.block.take().unwrap();
+            cb(TmuxControlEvent::Reply {
+                time: block.time,
+                number: block.number,
+                success,
+                output: &block.output,
+            });
+            return;
+        }
+
+        match name {
+            b"%begin" => {
+                let (time, rest) = split_word(args);
+                let (number, _) = split_word(rest);
+                self.block = Some(Block {
+                    time: parse_number(time).unwrap_or_default(),
+                    number: parse_number(number).unwrap_or_default(),
+                    output: Vec::new(),
+                });
+            }
+            b"%output" => {
+                let (pane, data) = split_word(args);
+                if let Some(pane) = parse_id(pane, b'%') {
+                    self.output(pane, data, cb);
+                }
+            }
+            b"%extended-output" => {
+                // %extended-output %pane age ... : data
+                let (pane, rest) = split_word(args);
+                let data = rest
+                    .windows(3)
+                    .position(|w| w == b" : ")
+                    .map(|i| &rest[i + 3..])
+                    .or_else(|| rest.strip_prefix(b": "));
+                if let (Some(pane), Some(data)) = (parse_id(pane, b'%'), data) {
+                    self.output(pane, data, cb);
+                }
+            }
+            b"" => {}
+            _ => cb(TmuxControlEvent::Notification(TmuxNotification::parse(
+                name, args,
+            ))),
+        }
+    }
+
+    fn output(&mut self, pane: u32, data: &[u8], cb: &mut impl FnMut(TmuxControlEvent<'_>)) {
+        self.output.clear();
+        unescape(data, &mut self.output);
+        self.panes
+            .entry(pane)
+            .or_default()
+            .feed_with(&self.output, |event: VTEvent| {
+                cb(TmuxControlEvent::Output { pane, event })
+            });
+    }
+}
+
+impl<'a> TmuxNotification<'a> {
+    /// Parses a notification from its name (including the `%`) and
+    /// arguments.
+    fn parse(name: &'a [u8], args: &'a [u8]) -> Self {
+        Self::parse_known(name, args).unwrap_or(TmuxNotification::Other {
+            name: name.strip_prefix(b"%").unwrap_or(name),
+            args,
+        })
+    }
+
+    fn parse_known(name: &'a [u8], args: &'a [u8]) -> Option<Self> {
+        let (first, rest) = split_word(args);
+        let window = || parse_id(first, b'@');
+        let pane = || parse_id(first, b'%');
+        Some(match name {
+            b"%layout-change" => {
+                let (layout, rest) = split_word(rest);
+                let (visible_layout, flags) = split_word(rest);
+                TmuxNotification::LayoutChange {
+                    window: window()?,
+                    layout,
+                    visible_layout: non_empty(visible_layout),
+                    flags: non_empty(flags),
+                }
+            }
+            b"%window-add" => TmuxNotification::WindowAdd { window: window()? },
+            b"%window-close" => TmuxNotification::WindowClose { window: window()? },
+            b"%window-renamed" => TmuxNotification::WindowRenamed {
+                window: window()?,
+                name: rest,
+            },
+            b"%window-pane-changed" => TmuxNotification::WindowPaneChanged {
+                window: window()?,
+                pane: parse_id(rest, b'%')?,
+            },
+            b"%unlinked-window-add" => TmuxNotification::UnlinkedWindowAdd { window: window()? },
+            b"%unlinked-window-close" => {
+                TmuxNotification::UnlinkedWindowClose { window: window()? }
+            }
+            b"%session-changed" => TmuxNotification::SessionChanged {
+                session: parse_id(first, b'$')?,
+                name: rest,
+            },
+            b"%session-renamed" => TmuxNotification::SessionRenamed { name: args },
+            b"%session-window-changed" => TmuxNotification::SessionWindowChanged {
+                session: parse_id(first, b'$')?,
+                window: parse_id(rest, b'@')?,
+            },
+            b"%sessions-changed" => TmuxNotification::SessionsChanged,
+            b"%pane-mode-changed" => TmuxNotification::PaneModeChanged { pane: pane()? },
+            b"%pause" => TmuxNotification::Pause { pane: pane()? },
+            b"%continue" => TmuxNotification::Continue { pane: pane()? },
+            b"%exit" => TmuxNotification::Exit {
+                reason: non_empty(args),
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Splits off the first space-separated word.
+fn split_word(input: &[u8]) -> (&[u8], &[u8]) {
+    match input.iter().position(|&b| b == b' ') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => (input, &[]),
+    }
+}
+
+fn non_empty(input: &[u8]) -> Option<&[u8]> {
+    (!input.is_empty()).then_some(input)
+}
+
+fn parse_number<T: std::str::FromStr>(input: &[u8]) -> Option<T> {
+    std::str::from_utf8(input).ok()?.parse().ok()
+}
+
+/// Parses an ID such as `%1`, `@1` or `$1`.
+fn parse_id(input: &[u8], prefix: u8) -> Option<u32> {
+    parse_number(input.strip_prefix(&[prefix])?)
+}
+
+/// Unescapes `\ooo` octal escapes.
+fn unescape(input: &[u8], output: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < input.len() {
+        let octal = input
+            .get(i + 1..i + 4)
+            .filter(|digits| input[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match octal {
+            Some(digits) => {
+                let value = digits
+                    .iter()
+                    .fold(0u16, |value, d| value * 8 + (d - b'0') as u16);
+                output.push(value as u8);
+                i += 4;
+            }
+            None => {
+                output.push(input[i]);
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply() {
+        let mut parser = TmuxControlParser::new();
+        let mut replies = vec![];
+        let input = b"\x1bP1000p%begin 1700000000 12 0\n%not-a-notification\n%end 11\n\
+            %end 1700000000 12 0\n%begin 1700000000 13 0\r\nparse error\r\n%error 1700000000 13 0\r\n";
+        for chunk in input.chunks(5) {
+            parser.feed_with(chunk, |event| {
+                if let TmuxControlEvent::Reply {
+                    time,
+                    number,
+                    success,
+                    output,
+                } = event
+                {
+                    replies.push((time, number, success, output.to_vec()));
+                }
+            });
+        }
+        assert_eq!(
+            replies,
+            [
+                (
+                    1700000000,
+                    12,
+                    true,
+                    b"%not-a-notification\n%end 11".to_vec()
+                ),
+                (1700000000, 13, false, b"parse error".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let mut parser = TmuxControlParser::new();
+        let mut events = vec![];
+        for chunk in b"%output %1 \\033[31\n%output %2 \\134o/\\015\\012\n%output %1 mred\n\
+            %extended-output %2 15 : \\033]0;t\\007\n"
+            .chunks(3)
+        {
+            parser.feed_with(chunk, |event| {
+                if let TmuxControlEvent::Output { pane, event } = event {
+                    events.push((pane, format!("{event:?}")));
+                }
+            });
+        }
+        // Each pane is parsed separately
+        assert_eq!(
+            events,
+            [
+                (2, "Raw('\\o/<CR><LF>')".to_owned()),
+                (1, "Csi('31', '', 'm')".to_owned()),
+                (1, "Raw('red')".to_owned()),
+                (2, "OscStart(0)".to_owned()),
+                (2, "OscEnd('0;t')".to_owned()),
+            ]
+        );
+        assert!(parser.pane(1).unwrap().is_ground());
+        parser.remove_pane(1);
+        assert!(parser.pane(1).is_none());
+    }
+
+    #[test]
+    fn test_notifications() {
+        let mut parser = TmuxControlParser::new();
+        let mut notifications = vec![];
+        parser.feed_with(
+            b"%layout-change @1 b25d,80x24,0,0,0 b25d,80x24,0,0,0 *\n%window-add @2\n\
+              %window-renamed @2 my window\n%window-pane-changed @2 %5\n\
+              %session-changed $0 main\n%sessions-changed\n%pause %5\n%unknown 1 2\n\
+              %window-add 2\n%exit\n\x1b\\",
+            |event| {
+                if let TmuxControlEvent::Notification(notification) = event {
+                    notifications.push(format!("{notification:?}"));
+                }
+            },
+        );
+        let expected = [
+            TmuxNotification::LayoutChange {
+                window: 1,
+                layout: b"b25d,80x24,0,0,0",
+                visible_layout: Some(b"b25d,80x24,0,0,0"),
+                flags: Some(b"*"),
+            },
+            TmuxNotification::WindowAdd { window: 2 },
+            TmuxNotification::WindowRenamed {
+                window: 2,
+                name: b"my window",
+            },
+            TmuxNotification::WindowPaneChanged { window: 2, pane: 5 },
+            TmuxNotification::SessionChanged {
+                session: 0,
+                name: b"main",
+            },
+            TmuxNotification::SessionsChanged,
+            TmuxNotification::Pause { pane: 5 },
+            TmuxNotification::Other {
+                name: b"unknown",
+                args: b"1 2",
+            },
+            // Malformed IDs are not recognized
+            TmuxNotification::Other {
+                name: b"window-add",
+                args: b"2",
+            },
+            TmuxNotification::Exit { reason: None },
+        ]
+        .map(|n| format!("{n:?}"));
+        assert_eq!(notifications, expected);
+    }
+
+    #[test]
+    fn test_unescape() {
+        let mut output = vec![];
+        unescape(b"a\\033b\\\\c\\08\\1", &mut output);
+        assert_eq!(output, b"a\x1bb\\\\c\\08\\1");
+    }
+}
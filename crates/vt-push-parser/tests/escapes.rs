@@ -42,7 +42,7 @@ macro_rules! callback {
                         panic!("DcsData without DcsStart");
                     };
                     acc.push_str(&encode_string(s));
-                    if matches!(vt_input, VTEvent::DcsEnd(s)) {
+                    if matches!(vt_input, VTEvent::DcsEnd(_)) {
                         $counts.0 -= 1;
                     }
                 }
@@ -53,8 +53,8 @@ macro_rules! callback {
                     *acc = format!("{} (cancelled)", acc.split_once(", data=").unwrap().0);
                     $counts.0 -= 1;
                 }
-                VTEvent::OscStart => {
-                    result.push(VTAccumulator::Osc("OscStart, data=".to_string()));
+                VTEvent::OscStart { .. } => {
+                    result.push(VTAccumulator::Osc(format!("{vt_input:?}, data=")));
                     $counts.1 += 1;
                 }
                 VTEvent::OscData(s) | VTEvent::OscEnd { data: s, .. } => {
@@ -62,7 +62,7 @@ macro_rules! callback {
                         panic!("OscData without OscStart");
                     };
                     acc.push_str(&encode_string(s));
-                    if matches!(vt_input, VTEvent::OscEnd { data: s, .. }) {
+                    if matches!(vt_input, VTEvent::OscEnd { .. }) {
                         $counts.1 -= 1;
                     }
                 }